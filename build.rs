@@ -1,6 +1,10 @@
 extern crate pkg_config;
 
 fn main() {
+    // SDL2 is only linked by the sdl feature, e.g. not in the wasm build
+    if std::env::var_os("CARGO_FEATURE_SDL").is_none() {
+        return;
+    }
     pkg_config::Config::new()
         .atleast_version("2.0.20")
         .probe("sdl2")
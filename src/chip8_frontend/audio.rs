@@ -0,0 +1,154 @@
+use chip8_lib::config::Waveform;
+use rand::Rng;
+use sdl2::audio::AudioCallback;
+use std::f32::consts::TAU;
+
+// Pitch of the beep when the sound timer is active
+pub const BEEP_FREQUENCY: f32 = 440.0;
+// Range of pitches used in variable pitch mode, from timer value 1 to 255
+const MIN_FREQUENCY: f32 = 220.0;
+const MAX_FREQUENCY: f32 = 880.0;
+const VOLUME: f32 = 0.25;
+// Number of one-bit samples in an XO-CHIP audio pattern
+pub const PATTERN_BITS: usize = 128;
+
+/// Beep frequency for the given sound timer value.
+/// With variable pitch, the frequency rises exponentially with the timer value,
+/// so that equal steps in the timer sound like equal steps in pitch.
+pub fn timer_to_frequency(timer: u8, variable_pitch: bool) -> f32 {
+    if !variable_pitch || timer == 0 {
+        return BEEP_FREQUENCY;
+    }
+    let t = (timer - 1) as f32 / (u8::MAX - 1) as f32;
+    MIN_FREQUENCY * (MAX_FREQUENCY / MIN_FREQUENCY).powf(t)
+}
+
+/// Samples per second at which an XO-CHIP audio pattern plays for the given pitch register.
+/// The default pitch of 64 plays at 4000 Hz, and every 48 steps doubles the rate.
+pub fn playback_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+/// Level of an XO-CHIP audio pattern at the given fraction of the pattern,
+/// 1.0 for a set bit and -1.0 for a clear one, most significant bit first.
+pub fn pattern_sample(pattern: &[u8; PATTERN_BITS / 8], phase: f32) -> f32 {
+    let bit = ((phase * PATTERN_BITS as f32) as usize).min(PATTERN_BITS - 1);
+    if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/// Level of a waveform from -1.0 to 1.0 at the given fraction of a period.
+/// Noise has no shape of its own, so the current noise level is passed through.
+pub fn sample(waveform: Waveform, phase: f32, noise: f32) -> f32 {
+    match waveform {
+        Waveform::Square => {
+            if phase <= 0.5 {
+                1.0
+            } else {
+                -1.0
+            }
+        }
+        Waveform::Sine => (TAU * phase).sin(),
+        Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        Waveform::Noise => noise,
+    }
+}
+
+pub struct Beeper {
+    pub waveform: Waveform,
+    // XO-CHIP audio pattern, played instead of the waveform when set
+    pub pattern: Option<[u8; PATTERN_BITS / 8]>,
+    // Fraction of a wave period, or of the whole pattern, advanced per sample
+    pub phase_inc: f32,
+    phase: f32,
+    // Level of the noise waveform, picked at random at the start of each period
+    noise: f32,
+}
+
+impl Beeper {
+    pub fn new(waveform: Waveform, frequency: f32, sample_rate: i32) -> Self {
+        Self {
+            waveform,
+            pattern: None,
+            phase_inc: frequency / sample_rate as f32,
+            phase: 0.0,
+            noise: 0.0,
+        }
+    }
+}
+
+impl AudioCallback for Beeper {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let mut rng = rand::thread_rng();
+        for x in out.iter_mut() {
+            *x = VOLUME
+                * match &self.pattern {
+                    Some(pattern) => pattern_sample(pattern, self.phase),
+                    None => sample(self.waveform, self.phase, self.noise),
+                };
+            self.phase += self.phase_inc;
+            if self.phase >= 1.0 {
+                self.phase %= 1.0;
+                self.noise = rng.gen_range(-1.0..=1.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each waveform has its expected level at the start, quarter and middle of a period
+    #[test]
+    fn sample_shapes() {
+        // (waveform, levels at phase 0.0, 0.25, 0.5 and 0.75)
+        let cases = [
+            (Waveform::Square, [1.0, 1.0, 1.0, -1.0]),
+            (Waveform::Sine, [0.0, 1.0, 0.0, -1.0]),
+            (Waveform::Triangle, [-1.0, 0.0, 1.0, 0.0]),
+            (Waveform::Noise, [0.3, 0.3, 0.3, 0.3]),
+        ];
+        for (waveform, levels) in cases {
+            for (phase, level) in [0.0, 0.25, 0.5, 0.75].into_iter().zip(levels) {
+                let actual = sample(waveform, phase, 0.3);
+                assert!(
+                    (actual - level).abs() < 1e-6,
+                    "{waveform:?} at {phase}: {actual}"
+                );
+            }
+        }
+    }
+
+    // Bits of an audio pattern are played from the most significant bit of the first byte
+    #[test]
+    fn pattern_samples() {
+        let mut pattern = [0; PATTERN_BITS / 8];
+        pattern[0] = 0x80;
+        pattern[15] = 0x01;
+        assert_eq!(pattern_sample(&pattern, 0.0), 1.0);
+        assert_eq!(pattern_sample(&pattern, 1.0 / PATTERN_BITS as f32), -1.0);
+        assert_eq!(pattern_sample(&pattern, 0.5), -1.0);
+        assert_eq!(pattern_sample(&pattern, 0.999), 1.0);
+        assert_eq!(playback_rate(64), 4000.0);
+        assert!((playback_rate(112) - 8000.0).abs() < 0.01);
+        assert!((playback_rate(16) - 2000.0).abs() < 0.01);
+    }
+
+    // Map sound timer values to frequencies with and without variable pitch
+    #[test]
+    fn timer_to_frequency_mapping() {
+        assert_eq!(timer_to_frequency(1, false), BEEP_FREQUENCY);
+        assert_eq!(timer_to_frequency(255, false), BEEP_FREQUENCY);
+        assert_eq!(timer_to_frequency(1, true), MIN_FREQUENCY);
+        assert_eq!(timer_to_frequency(255, true), MAX_FREQUENCY);
+        // Halfway through the timer range is one octave above the minimum
+        assert!((timer_to_frequency(128, true) - 2.0 * MIN_FREQUENCY).abs() < 0.01);
+        assert!(timer_to_frequency(10, true) < timer_to_frequency(11, true));
+    }
+}
@@ -1,34 +1,63 @@
+mod audio;
+mod chord;
+mod focus;
+mod gamepad;
+mod hud;
+mod keys;
 mod screen;
 
-use crate::screen::GRID_CELL_SIZE;
-use chip8_lib::chip8::Chip8;
+use crate::screen::{Viewport, GRID_CELL_SIZE, GRID_SIZE, HIRES_GRID_SIZE};
+use crate::audio::Beeper;
+use crate::chord::ChordTracker;
+use crate::gamepad::AnalogInput;
+use crate::keys::KeyFilter;
+use chip8_lib::chip8::{Chip8, ControlMessage, DebugInfo, HaltReason, SoundState};
 use chip8_lib::config::Cfg;
-use chip8_lib::display::PIXEL_COUNT;
+use chip8_lib::display::{self, FrameDiff, FRAME_SIZE};
 use chip8_lib::input::{InputController, KeyStatus};
+#[cfg(feature = "gif")]
+use chip8_lib::recorder::GifRecorder;
 use log::{debug, info, warn};
+use sdl2::audio::AudioSpecDesired;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::render::TextureAccess;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
 const CFG_FILE_PATH: &str = "cfg/config.ini";
-const REFRESH_RATE: Duration = Duration::from_nanos(1_000_000_000 / 60);
 
 fn main() -> Result<(), String> {
     env_logger::init();
     // Backend will run in its own separate thread, reacting to keypresses sent by message from
     // the main thread (SDL2 context). Backend will send frame buffer to frontend in similar way.
-    let mut chip8 = Chip8::default();
-    chip8.load_config(CFG_FILE_PATH);
     let (input_tx, input_rx): (Sender<(u8, KeyStatus)>, Receiver<(u8, KeyStatus)>) = mpsc::channel();
-    let (display_tx, display_rx): (Sender<[u8; PIXEL_COUNT]>, Receiver<[u8; PIXEL_COUNT]>) =
+    let (display_tx, display_rx): (Sender<FrameDiff>, Receiver<FrameDiff>) = mpsc::channel();
+    let (control_tx, control_rx): (Sender<ControlMessage>, Receiver<ControlMessage>) =
         mpsc::channel();
-    let (quit_tx, quit_rx): (Sender<bool>, Receiver<bool>) = mpsc::channel();
+    let (debug_tx, debug_rx): (Sender<DebugInfo>, Receiver<DebugInfo>) = mpsc::channel();
+    let (sound_tx, sound_rx): (Sender<SoundState>, Receiver<SoundState>) = mpsc::channel();
+    let (halt_tx, halt_rx): (Sender<HaltReason>, Receiver<HaltReason>) = mpsc::channel();
+    let mut builder = Chip8::builder()
+        .config(CFG_FILE_PATH)
+        .channels(input_rx, control_rx, display_tx)
+        .debug(debug_tx)
+        .sound(sound_tx)
+        .halt(halt_tx);
+    // The ROM to run is given as the first argument, or a bundle directory holding
+    // the ROM along with its config
+    let rom_path = std::env::args().nth(1);
+    if let Some(path) = &rom_path {
+        builder = builder.rom(path);
+    }
+    let mut chip8 = builder.build().map_err(|e| {
+        let path = rom_path.unwrap_or_default();
+        format!("Unable to load ROM {path}: {e}")
+    })?;
+    let title = screen::window_title(chip8.current_rom_name());
 
     thread::spawn(move || {
-        chip8.connect(input_rx, quit_rx, display_tx);
         info!("Chip-8 connected to main thread. Starting execution loop.");
         chip8.main_loop();
     });
@@ -37,16 +66,37 @@ fn main() -> Result<(), String> {
 
     info!("Initializing SDL2 context...");
     let sdl_context = sdl2::init()?;
-    let conf = Cfg::default();
+    let mut conf = Cfg::default();
+    conf.load_config(CFG_FILE_PATH);
+    let mut paused = conf.start_paused();
+    let mut bg_color = screen::color_from_rgb(conf.palette()[0]);
+    // Pixels which are turned off fade out over several frames when ghosting is enabled
+    let mut decay = screen::ghost_decay(&conf);
+    // Debug overlays, toggled with F1 (HUD) and F2 (grid)
+    let mut show_hud = false;
+    let mut show_grid = false;
+    // Show config lines for unmapped keys in the window title as they are pressed, toggled
+    // with F3. They are logged too, but only at info level, which env_logger hides by default.
+    let mut learn_keys = false;
+    // GIF recording, started and stopped with F9
+    #[cfg(feature = "gif")]
+    let mut recorder: Option<GifRecorder> = None;
+    let mut debug_info = DebugInfo::default();
+    // Local copy of the frame buffer, kept up to date with diffs from the backend
+    let mut frame_buffer = [0; FRAME_SIZE];
+    // Resolution the frame buffer is laid out in, as of the latest diff
+    let mut resolution = GRID_SIZE;
+    let mut intensity = [screen::UNLIT; HIRES_GRID_SIZE.0 * HIRES_GRID_SIZE.1];
     let video_subsystem = sdl_context.video()?;
     let window = video_subsystem
-        .window("CHIP-8", screen::SCREEN_SIZE.0, screen::SCREEN_SIZE.1)
+        .window(&title, screen::SCREEN_SIZE.0, screen::SCREEN_SIZE.1)
         .position_centered()
+        .resizable()
         .build()
         .map_err(|e| e.to_string())?;
 
     let mut canvas = window.into_canvas().build().unwrap();
-    canvas.set_draw_color(screen::BG_COLOR);
+    canvas.set_draw_color(bg_color);
     canvas.clear();
     canvas.present();
     let texture_creator = canvas.texture_creator();
@@ -62,6 +112,22 @@ fn main() -> Result<(), String> {
         GRID_CELL_SIZE.0,
         GRID_CELL_SIZE.1,
     );
+    let audio_subsystem = sdl_context.audio()?;
+    let audio_spec = AudioSpecDesired {
+        freq: Some(44_100),
+        channels: Some(1),
+        samples: None,
+    };
+    let mut beeper = audio_subsystem.open_playback(None, &audio_spec, |spec| {
+        Beeper::new(conf.waveform(), audio::BEEP_FREQUENCY, spec.freq)
+    })?;
+    let sample_rate = beeper.spec().freq;
+    let game_controller_subsystem = sdl_context.game_controller()?;
+    // Controllers are closed when dropped, so keep them open here
+    let mut controllers = vec![];
+    let mut analog_input = AnalogInput::new(conf.analog_bindings());
+    let mut chords = ChordTracker::new(conf.chords());
+    let mut key_filter = KeyFilter::default();
     let mut event_pump = sdl_context.event_pump()?;
 
     'running: loop {
@@ -74,14 +140,113 @@ fn main() -> Result<(), String> {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => {
-                    if let Err(e) = quit_tx.send(true) {
+                    if let Err(e) = control_tx.send(ControlMessage::Quit) {
                         warn!("Failed to send quit message to backend: {e}");
                     };
                     break 'running;
                 }
+                // Pause while the window does not have focus, if enabled in config
+                Event::Window { win_event, .. } if conf.pause_on_focus_loss() => {
+                    if let Some(msg) = focus::focus_control(&win_event, paused) {
+                        if let Err(e) = control_tx.send(msg) {
+                            warn!("Failed to send focus change to backend: {e}");
+                        }
+                    }
+                }
+                // Toggle pause
+                Event::KeyDown {
+                    keycode: Some(Keycode::Space),
+                    repeat: false,
+                    ..
+                } => {
+                    paused = !paused;
+                    let msg = if paused {
+                        ControlMessage::Pause
+                    } else {
+                        ControlMessage::Resume
+                    };
+                    if let Err(e) = control_tx.send(msg) {
+                        warn!("Failed to send pause message to backend: {e}");
+                    };
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    repeat: false,
+                    ..
+                } => show_hud = !show_hud,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    repeat: false,
+                    ..
+                } => show_grid = !show_grid,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    repeat: false,
+                    ..
+                } => {
+                    learn_keys = !learn_keys;
+                    info!("Key learn mode {}.", if learn_keys { "on" } else { "off" });
+                    let learn_title = match learn_keys {
+                        true => format!("{title} - Key learn mode: press an unmapped key"),
+                        false => title.clone(),
+                    };
+                    if let Err(e) = canvas.window_mut().set_title(&learn_title) {
+                        warn!("Failed to set window title: {e}");
+                    }
+                }
+                // Start recording, or stop and save the recording
+                #[cfg(feature = "gif")]
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    repeat: false,
+                    ..
+                } => match recorder.take() {
+                    Some(rec) => {
+                        let path = screen::recording_file_name();
+                        match rec.save(&path) {
+                            Ok(()) => info!("Saved {} frames to {path}.", rec.frame_count()),
+                            Err(e) => warn!("Failed to save recording to {path}: {e}"),
+                        }
+                    }
+                    None => {
+                        info!("Recording started.");
+                        let palette = conf.palette();
+                        // Record at hires size, so lores frames are scaled up to it
+                        let (width, height) = HIRES_GRID_SIZE;
+                        recorder = Some(GifRecorder::new(width, height, palette[1], palette[0]));
+                    }
+                },
+                // Reload the config, here and in the backend
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => {
+                    if conf.reload(CFG_FILE_PATH) {
+                        analog_input = AnalogInput::new(conf.analog_bindings());
+                        chords = ChordTracker::new(conf.chords());
+                        bg_color = screen::color_from_rgb(conf.palette()[0]);
+                        decay = screen::ghost_decay(&conf);
+                        beeper.lock().waveform = conf.waveform();
+                    }
+                    if let Err(e) = control_tx.send(ControlMessage::ReloadConfig) {
+                        warn!("Failed to send reload message to backend: {e}");
+                    };
+                }
                 // If a key is pressed, see if it corresponds to a key in the layout defind in config,
                 // then update internal keyboard state
                 Event::KeyDown { keycode: k, .. } => {
+                    // Ignore key repeats while the key is held
+                    if let Some(k) = k {
+                        if !key_filter.transition(k, KeyStatus::Pressed) {
+                            continue;
+                        }
+                        for event in chords.key_event(k, KeyStatus::Pressed) {
+                            if let Err(e) = input_tx.send(event) {
+                                warn!("Failed to send keyboard state to backend: {e}");
+                            }
+                        }
+                    }
                     let send = &conf.get_u8_from_keycode(k.unwrap());
                     match send {
                         Some(val) => {
@@ -93,10 +258,46 @@ fn main() -> Result<(), String> {
                         None => {
                             let str = k.unwrap().to_string();
                             debug!("Unmapped keypress received: {str}");
+                            if learn_keys {
+                                let suggestion = keys::layout_suggestion(k.unwrap(), &conf);
+                                info!("{suggestion}");
+                                if let Err(e) = canvas
+                                    .window_mut()
+                                    .set_title(&format!("{title} - Key learn mode: {suggestion}"))
+                                {
+                                    warn!("Failed to set window title: {e}");
+                                }
+                            }
+                        }
+                    }
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match game_controller_subsystem.open(which) {
+                        Ok(controller) => {
+                            info!("Opened game controller {}", controller.name());
+                            controllers.push(controller);
+                        }
+                        Err(e) => warn!("Failed to open game controller {which}: {e}"),
+                    }
+                }
+                Event::ControllerAxisMotion { axis, value, .. } => {
+                    for event in analog_input.axis_motion(axis, value) {
+                        if let Err(e) = input_tx.send(event) {
+                            warn!("Failed to send gamepad state to backend: {e}");
                         }
                     }
                 }
                 Event::KeyUp { keycode: k, .. } => {
+                    if let Some(k) = k {
+                        if !key_filter.transition(k, KeyStatus::Unpressed) {
+                            continue;
+                        }
+                        for event in chords.key_event(k, KeyStatus::Unpressed) {
+                            if let Err(e) = input_tx.send(event) {
+                                warn!("Failed to send keyboard state to backend: {e}");
+                            }
+                        }
+                    }
                     let send = &conf.get_u8_from_keycode(k.unwrap());
                     match send {
                         Some(val) => {
@@ -112,13 +313,77 @@ fn main() -> Result<(), String> {
             }
         }
 
-        // TODO: Draw the screen from frame buffer
+        while let Ok(diff) = display_rx.try_recv() {
+            // Intensities are laid out by resolution, so fading pixels cannot carry over
+            if (diff.width, diff.height) != resolution {
+                resolution = (diff.width, diff.height);
+                intensity.fill(screen::UNLIT);
+            }
+            display::apply_frame_diff(&mut frame_buffer, &diff);
+        }
+        screen::update_intensity(
+            &mut intensity,
+            &frame_buffer,
+            resolution,
+            &conf.palette(),
+            decay,
+        );
+        #[cfg(feature = "gif")]
+        if let Some(rec) = &mut recorder {
+            rec.capture(&frame_buffer, resolution.0, resolution.1, Instant::now());
+        }
+        // Beep, or play the XO-CHIP audio pattern, while the sound timer is active
+        while let Ok(sound) = sound_rx.try_recv() {
+            if sound.timer > 0 {
+                let frequency = match sound.pattern {
+                    Some(_) => audio::playback_rate(sound.pitch) / audio::PATTERN_BITS as f32,
+                    None => audio::timer_to_frequency(sound.timer, conf.variable_pitch()),
+                };
+                {
+                    let mut beeper = beeper.lock();
+                    beeper.pattern = sound.pattern;
+                    beeper.phase_inc = frequency / sample_rate as f32;
+                }
+                beeper.resume();
+            } else {
+                beeper.pause();
+            }
+        }
+        // Show why execution halted, as the backend pauses itself
+        while let Ok(reason) = halt_rx.try_recv() {
+            warn!("Emulation halted: {reason}");
+            paused = true;
+            if let Err(e) = canvas
+                .window_mut()
+                .set_title(&format!("{title} - Emulation halted: {reason}"))
+            {
+                warn!("Failed to set window title: {e}");
+            }
+        }
+        // Keep only the latest CPU state
+        while let Ok(info) = debug_rx.try_recv() {
+            debug_info = info;
+        }
+
+        canvas.set_draw_color(bg_color);
+        canvas.clear();
+        // Margins around the screen are left in the background color
+        let viewport = Viewport::fit(canvas.output_size()?, resolution, conf.integer_scaling());
+        screen::draw_screen(&mut canvas, &viewport, &intensity, resolution, bg_color)?;
+        if show_grid {
+            hud::draw_grid(&mut canvas, &viewport, resolution)?;
+        }
+        if show_hud {
+            hud::draw_hud(&mut canvas, &debug_info)?;
+        }
+        canvas.present();
 
-        // Enforce 60hz screen refresh rate
+        // Enforce the configured screen refresh rate; the backend keeps timers at 60hz
+        let frame_duration = screen::frame_duration(conf.render_hz());
         let end = Instant::now();
         let delta = end - start;
-        if delta < REFRESH_RATE {
-            std::thread::sleep(REFRESH_RATE - delta);
+        if delta < frame_duration {
+            std::thread::sleep(frame_duration - delta);
         }
     }
     Ok(())
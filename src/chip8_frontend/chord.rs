@@ -0,0 +1,102 @@
+use chip8_lib::config::Chord;
+use chip8_lib::input::KeyStatus;
+use sdl2::keyboard::Keycode;
+use std::collections::HashSet;
+
+/// Tracks held keyboard keys and translates chords into CHIP-8 key presses and releases
+pub struct ChordTracker {
+    chords: Vec<Chord>,
+    held: HashSet<Keycode>,
+    // Whether both keys of each chord are currently held
+    active: Vec<bool>,
+}
+
+impl ChordTracker {
+    pub fn new(chords: &[Chord]) -> Self {
+        Self {
+            chords: chords.to_vec(),
+            held: HashSet::new(),
+            active: vec![false; chords.len()],
+        }
+    }
+
+    /// Key events caused by a keyboard key being pressed or released
+    pub fn key_event(&mut self, keycode: Keycode, status: KeyStatus) -> Vec<(u8, KeyStatus)> {
+        match status {
+            KeyStatus::Pressed => self.held.insert(keycode),
+            KeyStatus::Unpressed => self.held.remove(&keycode),
+        };
+        let mut events = vec![];
+        for (chord, active) in self.chords.iter().zip(self.active.iter_mut()) {
+            let now_active = chord.keys.iter().all(|key| self.held.contains(key));
+            if now_active != *active {
+                *active = now_active;
+                let status = if now_active {
+                    KeyStatus::Pressed
+                } else {
+                    KeyStatus::Unpressed
+                };
+                events.push((chord.chip8_key, status));
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feed key events to a tracker, returning the CHIP-8 key events as (key, pressed)
+    fn run(tracker: &mut ChordTracker, events: &[(Keycode, bool)]) -> Vec<(u8, bool)> {
+        let mut out = vec![];
+        for &(keycode, pressed) in events {
+            let status = if pressed {
+                KeyStatus::Pressed
+            } else {
+                KeyStatus::Unpressed
+            };
+            for (key, status) in tracker.key_event(keycode, status) {
+                out.push((key, status == KeyStatus::Pressed));
+            }
+        }
+        out
+    }
+
+    // A chord is pressed once both keys are down, in either order, and released with either key
+    #[test]
+    fn chord_detection() {
+        let chords = [
+            Chord {
+                keys: [Keycode::Q, Keycode::W],
+                chip8_key: 0x5,
+            },
+            Chord {
+                keys: [Keycode::W, Keycode::E],
+                chip8_key: 0x6,
+            },
+        ];
+        let mut tracker = ChordTracker::new(&chords);
+        assert_eq!(run(&mut tracker, &[(Keycode::Q, true)]), []);
+        // Key repeat does not press the chord again
+        assert_eq!(
+            run(&mut tracker, &[(Keycode::W, true), (Keycode::W, true)]),
+            [(0x5, true)]
+        );
+        assert_eq!(
+            run(&mut tracker, &[(Keycode::Q, false), (Keycode::Q, true)]),
+            [(0x5, false), (0x5, true)]
+        );
+        // Keys can take part in several chords
+        assert_eq!(run(&mut tracker, &[(Keycode::E, true)]), [(0x6, true)]);
+        assert_eq!(
+            run(&mut tracker, &[(Keycode::W, false)]),
+            [(0x5, false), (0x6, false)]
+        );
+        // Unrelated keys are ignored
+        assert_eq!(
+            run(&mut tracker, &[(Keycode::A, true), (Keycode::A, false)]),
+            []
+        );
+    }
+}
@@ -0,0 +1,36 @@
+use chip8_lib::chip8::ControlMessage;
+use sdl2::event::WindowEvent;
+
+/// Control message to send when the window gains or loses focus, with pause_on_focus_loss set.
+/// Regaining focus only resumes execution if it was not paused otherwise, e.g. by the user.
+pub fn focus_control(event: &WindowEvent, paused: bool) -> Option<ControlMessage> {
+    match event {
+        WindowEvent::FocusLost => Some(ControlMessage::Pause),
+        WindowEvent::FocusGained if !paused => Some(ControlMessage::Resume),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Focus changes pause and resume, unless execution was paused already
+    #[test]
+    fn focus_events() {
+        assert_eq!(
+            focus_control(&WindowEvent::FocusLost, false),
+            Some(ControlMessage::Pause)
+        );
+        assert_eq!(
+            focus_control(&WindowEvent::FocusLost, true),
+            Some(ControlMessage::Pause)
+        );
+        assert_eq!(
+            focus_control(&WindowEvent::FocusGained, false),
+            Some(ControlMessage::Resume)
+        );
+        assert_eq!(focus_control(&WindowEvent::FocusGained, true), None);
+        assert_eq!(focus_control(&WindowEvent::Exposed, false), None);
+    }
+}
@@ -0,0 +1,112 @@
+use chip8_lib::config::AnalogBinding;
+use chip8_lib::input::KeyStatus;
+use sdl2::controller::Axis;
+
+// A pressed axis is only released once it falls below this fraction of its threshold,
+// so that an axis resting near the threshold does not flicker between pressed and released
+const RELEASE_NUMERATOR: i32 = 3;
+const RELEASE_DENOMINATOR: i32 = 4;
+
+/// Whether an axis bound to a key reads as pressed after moving to value, given whether it was
+/// pressed before. Keys are pressed at the threshold and released below 3/4 of it.
+pub fn axis_pressed(binding: &AnalogBinding, was_pressed: bool, value: i16) -> bool {
+    // Distance from the center in the direction of the binding
+    let distance = if binding.negative {
+        -(value as i32)
+    } else {
+        value as i32
+    };
+    let threshold = binding.threshold as i32;
+    if was_pressed {
+        distance * RELEASE_DENOMINATOR >= threshold * RELEASE_NUMERATOR
+    } else {
+        distance >= threshold
+    }
+}
+
+/// Translates gamepad axis motion into CHIP-8 key presses and releases
+pub struct AnalogInput {
+    bindings: Vec<AnalogBinding>,
+    // Whether each binding currently holds its key down
+    pressed: Vec<bool>,
+}
+
+impl AnalogInput {
+    pub fn new(bindings: &[AnalogBinding]) -> Self {
+        Self {
+            bindings: bindings.to_vec(),
+            pressed: vec![false; bindings.len()],
+        }
+    }
+
+    /// Key events caused by an axis moving to value
+    pub fn axis_motion(&mut self, axis: Axis, value: i16) -> Vec<(u8, KeyStatus)> {
+        let mut events = vec![];
+        for (binding, pressed) in self.bindings.iter().zip(self.pressed.iter_mut()) {
+            if binding.axis != axis {
+                continue;
+            }
+            let now_pressed = axis_pressed(binding, *pressed, value);
+            if now_pressed != *pressed {
+                *pressed = now_pressed;
+                let status = if now_pressed {
+                    KeyStatus::Pressed
+                } else {
+                    KeyStatus::Unpressed
+                };
+                events.push((binding.chip8_key, status));
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEFT: AnalogBinding = AnalogBinding {
+        axis: Axis::LeftX,
+        negative: true,
+        chip8_key: 0x4,
+        threshold: 16000,
+    };
+    const RIGHT: AnalogBinding = AnalogBinding {
+        negative: false,
+        chip8_key: 0x6,
+        ..LEFT
+    };
+
+    // Keys are pressed at the threshold and only released well below it
+    #[test]
+    fn axis_pressed_hysteresis() {
+        assert!(!axis_pressed(&RIGHT, false, 15999));
+        assert!(axis_pressed(&RIGHT, false, 16000));
+        assert!(axis_pressed(&RIGHT, true, 15000));
+        assert!(axis_pressed(&RIGHT, true, 12000));
+        assert!(!axis_pressed(&RIGHT, true, 11999));
+        // Direction matters
+        assert!(!axis_pressed(&RIGHT, false, -20000));
+        assert!(axis_pressed(&LEFT, false, -20000));
+        assert!(!axis_pressed(&LEFT, true, i16::MAX));
+    }
+
+    // Jitter around the threshold produces a single press, and moving across the center
+    // releases one direction before pressing the other
+    #[test]
+    fn axis_motion_events() {
+        let mut input = AnalogInput::new(&[LEFT, RIGHT]);
+        let mut events = vec![];
+        for value in [
+            0, 15000, 16500, 15500, 16500, 13000, 11000, 0, -17000, -32768,
+        ] {
+            events.extend(input.axis_motion(Axis::LeftX, value));
+        }
+        assert!(input.axis_motion(Axis::LeftY, 32767).is_empty());
+        let events: Vec<(u8, bool)> = events
+            .into_iter()
+            .map(|(key, status)| (key, status == KeyStatus::Pressed))
+            .collect();
+        assert_eq!(events, [(0x6, true), (0x6, false), (0x4, true)]);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::screen::{Viewport, GRID_COLOR, HUD_COLOR, HUD_DIM_COLOR};
+use chip8_lib::chip8::DebugInfo;
+use chip8_lib::FONT;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::WindowCanvas;
+
+// Size of one pixel of a HUD glyph
+const GLYPH_PIXEL: u32 = 3;
+// Horizontal distance between glyphs, in glyph pixels
+const GLYPH_ADVANCE: i32 = 5;
+// Vertical distance between lines of glyphs, in glyph pixels
+const LINE_ADVANCE: i32 = 7;
+// Distance of HUD from the window edges
+const HUD_MARGIN: i32 = 8;
+
+/// Draw faint lines along the boundaries of the simulated pixels at the given resolution
+pub fn draw_grid(
+    canvas: &mut WindowCanvas,
+    viewport: &Viewport,
+    resolution: (usize, usize),
+) -> Result<(), String> {
+    canvas.set_draw_color(GRID_COLOR);
+    let (width, height) = (resolution.0 as i32, resolution.1 as i32);
+    let (cell_w, cell_h) = (viewport.cell_size.0 as i32, viewport.cell_size.1 as i32);
+    let (left, top) = (viewport.x, viewport.y);
+    let (right, bottom) = (left + width * cell_w, top + height * cell_h);
+    for col in 1..width {
+        let x = left + col * cell_w;
+        canvas.draw_line(Point::new(x, top), Point::new(x, bottom))?;
+    }
+    for row in 1..height {
+        let y = top + row * cell_h;
+        canvas.draw_line(Point::new(left, y), Point::new(right, y))?;
+    }
+    Ok(())
+}
+
+/// Draw program counter, index register, delay timer and sound timer on the first line,
+/// followed by V0-VF in two lines of register number and value.
+/// The first line ends with flags of the last sprite draw: 1 for a collision,
+/// 2 for wrapping around the right edge and 4 for wrapping around the bottom edge.
+pub fn draw_hud(canvas: &mut WindowCanvas, info: &DebugInfo) -> Result<(), String> {
+    let step = GLYPH_ADVANCE * GLYPH_PIXEL as i32;
+    let line = LINE_ADVANCE * GLYPH_PIXEL as i32;
+    draw_hex(canvas, info.pc as u32, 4, HUD_MARGIN, HUD_MARGIN, HUD_COLOR)?;
+    draw_hex(
+        canvas,
+        info.i as u32,
+        4,
+        HUD_MARGIN + 5 * step,
+        HUD_MARGIN,
+        HUD_COLOR,
+    )?;
+    draw_hex(
+        canvas,
+        info.dt as u32,
+        2,
+        HUD_MARGIN + 10 * step,
+        HUD_MARGIN,
+        HUD_COLOR,
+    )?;
+    draw_hex(
+        canvas,
+        info.st as u32,
+        2,
+        HUD_MARGIN + 13 * step,
+        HUD_MARGIN,
+        HUD_COLOR,
+    )?;
+    let draw = info.last_draw;
+    let flags = draw.collision as u32 | (draw.wrapped_x as u32) << 1 | (draw.wrapped_y as u32) << 2;
+    let flags_color = if flags == 0 { HUD_DIM_COLOR } else { HUD_COLOR };
+    draw_hex(
+        canvas,
+        flags,
+        1,
+        HUD_MARGIN + 16 * step,
+        HUD_MARGIN,
+        flags_color,
+    )?;
+    for (x, val) in info.registers.iter().enumerate() {
+        let col = (x % 8) as i32 * 4 * step + HUD_MARGIN;
+        let row = (1 + x / 8) as i32 * line + HUD_MARGIN;
+        draw_hex(canvas, x as u32, 1, col, row, HUD_DIM_COLOR)?;
+        draw_hex(canvas, *val as u32, 2, col + step, row, HUD_COLOR)?;
+    }
+    Ok(())
+}
+
+// Draw the lowest n hex digits of val with the top left corner at (x, y)
+fn draw_hex(
+    canvas: &mut WindowCanvas,
+    val: u32,
+    digits: u32,
+    x: i32,
+    y: i32,
+    color: Color,
+) -> Result<(), String> {
+    canvas.set_draw_color(color);
+    for n in 0..digits {
+        let digit = (val >> (4 * (digits - 1 - n))) & 0xF;
+        let glyph_x = x + (n as i32) * GLYPH_ADVANCE * GLYPH_PIXEL as i32;
+        canvas.fill_rects(&glyph_rects(digit as u8, glyph_x, y))?;
+    }
+    Ok(())
+}
+
+// Rects making up the CHIP-8 font glyph of a hex digit, with the top left corner at (x, y)
+fn glyph_rects(digit: u8, x: i32, y: i32) -> Vec<Rect> {
+    let mut rects = vec![];
+    let glyph = &FONT[digit as usize * 5..digit as usize * 5 + 5];
+    for (row, byte) in glyph.iter().enumerate() {
+        // Font glyphs are 4 pixels wide, stored in the high nibble
+        for col in 0..4 {
+            if byte & (0x80 >> col) != 0 {
+                rects.push(Rect::new(
+                    x + col * GLYPH_PIXEL as i32,
+                    y + row as i32 * GLYPH_PIXEL as i32,
+                    GLYPH_PIXEL,
+                    GLYPH_PIXEL,
+                ));
+            }
+        }
+    }
+    rects
+}
@@ -0,0 +1,61 @@
+use chip8_lib::config::Cfg;
+use chip8_lib::input::{KeyStatus, KEY_COUNT};
+use sdl2::keyboard::Keycode;
+use std::collections::HashSet;
+
+/// Tracks the last known state of each keyboard key, so that key repeats while a key is
+/// held don't flood the backend with duplicate messages
+#[derive(Default)]
+pub struct KeyFilter {
+    held: HashSet<Keycode>,
+}
+
+impl KeyFilter {
+    /// Record a key event, returning whether it changed the state of the key.
+    /// Repeated presses of a held key and releases of an unheld key return false.
+    pub fn transition(&mut self, keycode: Keycode, status: KeyStatus) -> bool {
+        match status {
+            KeyStatus::Pressed => self.held.insert(keycode),
+            KeyStatus::Unpressed => self.held.remove(&keycode),
+        }
+    }
+}
+
+/// Config line for the [keyboard_layout] heading which binds an unmapped keyboard key,
+/// shown in learn mode. Suggests the first CHIP-8 key which has no keyboard key yet,
+/// or 0 if every key is bound.
+pub fn layout_suggestion(keycode: Keycode, conf: &Cfg) -> String {
+    let chip8_key = (0..KEY_COUNT)
+        .find(|&key| conf.keycodes_for(key).is_empty())
+        .unwrap_or(0);
+    format!("{} = {chip8_key}", keycode.name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Repeated key downs are suppressed until the key is released
+    #[test]
+    fn suppress_repeated_downs() {
+        let mut filter = KeyFilter::default();
+        assert!(filter.transition(Keycode::A, KeyStatus::Pressed));
+        assert!(!filter.transition(Keycode::A, KeyStatus::Pressed));
+        assert!(!filter.transition(Keycode::A, KeyStatus::Pressed));
+        // Other keys are tracked separately
+        assert!(filter.transition(Keycode::B, KeyStatus::Pressed));
+        assert!(filter.transition(Keycode::A, KeyStatus::Unpressed));
+        assert!(!filter.transition(Keycode::A, KeyStatus::Unpressed));
+        assert!(filter.transition(Keycode::A, KeyStatus::Pressed));
+    }
+
+    // Suggest a config line binding the key to the first unbound CHIP-8 key
+    #[test]
+    fn suggest_layout_line() {
+        let mut conf = Cfg::default();
+        let name = Keycode::P.name();
+        assert_eq!(layout_suggestion(Keycode::P, &conf), format!("{name} = 0"));
+        conf.clear_mapping(Keycode::R);
+        assert_eq!(layout_suggestion(Keycode::P, &conf), format!("{name} = 13"));
+    }
+}
@@ -1,8 +1,17 @@
-use chip8_lib::display::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip8_lib::config::Cfg;
+use chip8_lib::display::{
+    self, Palette, FRAME_SIZE, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH, PIXEL_COUNT, SCREEN_HEIGHT,
+    SCREEN_WIDTH,
+};
 use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+use std::time::Duration;
 
-// Simulated pixel grid resolution
+// Simulated pixel grid resolution, which the window is sized for
 pub const GRID_SIZE: (usize, usize) = (SCREEN_WIDTH, SCREEN_HEIGHT);
+// Pixel grid resolution in high resolution mode
+pub const HIRES_GRID_SIZE: (usize, usize) = (HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT);
 // Size of each pixel
 pub const GRID_CELL_SIZE: (u32, u32) = (16, 16);
 // True resolution
@@ -10,6 +19,273 @@ pub const SCREEN_SIZE: (u32, u32) = (
     GRID_SIZE.0 as u32 * GRID_CELL_SIZE.0,
     GRID_SIZE.1 as u32 * GRID_CELL_SIZE.1,
 );
-pub const RENDER_FPS: u32 = 60;
-pub const BG_COLOR: Color = Color::BLACK;
 pub const FG_COLOR: Color = Color::GREEN;
+// Faint lines along pixel boundaries
+pub const GRID_COLOR: Color = Color::RGB(40, 40, 40);
+pub const HUD_COLOR: Color = Color::WHITE;
+pub const HUD_DIM_COLOR: Color = Color::GREY;
+
+/// Time to spend on each redraw at the given rate
+pub fn frame_duration(render_hz: u32) -> Duration {
+    Duration::from_nanos(1_000_000_000 / u64::from(render_hz.max(1)))
+}
+
+/// Area of the window which the simulated screen is drawn in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Viewport {
+    // Position of the top left corner in the window
+    pub x: i32,
+    pub y: i32,
+    // Size of each simulated pixel
+    pub cell_size: (u32, u32),
+}
+
+impl Viewport {
+    /// Fit the simulated screen at the given resolution in a window of the given size, centered.
+    /// With integer_scaling, pixels are scaled by the largest whole factor which fits, keeping them square;
+    /// otherwise the width and height are scaled independently to fill as much of the window as possible.
+    pub fn fit(window: (u32, u32), resolution: (usize, usize), integer_scaling: bool) -> Self {
+        let fit_w = (window.0 / resolution.0 as u32).max(1);
+        let fit_h = (window.1 / resolution.1 as u32).max(1);
+        let cell_size = if integer_scaling {
+            (fit_w.min(fit_h), fit_w.min(fit_h))
+        } else {
+            (fit_w, fit_h)
+        };
+        let width = (resolution.0 as u32 * cell_size.0) as i32;
+        let height = (resolution.1 as u32 * cell_size.1) as i32;
+        Self {
+            x: (window.0 as i32 - width) / 2,
+            y: (window.1 as i32 - height) / 2,
+            cell_size,
+        }
+    }
+
+    /// Window area covered by the simulated pixel at (x, y)
+    pub fn cell(&self, x: u32, y: u32) -> Rect {
+        Rect::new(
+            self.x + (x * self.cell_size.0) as i32,
+            self.y + (y * self.cell_size.1) as i32,
+            self.cell_size.0,
+            self.cell_size.1,
+        )
+    }
+}
+
+// Brightness of each simulated pixel, 0 for off and u8::MAX for fully lit, with the color
+// it was last lit in, which it keeps while fading out.
+// Pixels are stored row by row at the current resolution, so there is room for high resolution.
+pub type Intensity = [(u8, Color); HIRES_GRID_SIZE.0 * HIRES_GRID_SIZE.1];
+pub const UNLIT: (u8, Color) = (0, Color::BLACK);
+
+/// Window title, naming the loaded ROM if there is one
+pub fn window_title(rom_name: Option<&str>) -> String {
+    match rom_name {
+        Some(name) => format!("CHIP-8 - {name}"),
+        None => "CHIP-8".to_string(),
+    }
+}
+
+/// File name for a new GIF recording, unique to the second it was saved
+#[cfg(feature = "gif")]
+pub fn recording_file_name() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    format!("chip8-{secs}.gif")
+}
+
+// Convert a 0xRRGGBB color from the config to an SDL color
+pub fn color_from_rgb(rgb: u32) -> Color {
+    Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+}
+
+// Whether pixel (x, y) is set in the first and in the second plane of a frame buffer
+// laid out for the given screen width
+fn pixel_planes(frame: &[u8; FRAME_SIZE], width: usize, x: usize, y: usize) -> (bool, bool) {
+    let idx = y * (width / 8) + x / 8;
+    let mask = 0x80 >> (x % 8);
+    (frame[idx] & mask != 0, frame[PIXEL_COUNT + idx] & mask != 0)
+}
+
+/// Intensity lost per frame by pixels which are turned off; without ghosting they turn off at once
+pub fn ghost_decay(conf: &Cfg) -> u8 {
+    if conf.ghosting() {
+        conf.ghost_decay()
+    } else {
+        u8::MAX
+    }
+}
+
+/// Advance pixel intensities by one frame of the given resolution: lit pixels are at full intensity,
+/// in the palette entry selected by the planes they are set in, and other pixels lose decay
+/// until they are off. A decay of u8::MAX turns pixels off instantly.
+pub fn update_intensity(
+    intensity: &mut Intensity,
+    frame: &[u8; FRAME_SIZE],
+    resolution: (usize, usize),
+    palette: &Palette,
+    decay: u8,
+) {
+    let (width, height) = resolution;
+    for (i, (val, color)) in intensity.iter_mut().take(width * height).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let (first, second) = pixel_planes(frame, width, x, y);
+        if first || second {
+            *val = u8::MAX;
+            *color = color_from_rgb(display::plane_color(palette, first, second));
+        } else {
+            *val = val.saturating_sub(decay);
+        }
+    }
+}
+
+// Mix fg into bg in proportion to intensity
+fn blend(bg: Color, fg: Color, intensity: u8) -> Color {
+    let mix = |b: u8, f: u8| {
+        let (b, f, a) = (b as u32, f as u32, intensity as u32);
+        ((b * (255 - a) + f * a) / 255) as u8
+    };
+    Color::RGB(mix(bg.r, fg.r), mix(bg.g, fg.g), mix(bg.b, fg.b))
+}
+
+/// Draw every pixel of the given resolution which is not off, blending its color over bg
+/// by its intensity
+pub fn draw_screen(
+    canvas: &mut WindowCanvas,
+    viewport: &Viewport,
+    intensity: &Intensity,
+    resolution: (usize, usize),
+    bg: Color,
+) -> Result<(), String> {
+    let (width, height) = resolution;
+    for (i, &(val, color)) in intensity.iter().take(width * height).enumerate() {
+        if val == 0 {
+            continue;
+        }
+        let (x, y) = ((i % width) as u32, (i / width) as u32);
+        canvas.set_draw_color(blend(bg, color, val));
+        canvas.fill_rect(viewport.cell(x, y))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8_lib::display::DEFAULT_PALETTE as PALETTE;
+
+    // The title names the ROM when one is loaded
+    #[test]
+    fn window_title_with_rom() {
+        assert_eq!(window_title(Some("pong.ch8")), "CHIP-8 - pong.ch8");
+        assert_eq!(window_title(None), "CHIP-8");
+    }
+
+    // Frame pacing follows the configured rate
+    #[test]
+    fn frame_duration_from_rate() {
+        assert_eq!(frame_duration(60), Duration::from_nanos(16_666_666));
+        assert_eq!(frame_duration(120), Duration::from_nanos(8_333_333));
+        assert_eq!(frame_duration(0), Duration::from_secs(1));
+    }
+
+    // Pixels fade out by the decay rate once they are turned off, and light up instantly
+    #[test]
+    fn update_intensity_fades() {
+        let mut intensity = [UNLIT; HIRES_GRID_SIZE.0 * HIRES_GRID_SIZE.1];
+        let mut frame = [0; FRAME_SIZE];
+        // Pixels (0, 0) and (9, 1)
+        frame[0] = 0x80;
+        frame[GRID_SIZE.0 / 8 + 1] = 0x40;
+        update_intensity(&mut intensity, &frame, GRID_SIZE, &PALETTE, 100);
+        assert_eq!(intensity[0], (u8::MAX, Color::GREEN));
+        assert_eq!(intensity[GRID_SIZE.0 + 9].0, u8::MAX);
+        assert_eq!(intensity.iter().filter(|(val, _)| *val > 0).count(), 2);
+
+        // Turn off (0, 0)
+        frame[0] = 0;
+        let mut faded = vec![];
+        for _ in 0..4 {
+            update_intensity(&mut intensity, &frame, GRID_SIZE, &PALETTE, 100);
+            faded.push(intensity[0].0);
+        }
+        assert_eq!(faded, [155, 55, 0, 0]);
+        assert_eq!(intensity[GRID_SIZE.0 + 9].0, u8::MAX);
+
+        // Without ghosting pixels turn off in one frame
+        frame[GRID_SIZE.0 / 8 + 1] = 0;
+        update_intensity(&mut intensity, &frame, GRID_SIZE, &PALETTE, u8::MAX);
+        assert!(intensity.iter().all(|(val, _)| *val == 0));
+    }
+
+    // Pixels take the palette entry of the planes they are set in, and keep it while fading
+    #[test]
+    fn update_intensity_planes() {
+        let mut intensity = [UNLIT; HIRES_GRID_SIZE.0 * HIRES_GRID_SIZE.1];
+        let mut frame = [0; FRAME_SIZE];
+        // (0, 0) in the second plane, (1, 0) in both
+        frame[PIXEL_COUNT] = 0xC0;
+        frame[0] = 0x40;
+        update_intensity(&mut intensity, &frame, GRID_SIZE, &PALETTE, 100);
+        assert_eq!(intensity[0], (u8::MAX, color_from_rgb(PALETTE[2])));
+        assert_eq!(intensity[1], (u8::MAX, color_from_rgb(PALETTE[3])));
+        frame[PIXEL_COUNT] = 0;
+        update_intensity(&mut intensity, &frame, GRID_SIZE, &PALETTE, 100);
+        assert_eq!(intensity[0], (155, color_from_rgb(PALETTE[2])));
+        assert_eq!(intensity[1], (u8::MAX, color_from_rgb(PALETTE[1])));
+    }
+
+    // High resolution frames are read with their own row stride
+    #[test]
+    fn update_intensity_hires() {
+        let mut intensity = [UNLIT; HIRES_GRID_SIZE.0 * HIRES_GRID_SIZE.1];
+        let mut frame = [0; FRAME_SIZE];
+        // Pixels (127, 0) and (64, 63)
+        frame[HIRES_GRID_SIZE.0 / 8 - 1] = 0x01;
+        frame[PIXEL_COUNT - HIRES_GRID_SIZE.0 / 16] = 0x80;
+        update_intensity(&mut intensity, &frame, HIRES_GRID_SIZE, &PALETTE, u8::MAX);
+        assert_eq!(intensity[HIRES_GRID_SIZE.0 - 1].0, u8::MAX);
+        assert_eq!(intensity[63 * HIRES_GRID_SIZE.0 + 64].0, u8::MAX);
+        assert_eq!(intensity.iter().filter(|(val, _)| *val > 0).count(), 2);
+    }
+
+    // The screen is scaled by whole factors and centered in the window
+    #[test]
+    fn viewport_integer_scaling() {
+        let fit = |w, h| {
+            let viewport = Viewport::fit((w, h), GRID_SIZE, true);
+            (viewport.x, viewport.y, viewport.cell_size)
+        };
+        assert_eq!(fit(SCREEN_SIZE.0, SCREEN_SIZE.1), (0, 0, GRID_CELL_SIZE));
+        // Wide window: limited by height, with bars on the left and right
+        assert_eq!(fit(1920, 320), (640, 0, (10, 10)));
+        // Tall window: limited by width, with bars above and below
+        assert_eq!(fit(640, 1000), (0, 340, (10, 10)));
+        // Sizes between whole factors round down
+        assert_eq!(fit(200, 100), (4, 2, (3, 3)));
+        // Tiny windows still draw each pixel, overflowing the window
+        assert_eq!(fit(32, 16), (-16, -8, (1, 1)));
+        let viewport = Viewport::fit((1920, 320), GRID_SIZE, true);
+        assert_eq!(viewport.cell(63, 31), Rect::new(1270, 310, 10, 10));
+    }
+
+    // High resolution pixels are half the size, filling the same area
+    #[test]
+    fn viewport_hires() {
+        let viewport = Viewport::fit(SCREEN_SIZE, HIRES_GRID_SIZE, true);
+        let half = (GRID_CELL_SIZE.0 / 2, GRID_CELL_SIZE.1 / 2);
+        assert_eq!((viewport.x, viewport.y, viewport.cell_size), (0, 0, half));
+    }
+
+    // Without integer scaling, width and height scale independently
+    #[test]
+    fn viewport_stretch() {
+        let viewport = Viewport::fit((1920, 330), GRID_SIZE, false);
+        assert_eq!(
+            (viewport.x, viewport.y, viewport.cell_size),
+            (0, 5, (30, 10))
+        );
+    }
+}
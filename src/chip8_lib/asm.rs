@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use thiserror::Error;
+
+use crate::cpu::PROGRAM_ENTRY_POINT;
+use crate::opcode::Opcode;
+
+// Error handling
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum AsmError {
+    #[error("line {line}: unknown mnemonic {mnemonic}")]
+    UnknownMnemonic { line: usize, mnemonic: String },
+    #[error("line {line}: invalid operands for {mnemonic}")]
+    InvalidOperands { line: usize, mnemonic: String },
+    #[error("line {line}: value {value} is out of range")]
+    ValueOutOfRange { line: usize, value: String },
+    #[error("line {line}: label {label} is not defined")]
+    UndefinedLabel { line: usize, label: String },
+    #[error("line {line}: label {label} is already defined")]
+    DuplicateLabel { line: usize, label: String },
+}
+
+// Mnemonics of all supported instructions
+const MNEMONICS: [&str; 28] = [
+    "CLS", "RET", "EXIT", "LOW", "HIGH", "SYS", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR",
+    "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP", "PLANE", "AUDIO",
+    "PITCH", "SAVE", "LOAD",
+];
+
+// Operand of an instruction, as written in the source
+#[derive(Debug, PartialEq, Eq)]
+enum Operand {
+    Register(u8),
+    Number(u16),
+    Label(String),
+    // I
+    Index,
+    // [I]
+    IndexMemory,
+    // DT
+    DelayTimer,
+    // ST
+    SoundTimer,
+    // K
+    Key,
+    // F
+    Font,
+    // B
+    Bcd,
+    // LONG, for LD I, LONG
+    Long,
+}
+
+// A single source line, stripped of its label and comment
+struct Statement<'a> {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+/// Assemble a program written in Cowgod's mnemonics into bytes which can be loaded at 0x200.
+/// Comments start with ';', and labels are defined with a trailing ':'.
+/// DB emits raw bytes, e.g. for sprite data, or the 16-bit address which follows LD I, LONG.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    // First pass: find the address of every label
+    let mut labels: HashMap<&str, u16> = HashMap::new();
+    let mut statements: Vec<Statement> = vec![];
+    let mut addr = PROGRAM_ENTRY_POINT as u16;
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let mut text = text.split(';').next().unwrap_or_default().trim();
+        if let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            if labels.insert(label, addr).is_some() {
+                return Err(AsmError::DuplicateLabel {
+                    line,
+                    label: label.to_string(),
+                });
+            }
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let (mnemonic, operands) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let operands: Vec<&str> = operands
+            .split(',')
+            .map(str::trim)
+            .filter(|op| !op.is_empty())
+            .collect();
+        let mnemonic = mnemonic.to_uppercase();
+        let size = if mnemonic == "DB" { operands.len() } else { 2 };
+        // Addresses past 0xFFFF cannot be held by a label, or loaded into any memory
+        addr = u16::try_from(size)
+            .ok()
+            .and_then(|size| addr.checked_add(size))
+            .ok_or_else(|| AsmError::ValueOutOfRange {
+                line,
+                value: format!("{:#X}", addr as usize + size),
+            })?;
+        statements.push(Statement {
+            line,
+            mnemonic,
+            operands,
+        });
+    }
+
+    // Second pass: encode instructions, now that labels can be resolved
+    let mut program: Vec<u8> = vec![];
+    for statement in statements {
+        let line = statement.line;
+        let operands = statement
+            .operands
+            .iter()
+            .map(|op| parse_operand(op, line))
+            .collect::<Result<Vec<Operand>, AsmError>>()?;
+        if statement.mnemonic == "DB" {
+            for op in &operands {
+                let byte = resolve(op, &labels, line, 0xFF)?;
+                program.push(byte as u8);
+            }
+            continue;
+        }
+        let inst = encode(&statement.mnemonic, &operands, &labels, line)?;
+        program.extend_from_slice(&inst.to_be_bytes());
+    }
+    Ok(program)
+}
+
+// Parse a single operand; anything which is not a register, keyword or number is a label
+fn parse_operand(op: &str, line: usize) -> Result<Operand, AsmError> {
+    let upper = op.to_uppercase();
+    let operand = match upper.as_str() {
+        "I" => Operand::Index,
+        "[I]" => Operand::IndexMemory,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "B" => Operand::Bcd,
+        "LONG" => Operand::Long,
+        _ if upper.len() == 2 && upper.starts_with('V') => {
+            match u8::from_str_radix(&upper[1..], 16) {
+                Ok(x) => Operand::Register(x),
+                Err(_) => Operand::Label(op.to_string()),
+            }
+        }
+        _ if upper.starts_with(|c: char| c.is_ascii_digit()) => {
+            let parsed = match upper.strip_prefix("0X") {
+                Some(hex) => u16::from_str_radix(hex, 16),
+                None => upper.parse::<u16>(),
+            };
+            match parsed {
+                Ok(val) => Operand::Number(val),
+                Err(_) => {
+                    return Err(AsmError::ValueOutOfRange {
+                        line,
+                        value: op.to_string(),
+                    })
+                }
+            }
+        }
+        _ => Operand::Label(op.to_string()),
+    };
+    Ok(operand)
+}
+
+// Value of a number or label operand, which must not exceed max
+fn resolve(
+    op: &Operand,
+    labels: &HashMap<&str, u16>,
+    line: usize,
+    max: u16,
+) -> Result<u16, AsmError> {
+    let val = match op {
+        Operand::Number(val) => *val,
+        Operand::Label(label) => match labels.get(label.as_str()) {
+            Some(addr) => *addr,
+            None => {
+                return Err(AsmError::UndefinedLabel {
+                    line,
+                    label: label.clone(),
+                })
+            }
+        },
+        _ => {
+            return Err(AsmError::ValueOutOfRange {
+                line,
+                value: format!("{op:?}"),
+            })
+        }
+    };
+    if val > max {
+        return Err(AsmError::ValueOutOfRange {
+            line,
+            value: format!("{val:#X}"),
+        });
+    }
+    Ok(val)
+}
+
+// Encode a single instruction, through the same Opcode the CPU decodes
+fn encode(
+    mnemonic: &str,
+    operands: &[Operand],
+    labels: &HashMap<&str, u16>,
+    line: usize,
+) -> Result<u16, AsmError> {
+    use Opcode::*;
+    use Operand::{
+        Bcd, DelayTimer, Font, Index, IndexMemory, Key, Long, Register as R, SoundTimer,
+    };
+    let addr = |op: &Operand| resolve(op, labels, line, 0xFFF);
+    let byte = |op: &Operand| resolve(op, labels, line, 0xFF).map(|val| val as u8);
+    let nibble = |op: &Operand| resolve(op, labels, line, 0xF).map(|val| val as u8);
+    let opcode = match (mnemonic, operands) {
+        ("CLS", []) => Cls,
+        ("RET", []) => Ret,
+        ("EXIT", []) => Exit,
+        ("LOW", []) => Low,
+        ("HIGH", []) => High,
+        ("SYS", [a]) => Sys { addr: addr(a)? },
+        ("JP", [R(0), a]) => JpV0 { addr: addr(a)? },
+        ("JP", [a]) => Jp { addr: addr(a)? },
+        ("CALL", [a]) => Call { addr: addr(a)? },
+        ("SE", [R(x), R(y)]) => SeVxVy { x: *x, y: *y },
+        ("SE", [R(x), b]) => SeVxByte {
+            x: *x,
+            byte: byte(b)?,
+        },
+        ("SNE", [R(x), R(y)]) => SneVxVy { x: *x, y: *y },
+        ("SNE", [R(x), b]) => SneVxByte {
+            x: *x,
+            byte: byte(b)?,
+        },
+        ("LD", [R(x), R(y)]) => LdVxVy { x: *x, y: *y },
+        ("LD", [Index, Long]) => LdILong,
+        ("LD", [Index, a]) => LdI { addr: addr(a)? },
+        ("LD", [R(x), DelayTimer]) => LdVxDt { x: *x },
+        ("LD", [R(x), Key]) => LdVxK { x: *x },
+        ("LD", [DelayTimer, R(x)]) => LdDtVx { x: *x },
+        ("LD", [SoundTimer, R(x)]) => LdStVx { x: *x },
+        ("LD", [Font, R(x)]) => LdFVx { x: *x },
+        ("LD", [Bcd, R(x)]) => LdBVx { x: *x },
+        ("LD", [IndexMemory, R(x)]) => LdIVx { x: *x },
+        ("LD", [R(x), IndexMemory]) => LdVxI { x: *x },
+        ("LD", [R(x), b]) => LdVxByte {
+            x: *x,
+            byte: byte(b)?,
+        },
+        ("ADD", [Index, R(x)]) => AddIVx { x: *x },
+        ("ADD", [R(x), R(y)]) => AddVxVy { x: *x, y: *y },
+        ("ADD", [R(x), b]) => AddVxByte {
+            x: *x,
+            byte: byte(b)?,
+        },
+        ("OR", [R(x), R(y)]) => Or { x: *x, y: *y },
+        ("AND", [R(x), R(y)]) => And { x: *x, y: *y },
+        ("XOR", [R(x), R(y)]) => Xor { x: *x, y: *y },
+        ("SUB", [R(x), R(y)]) => Sub { x: *x, y: *y },
+        ("SHR", [R(x)]) => Shr { x: *x, y: 0 },
+        ("SHR", [R(x), R(y)]) => Shr { x: *x, y: *y },
+        ("SUBN", [R(x), R(y)]) => Subn { x: *x, y: *y },
+        ("SHL", [R(x)]) => Shl { x: *x, y: 0 },
+        ("SHL", [R(x), R(y)]) => Shl { x: *x, y: *y },
+        ("RND", [R(x), b]) => Rnd {
+            x: *x,
+            byte: byte(b)?,
+        },
+        ("DRW", [R(x), R(y), n]) => Drw {
+            x: *x,
+            y: *y,
+            n: nibble(n)?,
+        },
+        ("SKP", [R(x)]) => Skp { x: *x },
+        ("SKNP", [R(x)]) => Sknp { x: *x },
+        ("PLANE", [n]) => Plane { n: nibble(n)? },
+        ("AUDIO", []) => Audio,
+        ("PITCH", [R(x)]) => Pitch { x: *x },
+        ("SAVE", [R(x), R(y)]) => SaveVxVy { x: *x, y: *y },
+        ("LOAD", [R(x), R(y)]) => LoadVxVy { x: *x, y: *y },
+        _ if MNEMONICS.contains(&mnemonic) => {
+            return Err(AsmError::InvalidOperands {
+                line,
+                mnemonic: mnemonic.to_string(),
+            })
+        }
+        _ => {
+            return Err(AsmError::UnknownMnemonic {
+                line,
+                mnemonic: mnemonic.to_string(),
+            })
+        }
+    };
+    Ok(opcode.encode())
+}
+
+/// Disassemble a single instruction into the syntax accepted by assemble.
+/// Words which are not valid instructions are written as DB.
+pub fn disassemble(inst: u16) -> String {
+    use Opcode::*;
+    let Ok(opcode) = Opcode::decode(inst) else {
+        return format!("DB {:#04X}, {:#04X}", inst >> 8, inst & 0x00FF);
+    };
+    match opcode {
+        Cls => "CLS".to_string(),
+        Ret => "RET".to_string(),
+        Exit => "EXIT".to_string(),
+        Low => "LOW".to_string(),
+        High => "HIGH".to_string(),
+        Sys { addr } => format!("SYS {addr:#05X}"),
+        Jp { addr } => format!("JP {addr:#05X}"),
+        Call { addr } => format!("CALL {addr:#05X}"),
+        SeVxByte { x, byte } => format!("SE V{x:X}, {byte:#04X}"),
+        SneVxByte { x, byte } => format!("SNE V{x:X}, {byte:#04X}"),
+        SeVxVy { x, y } => format!("SE V{x:X}, V{y:X}"),
+        SaveVxVy { x, y } => format!("SAVE V{x:X}, V{y:X}"),
+        LoadVxVy { x, y } => format!("LOAD V{x:X}, V{y:X}"),
+        LdVxByte { x, byte } => format!("LD V{x:X}, {byte:#04X}"),
+        AddVxByte { x, byte } => format!("ADD V{x:X}, {byte:#04X}"),
+        LdVxVy { x, y } => format!("LD V{x:X}, V{y:X}"),
+        Or { x, y } => format!("OR V{x:X}, V{y:X}"),
+        And { x, y } => format!("AND V{x:X}, V{y:X}"),
+        Xor { x, y } => format!("XOR V{x:X}, V{y:X}"),
+        AddVxVy { x, y } => format!("ADD V{x:X}, V{y:X}"),
+        Sub { x, y } => format!("SUB V{x:X}, V{y:X}"),
+        Shr { x, y } => format!("SHR V{x:X}, V{y:X}"),
+        Subn { x, y } => format!("SUBN V{x:X}, V{y:X}"),
+        Shl { x, y } => format!("SHL V{x:X}, V{y:X}"),
+        SneVxVy { x, y } => format!("SNE V{x:X}, V{y:X}"),
+        LdI { addr } => format!("LD I, {addr:#05X}"),
+        JpV0 { addr } => format!("JP V0, {addr:#05X}"),
+        Rnd { x, byte } => format!("RND V{x:X}, {byte:#04X}"),
+        Drw { x, y, n } => format!("DRW V{x:X}, V{y:X}, {n}"),
+        Skp { x } => format!("SKP V{x:X}"),
+        Sknp { x } => format!("SKNP V{x:X}"),
+        Plane { n } => format!("PLANE {n}"),
+        LdILong => "LD I, LONG".to_string(),
+        Audio => "AUDIO".to_string(),
+        LdVxDt { x } => format!("LD V{x:X}, DT"),
+        LdVxK { x } => format!("LD V{x:X}, K"),
+        LdDtVx { x } => format!("LD DT, V{x:X}"),
+        LdStVx { x } => format!("LD ST, V{x:X}"),
+        AddIVx { x } => format!("ADD I, V{x:X}"),
+        LdFVx { x } => format!("LD F, V{x:X}"),
+        LdBVx { x } => format!("LD B, V{x:X}"),
+        Pitch { x } => format!("PITCH V{x:X}"),
+        LdIVx { x } => format!("LD [I], V{x:X}"),
+        LdVxI { x } => format!("LD V{x:X}, [I]"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Assemble a program using labels, comments and data
+    #[test]
+    fn assemble_program() {
+        let source = "
+            ; Draw a smiley face forever
+                    CLS
+                    LD I, face
+                    LD V0, 0x10     ; x
+                    LD V1, 8        ; y
+            loop:   DRW V0, V1, 2
+                    JP loop
+            face:   DB 0x66, 0x3C
+        ";
+        let program = assemble(source).expect("assemble failed");
+        assert_eq!(
+            program,
+            vec![
+                0x00, 0xE0, 0xA2, 0x0C, 0x60, 0x10, 0x61, 0x08, 0xD0, 0x12, 0x12, 0x08, 0x66, 0x3C
+            ]
+        );
+    }
+
+    // Assembling the disassembly of a program produces the same program
+    #[test]
+    fn assemble_disassemble_round_trip() {
+        let source = "
+                    LD V5, 0xFF
+                    LD I, 0x300
+                    LD [I], V5
+                    ADD I, V5
+            sub:    SHR VA, VB
+                    SUBN V1, V2
+                    SE V3, V4
+                    SKNP VF
+                    LD F, VE
+                    LD B, VE
+                    CALL sub
+                    JP V0, 0x400
+                    HIGH
+                    PLANE 3
+                    LD I, LONG
+                    DB 0x12, 0x34
+                    AUDIO
+                    PITCH V7
+                    SAVE V2, V5
+                    LOAD V5, V2
+                    RET
+        ";
+        let program = assemble(source).expect("assemble failed");
+        let disassembly = program
+            .chunks_exact(2)
+            .map(|word| disassemble(u16::from_be_bytes([word[0], word[1]])))
+            .collect::<Vec<String>>();
+        assert_eq!(disassembly[4], "SHR VA, VB");
+        assert_eq!(disassembly[10], "CALL 0x208");
+        assert_eq!(disassembly[14], "LD I, LONG");
+        assert_eq!(
+            assemble(&disassembly.join("\n")).expect("assemble failed"),
+            program
+        );
+    }
+
+    // Report errors with the line on which they occur
+    #[test]
+    fn assemble_errors() {
+        assert_eq!(
+            assemble("CLS\nFOO V0"),
+            Err(AsmError::UnknownMnemonic {
+                line: 2,
+                mnemonic: "FOO".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("DRW V0, V1"),
+            Err(AsmError::InvalidOperands {
+                line: 1,
+                mnemonic: "DRW".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("LD V0, 0x100"),
+            Err(AsmError::ValueOutOfRange {
+                line: 1,
+                value: "0x100".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("JP nowhere"),
+            Err(AsmError::UndefinedLabel {
+                line: 1,
+                label: "nowhere".to_string()
+            })
+        );
+        // A program running past the end of the 16-bit address space
+        let data = format!("CLS\nDB {}", vec!["0"; 0xFE00].join(", "));
+        assert_eq!(
+            assemble(&data),
+            Err(AsmError::ValueOutOfRange {
+                line: 2,
+                value: "0x10002".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("a: CLS\na: CLS"),
+            Err(AsmError::DuplicateLabel {
+                line: 2,
+                label: "a".to_string()
+            })
+        );
+    }
+}
@@ -1,21 +1,212 @@
 use crate::config::Cfg;
-use crate::cpu::{self, Cpu};
-use crate::display::PIXEL_COUNT;
-use crate::input::KeyStatus;
+use crate::cpu::{self, Cpu, CpuError, IOError};
+use crate::display::{DisplayController, DrawReport, FrameDiff, FRAME_SIZE};
+use crate::input::{KeyStatus, KEY_COUNT};
+use crate::opcode::{Opcode, OpcodeFamily};
+use crate::quirk_test::{GridResults, ResultGrid, TestInput, PLATFORM_ADDR};
+use crate::quirks::Quirks;
+use crate::rom::{self, RomInfo};
+use crate::trace::StateSnapshot;
 use log::{debug, error, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
 use std::sync::mpsc::{Receiver, Sender};
 use std::time::{Duration, Instant};
 
+// Messages from the main thread which control execution
+#[derive(Debug, PartialEq, Eq)]
+pub enum ControlMessage {
+    Quit,
+    Pause,
+    Resume,
+    // Re-read the config file
+    ReloadConfig,
+}
+
+// Length of a frame, by which e.g. sticky keys are counted
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// Files making up a bundle directory, which ships a ROM together with its config
+const BUNDLE_ROM_FILE: &str = "rom.ch8";
+const BUNDLE_CONFIG_FILE: &str = "config.ini";
+
+// How often CPU state is sent to the main thread for its debug display
+const DEBUG_INFO_INTERVAL: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// Snapshot of CPU state for the frontend's debug display
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    pub pc: u16,
+    pub i: u16,
+    pub dt: u8,
+    pub st: u8,
+    pub registers: [u8; 16],
+    // Collision and wrapping of the most recent sprite draw
+    pub last_draw: DrawReport,
+}
+
+// Sound timer value and XO-CHIP audio state, sent to the main thread whenever they change
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SoundState {
+    pub timer: u8,
+    // Pattern to play instead of the beep, if the program has loaded one
+    pub pattern: Option<[u8; cpu::AUDIO_PATTERN_SIZE]>,
+    // Pitch register, which sets the playback rate of the pattern
+    pub pitch: u8,
+}
+
+// Why execution stopped, sent to the main thread when the CPU halts on an error
+// or the program exits
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HaltReason {
+    pub message: String,
+    pub opcode: u16,
+    pub pc: u16,
+}
+
+impl fmt::Display for HaltReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:#06X} at {:#05X}",
+            self.message, self.opcode, self.pc
+        )
+    }
+}
+
+// Callback given the frame buffer each time a changed frame is produced
+pub type FrameObserver = Box<dyn FnMut(&[u8]) + Send>;
+
+// Callback given an instruction error and the address of the instruction which caused it
+pub type ErrorCallback = Box<dyn Fn(CpuError, u16) + Send>;
+
+/// What happens when an instruction fails
+#[derive(Default)]
+pub enum ErrorPolicy {
+    // Log the error and pause execution, sending the halt reason
+    #[default]
+    Halt,
+    // Log the error and skip the failing instruction. If it cannot be skipped, halt instead.
+    Continue,
+    // Pass the error to the callback instead of logging it, then pause as with Halt,
+    // leaving recovery e.g. through undo_instruction or resume to the host
+    Callback(ErrorCallback),
+}
+
+/// Sets up a Chip8 in one chain: the config file, the ROM and the channels to the
+/// main thread, applied in that order by build. Anything left out keeps its default.
+#[derive(Default)]
+pub struct Chip8Builder {
+    config_path: Option<String>,
+    rom_path: Option<String>,
+    input_receiver: Option<Receiver<(u8, KeyStatus)>>,
+    control_receiver: Option<Receiver<ControlMessage>>,
+    display_transmitter: Option<Sender<FrameDiff>>,
+    debug_transmitter: Option<Sender<DebugInfo>>,
+    sound_transmitter: Option<Sender<SoundState>>,
+    halt_transmitter: Option<Sender<HaltReason>>,
+}
+
+impl Chip8Builder {
+    pub fn config(mut self, path: &str) -> Self {
+        self.config_path = Some(path.to_string());
+        self
+    }
+
+    /// ROM file to load, or a bundle directory as taken by Chip8::load_bundle
+    pub fn rom(mut self, path: &str) -> Self {
+        self.rom_path = Some(path.to_string());
+        self
+    }
+
+    /// Channels as taken by Chip8::connect
+    pub fn channels(
+        mut self,
+        input_rx: Receiver<(u8, KeyStatus)>,
+        control_rx: Receiver<ControlMessage>,
+        display_tx: Sender<FrameDiff>,
+    ) -> Self {
+        self.input_receiver = Some(input_rx);
+        self.control_receiver = Some(control_rx);
+        self.display_transmitter = Some(display_tx);
+        self
+    }
+
+    pub fn debug(mut self, debug_tx: Sender<DebugInfo>) -> Self {
+        self.debug_transmitter = Some(debug_tx);
+        self
+    }
+
+    pub fn sound(mut self, sound_tx: Sender<SoundState>) -> Self {
+        self.sound_transmitter = Some(sound_tx);
+        self
+    }
+
+    pub fn halt(mut self, halt_tx: Sender<HaltReason>) -> Self {
+        self.halt_transmitter = Some(halt_tx);
+        self
+    }
+
+    /// Create the interpreter, failing if the ROM cannot be loaded
+    pub fn build(self) -> Result<Chip8, IOError> {
+        let mut c8 = Chip8::new();
+        if let Some(path) = &self.config_path {
+            c8.load_config(path);
+        }
+        if let Some(path) = &self.rom_path {
+            if Path::new(path).is_dir() {
+                c8.load_bundle(path)?;
+            } else {
+                c8.load_rom(path)?;
+            }
+        }
+        c8.input_receiver = self.input_receiver;
+        c8.control_receiver = self.control_receiver;
+        c8.display_transmitter = self.display_transmitter;
+        c8.debug_transmitter = self.debug_transmitter;
+        c8.sound_transmitter = self.sound_transmitter;
+        c8.halt_transmitter = self.halt_transmitter;
+        Ok(c8)
+    }
+}
+
 #[derive(Default)]
 pub struct Chip8 {
     cpu: Cpu,
     config: Cfg,
+    // File the config was loaded from, for reloading
+    config_path: Option<String>,
     // Receiver which updates input controller from main thread
     input_receiver: Option<Receiver<(u8, KeyStatus)>>,
-    // Receiver which receives message to quit from main thread
-    quit_receiver: Option<Receiver<bool>>,
-    // Transmitter which sends frame buffer state
-    display_transmitter: Option<Sender<[u8; PIXEL_COUNT]>>,
+    // Receiver which receives control messages such as quit from main thread
+    control_receiver: Option<Receiver<ControlMessage>>,
+    // Transmitter which sends changes to frame buffer state
+    display_transmitter: Option<Sender<FrameDiff>>,
+    // Transmitter which sends CPU state for debugging
+    debug_transmitter: Option<Sender<DebugInfo>>,
+    // Transmitter which sends sound timer state
+    sound_transmitter: Option<Sender<SoundState>>,
+    // Transmitter which sends the reason execution halted
+    halt_transmitter: Option<Sender<HaltReason>>,
+    // How instruction errors are handled
+    error_policy: ErrorPolicy,
+    // Callback for in-process consumers of frames, e.g. a recorder
+    frame_observer: Option<FrameObserver>,
+    // Hash of the frame last passed to the frame observer
+    observed_frame_hash: u64,
+    // Number of 60Hz frames run since the program was loaded
+    frame: u64,
+    // Emulated time since the last 60Hz frame
+    frame_time: Duration,
+    // Instructions executed or dropped so far in the current frame
+    frame_instructions: usize,
+    // Instructions executed since the last one which drew or read input
+    instructions_since_draw: u64,
+    // Frames run at full speed so far, up to boot_turbo_frames
+    turbo_frames_run: u64,
+    // File name of the ROM loaded with load_rom; None for programs loaded from a slice
+    rom_name: Option<String>,
 }
 
 impl Chip8 {
@@ -23,80 +214,1359 @@ impl Chip8 {
         Self {
             cpu: Cpu::default(),
             config: Cfg::default(),
+            config_path: None,
             input_receiver: None,
-            quit_receiver: None,
+            control_receiver: None,
             display_transmitter: None,
+            debug_transmitter: None,
+            sound_transmitter: None,
+            halt_transmitter: None,
+            error_policy: ErrorPolicy::Halt,
+            frame_observer: None,
+            observed_frame_hash: 0,
+            frame: 0,
+            frame_time: Duration::ZERO,
+            frame_instructions: 0,
+            instructions_since_draw: 0,
+            turbo_frames_run: 0,
+            rom_name: None,
         }
     }
 
+    /// Set up an interpreter with its config, ROM and channels in one chain
+    pub fn builder() -> Chip8Builder {
+        Chip8Builder::default()
+    }
+
+    /// Create an interpreter running with the given config, e.g. a clone of another
+    /// instance's config with different quirks to compare the two side by side.
+    /// Instances share no state, so any number can run at once.
+    pub fn new_with_config(config: Cfg) -> Self {
+        let mut c8 = Self {
+            config,
+            ..Self::new()
+        };
+        c8.apply_config();
+        c8
+    }
+
     pub fn load_config(&mut self, filename: &str) -> &mut Self {
         self.config.load_config(filename);
+        self.config_path = Some(filename.to_string());
+        self.apply_config()
+    }
+
+    /// Re-read the config file given to load_config and apply it without restarting.
+    /// If the file is now missing, the previous config is kept.
+    pub fn reload_config(&mut self) -> &mut Self {
+        let Some(path) = &self.config_path else {
+            warn!("No config file has been loaded, nothing to reload.");
+            return self;
+        };
+        if self.config.reload(path) {
+            info!("Reloaded config from {path}.");
+            self.apply_settings();
+        }
+        self
+    }
+
+    /// Config the interpreter is currently running with
+    pub fn config(&self) -> &Cfg {
+        &self.config
+    }
+
+    /// Pass settings from the loaded config on to the CPU
+    pub fn apply_config(&mut self) -> &mut Self {
+        self.apply_settings();
+        if self.config.start_paused() {
+            info!("Starting paused as requested by config.");
+            self.cpu.pause();
+        }
         self
     }
 
+    // Pass settings which can change at runtime on to the CPU
+    fn apply_settings(&mut self) {
+        self.cpu.set_quirks(self.config.quirks());
+        self.cpu.set_min_beep_frames(self.config.min_beep_frames());
+        self.cpu.set_write_protect(self.config.write_protect());
+        self.cpu.set_memory_fill(self.config.memory_fill());
+        let sticky_frames = if self.config.sticky_keys() {
+            self.config.sticky_frames()
+        } else {
+            0
+        };
+        self.cpu.ict.set_sticky_frames(sticky_frames);
+        self.cpu.ict.set_track_hold(self.config.track_key_hold());
+    }
+
+    /// Quirks the CPU is currently running with
+    pub fn quirks(&self) -> Quirks {
+        self.cpu.quirks()
+    }
+
+    pub fn pause(&mut self) {
+        self.cpu.pause();
+    }
+
+    pub fn resume(&mut self) {
+        self.cpu.resume();
+    }
+
+    pub fn paused(&self) -> bool {
+        self.cpu.paused()
+    }
+
+    /// JSON snapshot of the CPU state for external tooling
+    #[cfg(feature = "serde")]
+    pub fn state_json(&self, include_memory: bool) -> String {
+        self.cpu.state_json(include_memory)
+    }
+
+    /// Log writes by the program to the given address, e.g. to follow a variable
+    pub fn watch_memory(&mut self, addr: u16) {
+        self.cpu.watch_memory(addr);
+    }
+
+    pub fn unwatch_memory(&mut self, addr: u16) {
+        self.cpu.unwatch_memory(addr);
+    }
+
+    /// Start or stop counting which opcodes the running program executes
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.cpu.set_profiling(enabled);
+    }
+
+    /// Only trace instructions of the given families, e.g. only draws, or every instruction with None.
+    /// Instructions are traced at the trace log level.
+    pub fn set_trace_filter(&mut self, filter: Option<HashSet<OpcodeFamily>>) {
+        self.cpu.set_trace_filter(filter);
+    }
+
+    /// Executed opcodes and their counts, if profiling is enabled
+    pub fn coverage_report(&self) -> Option<&HashMap<u16, u64>> {
+        self.cpu.coverage_report()
+    }
+
+    /// Start or stop counting sprite draws which collide with lit pixels
+    pub fn set_collision_counting(&mut self, enabled: bool) {
+        self.cpu.dct.set_collision_counting(enabled);
+    }
+
+    /// Read a program from the given file and gather metadata about it,
+    /// without loading it into the interpreter
+    pub fn inspect_rom(path: &str) -> Result<RomInfo, IOError> {
+        let program = cpu::read_program(path)?;
+        Ok(rom::inspect(&program))
+    }
+
     pub fn connect(
         &mut self,
         input_rx: Receiver<(u8, KeyStatus)>,
-        quit_rx: Receiver<bool>,
-        display_tx: Sender<[u8; PIXEL_COUNT]>,
+        control_rx: Receiver<ControlMessage>,
+        display_tx: Sender<FrameDiff>,
     ) -> &mut Self {
         self.input_receiver = Some(input_rx);
-        self.quit_receiver = Some(quit_rx);
+        self.control_receiver = Some(control_rx);
         self.display_transmitter = Some(display_tx);
         self
     }
 
+    /// Optionally connect a channel over which CPU state is periodically sent for debugging
+    pub fn connect_debug(&mut self, debug_tx: Sender<DebugInfo>) -> &mut Self {
+        self.debug_transmitter = Some(debug_tx);
+        self
+    }
+
+    /// Optionally connect a channel over which sound timer changes are sent
+    pub fn connect_sound(&mut self, sound_tx: Sender<SoundState>) -> &mut Self {
+        self.sound_transmitter = Some(sound_tx);
+        self
+    }
+
+    /// Register a callback which is given the frame buffer at the end of each 60Hz frame
+    /// in which the screen changed. This is an alternative to the display channel for
+    /// in-process consumers, e.g. a GIF recorder.
+    pub fn on_frame(&mut self, observer: FrameObserver) -> &mut Self {
+        self.frame_observer = Some(observer);
+        self.observed_frame_hash = self.cpu.dct.frame_hash();
+        self
+    }
+
+    // Pass the frame buffer to the frame observer if it has changed since it was last passed
+    fn notify_frame_observer(&mut self) {
+        let Some(observer) = &mut self.frame_observer else {
+            return;
+        };
+        let hash = self.cpu.dct.frame_hash();
+        if hash != self.observed_frame_hash {
+            self.observed_frame_hash = hash;
+            observer(&self.cpu.dct.snapshot());
+        }
+    }
+
+    /// Reset the interpreter, then copy a program to the entry point and start executing from it
+    pub fn load_rom_from_slice(&mut self, program: &[u8]) -> Result<(), IOError> {
+        if program.len() > self.cpu.memory_size() - cpu::PROGRAM_ENTRY_POINT {
+            return Err(IOError::ProgramTooLarge);
+        }
+        self.cpu.reset();
+        self.cpu.load_program_from_slice(program)?;
+        self.turbo_frames_run = 0;
+        self.frame = 0;
+        self.frame_time = Duration::ZERO;
+        self.frame_instructions = 0;
+        self.rom_name = None;
+        Ok(())
+    }
+
+    /// Read a program from the given file and load it as load_rom_from_slice does,
+    /// remembering the file name for current_rom_name
+    pub fn load_rom(&mut self, path: &str) -> Result<(), IOError> {
+        let program = cpu::read_program(path)?;
+        self.load_rom_from_slice(&program)?;
+        self.rom_name = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        Ok(())
+    }
+
+    /// Load a bundle: a directory holding a ROM as rom.ch8 along with the config it needs,
+    /// e.g. its quirks and timing, as config.ini. The config is applied before the ROM is loaded.
+    /// The bundle is named after its directory.
+    pub fn load_bundle(&mut self, path: &str) -> Result<(), IOError> {
+        let dir = Path::new(path);
+        let config_path = dir.join(BUNDLE_CONFIG_FILE);
+        if !config_path.is_file() {
+            error!("Bundle {path} has no {BUNDLE_CONFIG_FILE}.");
+            return Err(IOError::FileOpenError);
+        }
+        let program = cpu::read_program(&dir.join(BUNDLE_ROM_FILE).to_string_lossy())?;
+        self.load_config(&config_path.to_string_lossy());
+        self.load_rom_from_slice(&program)?;
+        self.rom_name = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        Ok(())
+    }
+
+    /// File name of the ROM loaded with load_rom, e.g. for the window title
+    pub fn current_rom_name(&self) -> Option<&str> {
+        self.rom_name.as_deref()
+    }
+
+    /// Display state, e.g. for a frontend which draws the screen itself
+    pub fn display(&self) -> &DisplayController {
+        &self.cpu.dct
+    }
+
+    /// Current contents of the frame buffer, e.g. for a frontend which connects mid-game
+    pub fn frame_snapshot(&self) -> [u8; FRAME_SIZE] {
+        self.cpu.dct.snapshot()
+    }
+
+    /// Optionally connect a channel over which the reason is sent when execution halts on an error
+    /// or the program exits
+    pub fn connect_halt(&mut self, halt_tx: Sender<HaltReason>) -> &mut Self {
+        self.halt_transmitter = Some(halt_tx);
+        self
+    }
+
+    /// Move the font to another address below the program entry point
+    pub fn set_font_address(&mut self, addr: usize) -> Result<(), CpuError> {
+        self.cpu.set_font_address(addr)
+    }
+
+    /// Choose how instruction errors are handled; they halt execution by default
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) -> &mut Self {
+        self.error_policy = policy;
+        self
+    }
+
+    /// Set all general purpose registers and the index register before running,
+    /// e.g. to reproduce a bug report. Loading a ROM resets them, so call this afterwards.
+    pub fn preset(&mut self, registers: [u8; 16], i: u16) {
+        self.cpu.preset(registers, i);
+    }
+
+    /// Set general purpose register Vx, for setting up test scenarios
+    #[cfg(feature = "testing")]
+    pub fn set_register(&mut self, x: usize, val: u8) -> Result<(), CpuError> {
+        self.cpu.set_register(x, val)
+    }
+
+    /// Set the byte of memory at addr, for setting up test scenarios
+    #[cfg(feature = "testing")]
+    pub fn set_memory(&mut self, addr: usize, val: u8) -> Result<(), CpuError> {
+        self.cpu.set_memory(addr, val)
+    }
+
+    /// Set the index register, for setting up test scenarios
+    #[cfg(feature = "testing")]
+    pub fn set_index(&mut self, val: u16) {
+        self.cpu.set_index(val);
+    }
+
+    pub fn debug_info(&self) -> DebugInfo {
+        DebugInfo {
+            pc: self.cpu.pc(),
+            i: self.cpu.index(),
+            dt: self.cpu.delay_timer(),
+            st: self.cpu.sound_timer(),
+            registers: self.cpu.registers(),
+            last_draw: self.cpu.dct.last_draw(),
+        }
+    }
+
+    /// Advance the interpreter by the given amount of host time.
+    /// Each 60Hz frame runs a fixed number of instructions, ipf or clock_hz / 60, spread over
+    /// the frame, and ticks the timers once at its end, so that how the time is split
+    /// between calls does not change execution.
+    /// At most max_instructions_per_frame instructions run per call; time beyond that is dropped.
+    /// While waiting for a key press only the timers run, as on real hardware.
+    /// Returns the number of instructions executed.
+    pub fn tick(&mut self, delta: Duration) -> usize {
+        if self.cpu.paused() || self.cpu.halted() {
+            return 0;
+        }
+        self.frame_time += delta;
+        let max_instructions = self.config.max_instructions_per_frame();
+        let mut executed = 0;
+        let mut dropped = 0;
+        while self.frame_time >= FRAME_DURATION {
+            self.frame_time -= FRAME_DURATION;
+            // Finish the current frame before starting the next one
+            let instructions_per_frame = self.instructions_per_frame();
+            let (frame_executed, frame_dropped) =
+                self.run_frame_instructions(instructions_per_frame, max_instructions - executed);
+            executed += frame_executed;
+            dropped += frame_dropped;
+            self.frame += 1;
+            self.frame_instructions = 0;
+            // Timers count down once per frame, however many frames the delta covers
+            self.cpu.timer_tick(FRAME_DURATION);
+            self.cpu.ict.frame_tick();
+            self.cpu.vblank();
+            self.notify_frame_observer();
+        }
+        let due = self.instructions_due();
+        let (frame_executed, frame_dropped) =
+            self.run_frame_instructions(due, max_instructions - executed);
+        executed += frame_executed;
+        dropped += frame_dropped;
+        if dropped > 0 {
+            warn!("Executed {max_instructions} instructions in one call; skipping {dropped} more of the emulated time.");
+        }
+        executed
+    }
+
+    // Run instructions of the current frame until target of them have run, at most limit.
+    // Instructions which cannot run because the CPU is waiting are dropped rather than
+    // carried over, as time spent waiting is lost on the COSMAC VIP.
+    // Returns the number executed, and the number dropped because limit was reached.
+    fn run_frame_instructions(&mut self, target: usize, limit: usize) -> (usize, usize) {
+        let mut executed = 0;
+        let mut dropped = 0;
+        while self.frame_instructions < target {
+            if executed == limit {
+                dropped = target - self.frame_instructions;
+                self.frame_instructions = target;
+                break;
+            }
+            if self.cpu.paused()
+                || self.cpu.halted()
+                || self.cpu.is_blocking()
+                || self.cpu.waiting_for_vblank()
+            {
+                self.frame_instructions = target;
+                break;
+            }
+            self.frame_instructions += 1;
+            executed += 1;
+            if !self.exec_instruction() {
+                break;
+            }
+        }
+        (executed, dropped)
+    }
+
+    // Instructions of the current frame which are due after frame_time of it has passed.
+    // Rounded to the nearest, since FRAME_DURATION is not a whole number of nanoseconds
+    // and rounding down would lose instructions to the error.
+    fn instructions_due(&self) -> usize {
+        let frame = FRAME_DURATION.as_nanos();
+        let instructions_per_frame = self.instructions_per_frame() as u128;
+        ((self.frame_time.as_nanos() * instructions_per_frame + frame / 2) / frame) as usize
+    }
+
+    /// Number of 60Hz frames run since the program was loaded, by tick or step_frame
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Advance exactly one 60Hz frame, whether or not execution is paused:
+    /// tick the timers once and run one frame's worth of instructions.
+    /// Stops early if the CPU halts on an error or waits for a key press.
+    /// Returns the number of instructions executed.
+    pub fn step_frame(&mut self) -> usize {
+        self.run_frame(FRAME_DURATION)
+    }
+
+    /// Run a test ROM such as the quirks test for the given number of frames, then read
+    /// the pass and fail marks it has drawn from the screen. Frames are stepped as by
+    /// step_frame, so the result does not depend on real time.
+    /// The input picks a platform or presses keys, so that the ROM gets past its menu.
+    pub fn run_quirk_test(
+        &mut self,
+        frames: u64,
+        grid: &ResultGrid,
+        input: &TestInput,
+    ) -> Result<GridResults, CpuError> {
+        if let Some(platform) = input.platform {
+            self.cpu.set_memory(PLATFORM_ADDR, platform)?;
+        }
+        for frame in 0..frames {
+            if self.cpu.halted() {
+                break;
+            }
+            for &(at, key) in &input.key_presses {
+                if at + 1 == frame {
+                    self.inject_key(key, KeyStatus::Unpressed);
+                }
+            }
+            for &(at, key) in &input.key_presses {
+                if at == frame {
+                    self.inject_key(key, KeyStatus::Pressed);
+                }
+            }
+            self.step_frame();
+        }
+        Ok(grid.read(&self.cpu.dct))
+    }
+
+    // Run one frame's worth of instructions, ticking the timers by timer_delta
+    fn run_frame(&mut self, timer_delta: Duration) -> usize {
+        if self.cpu.halted() {
+            return 0;
+        }
+        self.cpu.timer_tick(timer_delta);
+        self.cpu.ict.frame_tick();
+        self.cpu.vblank();
+        self.frame += 1;
+        self.frame_instructions = 0;
+        let instructions_per_frame = self.instructions_per_frame();
+        let mut executed = 0;
+        while executed < instructions_per_frame
+            && !self.cpu.is_blocking()
+            && !self.cpu.waiting_for_vblank()
+        {
+            executed += 1;
+            if !self.exec_instruction() {
+                break;
+            }
+        }
+        self.notify_frame_observer();
+        executed
+    }
+
+    // Time taken by one instruction at the configured speed
+    fn instruction_period(&self) -> Duration {
+        FRAME_DURATION / self.instructions_per_frame() as u32
+    }
+
+    // Instructions executed per 60Hz frame: ipf if set, otherwise derived from clock_hz
+    fn instructions_per_frame(&self) -> usize {
+        match self.config.ipf() {
+            0 => (self.config.clock_hz() / 60).max(1) as usize,
+            ipf => ipf as usize,
+        }
+    }
+
+    /// Whether execution is still within the configured boot_turbo_frames,
+    /// which run back to back without waiting for real time to pass
+    pub fn boot_turbo(&self) -> bool {
+        self.turbo_frames_run < self.config.boot_turbo_frames()
+    }
+
+    // Advance emulation for one iteration of the main loop, by a whole frame while in boot turbo
+    // and by the real time passed otherwise. Returns whether the loop should sleep to keep pace.
+    // Turbo frames tick the timers by a frame each, or by the real time passed
+    // if turbo_affects_timers is off.
+    fn run_slice(&mut self, delta: Duration) -> bool {
+        if self.boot_turbo() && !self.paused() {
+            self.turbo_frames_run += 1;
+            if self.config.turbo_affects_timers() {
+                self.run_frame(FRAME_DURATION);
+            } else {
+                self.run_frame(delta);
+            }
+            if !self.boot_turbo() {
+                info!("Boot turbo finished, running at normal speed.");
+            }
+            return false;
+        }
+        self.tick(delta);
+        true
+    }
+
+    /// Number of instructions executed since the last one which drew a sprite or read input
+    pub fn instructions_since_draw(&self) -> u64 {
+        self.instructions_since_draw
+    }
+
+    /// Whether the program has run for longer than the configured watchdog_instructions
+    /// without drawing or reading input, which usually means it is stuck in a loop
+    pub fn possibly_hung(&self) -> bool {
+        let limit = self.config.watchdog_instructions();
+        limit != 0 && self.instructions_since_draw >= limit
+    }
+
+    // Count instructions towards the watchdog, warning once when the limit is reached
+    fn watchdog(&mut self, opcode: u16) {
+        if is_draw_or_input(opcode) {
+            self.instructions_since_draw = 0;
+            return;
+        }
+        self.instructions_since_draw += 1;
+        if self.instructions_since_draw == self.config.watchdog_instructions() {
+            warn!(
+                "Executed {} instructions without drawing or reading input; the program may be hung at {:#05X}.",
+                self.instructions_since_draw,
+                self.cpu.pc()
+            );
+        }
+    }
+
+    // Execute a single instruction. On error, pause and notify the main thread,
+    // which is also notified when the program exits.
+    // Returns whether execution can continue.
+    fn exec_instruction(&mut self) -> bool {
+        let (pc, opcode) = (self.cpu.pc(), self.cpu.current_instruction());
+        self.watchdog(opcode);
+        let message = match self.cpu.exec_routine() {
+            Ok(()) if self.cpu.halted() => "program exited with".to_string(),
+            Ok(()) => return true,
+            Err(e) => {
+                let message = e.to_string();
+                match &self.error_policy {
+                    ErrorPolicy::Continue if self.cpu.skip_instruction().is_ok() => {
+                        error!("Error while executing instruction: {e}. Skipping it.");
+                        return true;
+                    }
+                    ErrorPolicy::Halt | ErrorPolicy::Continue => {
+                        error!("Error while executing instruction: {e}. Pausing execution.");
+                    }
+                    ErrorPolicy::Callback(callback) => callback(e, pc),
+                }
+                self.cpu.pause();
+                message
+            }
+        };
+        if let Some(tx) = &self.halt_transmitter {
+            let reason = HaltReason {
+                message,
+                opcode,
+                pc,
+            };
+            if let Err(e) = tx.send(reason) {
+                warn!("Failed to send halt reason to main thread: {e}");
+            }
+        }
+        false
+    }
+
+    /// Whether the program has exited, after which no further instructions run
+    pub fn halted(&self) -> bool {
+        self.cpu.halted()
+    }
+
+    /// Make RND repeatable, e.g. to compare runs of the same program with run_traced
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.cpu.seed_rng(seed);
+    }
+
+    /// Keep the changes made by the last depth instructions for undo_instruction,
+    /// e.g. while step debugging; 0 turns undo off
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.cpu.set_undo_depth(depth);
+    }
+
+    /// Revert the most recently executed instruction. Returns false if there is nothing to undo.
+    pub fn undo_instruction(&mut self) -> bool {
+        self.cpu.undo_instruction()
+    }
+
+    /// Execute up to max_instructions instructions, recording the state after each one.
+    /// Key events in inputs are applied just before the instruction with the given index,
+    /// so that runs with the same program, inputs and RNG seed are repeatable.
+    /// Stops early if the program halts, fails, waits for a key which is not in inputs or
+    /// jumps to its own address, as run_until_loop does. Timers are not ticked.
+    pub fn run_traced(
+        &mut self,
+        max_instructions: usize,
+        inputs: &[(usize, u8, KeyStatus)],
+    ) -> Vec<StateSnapshot> {
+        let mut trace = Vec::new();
+        for step in 0..max_instructions {
+            for &(_, key, status) in inputs.iter().filter(|(n, _, _)| *n == step) {
+                self.inject_key(key, status);
+            }
+            if self.cpu.is_blocking() {
+                break;
+            }
+            let (pc, opcode) = (self.cpu.pc(), self.cpu.current_instruction());
+            if self.jumps_to_self() || !self.exec_instruction() {
+                break;
+            }
+            trace.push(StateSnapshot {
+                pc,
+                opcode,
+                i: self.cpu.index(),
+                registers: self.cpu.registers(),
+                frame: self.cpu.dct.snapshot(),
+            });
+        }
+        trace
+    }
+
+    /// Run until the program exits or jumps to its own address, which test ROMs commonly do
+    /// when finished, or until max_instructions have been executed. Timers are not ticked.
+    pub fn run_until_loop(&mut self, max_instructions: usize) -> Result<(), CpuError> {
+        for _ in 0..max_instructions {
+            if self.cpu.halted() || self.jumps_to_self() {
+                return Ok(());
+            }
+            self.cpu.exec_routine()?;
+        }
+        Err(CpuError::InstructionLimitReached)
+    }
+
+    // Whether the current instruction is a JP to its own address. JP only addresses 12 bits,
+    // so this is never the case past 0xFFF, where JP nnn jumps back into the first 4KB.
+    fn jumps_to_self(&self) -> bool {
+        match Opcode::decode(self.cpu.current_instruction()) {
+            Ok(Opcode::Jp { addr }) => addr == self.cpu.pc(),
+            _ => false,
+        }
+    }
+
+    /// Number of frames a key has been held for, or was held for the last time it was released.
+    /// Only available when track_key_hold is enabled in the config.
+    pub fn key_held_duration(&self, key: u8) -> Option<u64> {
+        self.cpu.ict.key_held_duration(key)
+    }
+
+    /// Update the state of a CHIP-8 key directly, bypassing the input channel, and resume
+    /// execution if the program is waiting for a key press. Lets tests and scripts drive
+    /// input deterministically.
+    pub fn inject_key(&mut self, key: u8, state: KeyStatus) {
+        self.cpu.ict.update_key(key, &state);
+        if self.cpu.is_blocking() && state == KeyStatus::Pressed && key < KEY_COUNT {
+            debug!("Key {key} pressed while waiting for input.");
+            self.cpu.unblock(key);
+        }
+    }
+
+    // Apply all keyboard events which have arrived from main thread since the last call,
+    // so that presses are not delayed when several arrive between iterations
+    fn process_input(&mut self) {
+        match &self.input_receiver {
+            Some(rx) => {
+                let events: Vec<(u8, KeyStatus)> = rx.try_iter().collect();
+                for (key, state) in events {
+                    self.inject_key(key, state);
+                }
+            }
+            // Interpreter has not been connected with main thread
+            None => {
+                warn!("input_receiver has not been connected with main thread.")
+            }
+        }
+    }
+
     pub fn main_loop(&mut self) {
-        let mut start = Instant::now();
-        let mut end = Instant::now();
-        let mut delta: Duration;
+        let mut last = Instant::now();
+        let mut last_debug_info = last;
+        let mut last_sound_state = SoundState::default();
         'main: loop {
             // Check for new keyboard state from main thread
-            match &self.input_receiver {
-                Some(rx) => {
-                    if let Ok((key, state)) = rx.try_recv() {
-                        self.cpu.ict.update_key(key, &state);
-                        if self.cpu.is_blocking() && state == KeyStatus::Pressed {
-                            debug!("");
-                            self.cpu.unblock(key);
-                        }
+            self.process_input();
+
+            // Check for control messages from main thread
+            match &self.control_receiver {
+                Some(rx) => match rx.try_recv() {
+                    Ok(ControlMessage::Quit) => {
+                        info!("CPU: Halting execution.");
+                        break 'main;
                     }
-                }
-                // Interpreter has not been connected with main thread
+                    Ok(ControlMessage::Pause) => {
+                        info!("CPU: Pausing execution.");
+                        self.pause();
+                    }
+                    Ok(ControlMessage::Resume) => {
+                        info!("CPU: Resuming execution.");
+                        self.resume();
+                    }
+                    Ok(ControlMessage::ReloadConfig) => {
+                        self.reload_config();
+                    }
+                    Err(_) => {}
+                },
                 None => {
-                    warn!("input_receiver has not been connected with main thread.")
+                    warn!("control_receiver has not been connected with main thread.")
                 }
             }
 
-            // Check for quit message from main thread
-            match &self.quit_receiver {
-                Some(rx) => {
-                    if rx.try_recv().is_ok() {
-                        info!("CPU: Halting execution.");
-                        break 'main;
+            let now = Instant::now();
+            let keep_pace = self.run_slice(now - last);
+            last = now;
+
+            // Send changes to frame buffer to main thread
+            if let Some(tx) = &self.display_transmitter {
+                let diff = self.cpu.dct.take_frame_diff();
+                if !diff.changes.is_empty() {
+                    if let Err(e) = tx.send(diff) {
+                        warn!("Failed to send frame buffer to main thread: {e}");
                     }
                 }
-                None => {
-                    warn!("quit_receiver has not been connected with main thread.")
+            }
+
+            // Send sound state to main thread if it has changed
+            if let Some(tx) = &self.sound_transmitter {
+                let sound_state = SoundState {
+                    timer: self.cpu.sound_timer(),
+                    pattern: self.cpu.audio_pattern(),
+                    pitch: self.cpu.pitch(),
+                };
+                if sound_state != last_sound_state {
+                    last_sound_state = sound_state;
+                    if let Err(e) = tx.send(sound_state) {
+                        warn!("Failed to send sound state to main thread: {e}");
+                    }
                 }
             }
 
-            end = Instant::now();
-            delta = end - start;
-            if !self.cpu.paused() && !self.cpu.is_blocking() {
-                self.cpu.timer_tick(delta);
-                match self.cpu.exec_routine() {
-                    Ok(_) => {},
-                    Err(e) => {
-                        error!("Error while executing instruction: {e}. Pausing execution.");
-                        self.cpu.pause();
+            // Send CPU state to main thread for debugging
+            if let Some(tx) = &self.debug_transmitter {
+                if now - last_debug_info >= DEBUG_INFO_INTERVAL {
+                    last_debug_info = now;
+                    if let Err(e) = tx.send(self.debug_info()) {
+                        warn!("Failed to send debug info to main thread: {e}");
                     }
                 }
             }
-            start = Instant::now();
-            if delta < cpu::CLOCK_SPEED {
-                std::thread::sleep(cpu::CLOCK_SPEED - delta);
+            if self.cpu.halted() {
+                info!("CPU: Program exited, halting execution.");
+                break 'main;
+            }
+            let elapsed = Instant::now() - now;
+            let period = self.instruction_period();
+            if keep_pace && elapsed < period {
+                std::thread::sleep(period - elapsed);
             }
         }
     }
 }
+
+// DRW, SKP, SKNP and LD Vx, K, which a program that is not hung runs regularly
+fn is_draw_or_input(opcode: u16) -> bool {
+    opcode & 0xF000 == 0xD000 || matches!(opcode & 0xF0FF, 0xE09E | 0xE0A1 | 0xF00A)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+    use crate::trace::first_divergence;
+    use configparser::ini::Ini;
+    use std::sync::{mpsc, Arc, Mutex};
+
+    // Interpreter running with a config read from INI source, without going through a file
+    fn chip8_with_config(source: &str) -> Chip8 {
+        let mut config = Ini::new();
+        config.read(source.to_string()).expect("invalid INI");
+        let mut cfg = Cfg::default();
+        cfg.load_ini(&config);
+        Chip8::new_with_config(cfg)
+    }
+
+    // A huge delta must not run more than max_instructions_per_frame instructions
+    #[test]
+    fn tick_instruction_cap() {
+        let mut c8 = Chip8::default();
+        // JP 0x200; loops forever
+        c8.cpu
+            .load_program_from_slice(&[0x12, 0x00])
+            .expect("failed to load program");
+        let executed = c8.tick(Duration::from_secs(3600));
+        assert_eq!(executed, c8.config.max_instructions_per_frame());
+        assert_eq!(c8.frame(), 3600 * 60);
+        // Dropped time is not carried over to the next frame
+        let executed = c8.tick(cpu::CLOCK_SPEED * 3);
+        assert_eq!(executed, 3);
+    }
+
+    // Timers count down once for every frame a delta covers, even past the instruction cap
+    #[test]
+    fn tick_timers_per_frame() {
+        let mut c8 = Chip8::default();
+        // LD V0, 200; LD DT, V0; LD ST, V0; JP 0x206
+        c8.load_rom_from_slice(&[0x60, 0xC8, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06])
+            .unwrap();
+        c8.tick(FRAME_DURATION);
+        assert_eq!((c8.debug_info().dt, c8.debug_info().st), (199, 199));
+        c8.tick(FRAME_DURATION * 100);
+        assert_eq!((c8.debug_info().dt, c8.debug_info().st), (99, 99));
+        // A stalled host catches up on every timer tick, not just one
+        c8.tick(Duration::from_secs(3600));
+        assert_eq!((c8.debug_info().dt, c8.debug_info().st), (0, 0));
+    }
+
+    // Only instructions cut off by the limit count as dropped, not reaching it exactly
+    #[test]
+    fn run_frame_instructions_dropped() {
+        let mut c8 = Chip8::default();
+        // JP 0x200
+        c8.load_rom_from_slice(&[0x12, 0x00]).unwrap();
+        assert_eq!(c8.run_frame_instructions(10, 10), (10, 0));
+        c8.frame_instructions = 0;
+        assert_eq!(c8.run_frame_instructions(10, 4), (4, 6));
+        assert_eq!(c8.frame_instructions, 10);
+        // Instructions lost while waiting for a key are not dropped by the limit
+        c8.frame_instructions = 0;
+        // LD V0, K
+        c8.load_rom_from_slice(&[0xF0, 0x0A]).unwrap();
+        assert_eq!(c8.run_frame_instructions(10, 10), (1, 0));
+    }
+
+    // Every frame runs clock_hz / 60 instructions, however the time is split between ticks
+    #[test]
+    fn deterministic_frames() {
+        let mut c8 = Chip8::default();
+        // JP 0x200
+        c8.load_rom_from_slice(&[0x12, 0x00]).unwrap();
+        let total = FRAME_DURATION * 5;
+        let mut elapsed = Duration::ZERO;
+        let mut executed = 0;
+        for chunk in [7, 3, 11, 1, 13].into_iter().cycle() {
+            let delta = Duration::from_millis(chunk).min(total - elapsed);
+            executed += c8.tick(delta);
+            elapsed += delta;
+            if elapsed == total {
+                break;
+            }
+        }
+        assert_eq!(c8.frame(), 5);
+        assert_eq!(executed, 5 * c8.instructions_per_frame());
+        assert_eq!(c8.step_frame(), c8.instructions_per_frame());
+        assert_eq!(c8.frame(), 6);
+        // Loading a program starts counting again
+        c8.load_rom_from_slice(&[0x12, 0x00]).unwrap();
+        assert_eq!(c8.frame(), 0);
+    }
+
+    // One long tick and a tick per frame end with the same frame count and timers
+    #[test]
+    fn deterministic_timers() {
+        // LD V0, 90; LD DT, V0; LD ST, V0; JP 0x206
+        let rom = [0x60, 0x5A, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06];
+        let mut whole = Chip8::default();
+        whole.load_rom_from_slice(&rom).unwrap();
+        whole.tick(Duration::from_secs(1));
+        let mut split = Chip8::default();
+        split.load_rom_from_slice(&rom).unwrap();
+        for _ in 0..60 {
+            split.tick(Duration::from_secs(1) / 60);
+        }
+        assert_eq!(whole.frame(), 60);
+        assert_eq!(split.frame(), whole.frame());
+        let (whole, split) = (whole.debug_info(), split.debug_info());
+        assert_eq!((whole.dt, whole.st), (30, 30));
+        assert_eq!((split.dt, split.st), (whole.dt, whole.st));
+    }
+
+    // ipf sets the instructions per frame directly, overriding clock_hz
+    #[test]
+    fn ipf_override() {
+        let mut c8 = chip8_with_config("[timing]\nclock_hz = 1200\nipf = 15\n");
+        // JP 0x200
+        c8.load_rom_from_slice(&[0x12, 0x00]).unwrap();
+        assert_eq!(c8.instructions_per_frame(), 15);
+        assert_eq!(c8.tick(FRAME_DURATION), 15);
+        assert_eq!(c8.step_frame(), 15);
+        assert_eq!(c8.tick(FRAME_DURATION * 2), 30);
+    }
+
+    // Nothing executes while paused by config until resume is called
+    #[test]
+    fn start_paused() {
+        let mut c8 = chip8_with_config("[debug]\nstart_paused = true\n");
+        c8.cpu
+            .load_program_from_slice(&[0x12, 0x00])
+            .expect("failed to load program");
+        assert!(c8.paused());
+        assert_eq!(c8.tick(cpu::CLOCK_SPEED * 10), 0);
+        c8.resume();
+        assert!(!c8.paused());
+        assert_eq!(c8.tick(cpu::CLOCK_SPEED * 10), 10);
+    }
+
+    // Quirks from the config file reach the CPU
+    #[test]
+    fn load_config_quirks() {
+        let c8 = chip8_with_config("[quirks]\nshift_quirk = true\n");
+        assert!(c8.quirks().shift_quirk);
+        assert!(!c8.quirks().strict);
+    }
+
+    // All queued keyboard events are applied in one iteration
+    #[test]
+    fn process_input_drains_queue() {
+        let mut c8 = Chip8::default();
+        let (input_tx, input_rx) = mpsc::channel();
+        let (_control_tx, control_rx) = mpsc::channel();
+        let (display_tx, _display_rx) = mpsc::channel();
+        c8.connect(input_rx, control_rx, display_tx);
+        input_tx.send((0x1, KeyStatus::Pressed)).unwrap();
+        input_tx.send((0x5, KeyStatus::Pressed)).unwrap();
+        input_tx.send((0xA, KeyStatus::Pressed)).unwrap();
+        input_tx.send((0x5, KeyStatus::Unpressed)).unwrap();
+        c8.process_input();
+        assert!(c8.cpu.ict.key_pressed(0x1));
+        assert!(!c8.cpu.ict.key_pressed(0x5));
+        assert!(c8.cpu.ict.key_pressed(0xA));
+        assert_eq!(c8.cpu.ict.keys(), 0b0000_0100_0000_0010);
+    }
+
+    // An injected key press satisfies a pending LD Vx, K without a connected channel
+    #[test]
+    fn inject_key() {
+        let mut c8 = Chip8::default();
+        // LD V3, K; JP 0x202
+        c8.load_rom_from_slice(&[0xF3, 0x0A, 0x12, 0x02]).unwrap();
+        c8.exec_instruction();
+        assert!(c8.cpu.is_blocking());
+        // Keys outside the keypad do not unblock
+        c8.inject_key(0x10, KeyStatus::Pressed);
+        assert!(c8.cpu.is_blocking());
+        c8.inject_key(0xB, KeyStatus::Pressed);
+        assert!(!c8.cpu.is_blocking());
+        assert!(c8.cpu.ict.key_pressed(0xB));
+        assert_eq!(c8.debug_info().registers[3], 0xB);
+        c8.inject_key(0xB, KeyStatus::Unpressed);
+        assert!(!c8.cpu.ict.key_pressed(0xB));
+    }
+
+    // Timers keep counting down while waiting for a key press
+    #[test]
+    fn timers_tick_while_blocking() {
+        let mut c8 = Chip8::default();
+        // LD V0, 0x0A; LD DT, V0; LD V1, K
+        c8.cpu
+            .load_program_from_slice(&[0x60, 0x0A, 0xF0, 0x15, 0xF1, 0x0A])
+            .expect("failed to load program");
+        assert_eq!(c8.tick(cpu::CLOCK_SPEED * 3), 3);
+        assert!(c8.cpu.is_blocking());
+        let dt = c8.debug_info().dt;
+        for _ in 0..4 {
+            assert_eq!(c8.tick(FRAME_DURATION), 0);
+        }
+        assert_eq!(c8.debug_info().dt, dt - 4);
+        assert_eq!(c8.debug_info().pc, 0x206);
+    }
+
+    // The first boot_turbo_frames frames run whole frames without sleeping, later ones keep pace
+    #[test]
+    fn boot_turbo() {
+        let mut c8 = chip8_with_config("[timing]\nboot_turbo_frames = 3\n");
+        // JP 0x200
+        c8.load_rom_from_slice(&[0x12, 0x00]).unwrap();
+        for _ in 0..3 {
+            assert!(c8.boot_turbo());
+            assert!(!c8.run_slice(Duration::ZERO));
+        }
+        assert_eq!(c8.debug_info().pc, 0x200);
+        assert!(!c8.boot_turbo());
+        assert!(c8.run_slice(Duration::ZERO));
+        assert!(c8.run_slice(Duration::ZERO));
+    }
+
+    // With display_wait, execution stops after a draw until the next frame
+    #[test]
+    fn display_wait() {
+        let mut c8 = Chip8::default();
+        c8.cpu.set_quirks(Quirks {
+            display_wait: true,
+            ..Default::default()
+        });
+        // DRW V0, V0, 1; JP 0x200
+        c8.load_rom_from_slice(&[0xD0, 0x01, 0x12, 0x00]).unwrap();
+        assert_eq!(c8.step_frame(), 1);
+        assert_eq!(c8.step_frame(), 2);
+        // Time passing within the frame is dropped while waiting
+        assert_eq!(c8.tick(cpu::CLOCK_SPEED * 5), 0);
+        assert_eq!(c8.tick(FRAME_DURATION), 2);
+        assert_eq!(c8.tick(cpu::CLOCK_SPEED * 5), 0);
+    }
+
+    // The ROM name follows the most recently loaded program
+    #[test]
+    fn current_rom_name() {
+        let path = temp_path("rom_name.ch8");
+        let name = path.file_name().unwrap().to_str().unwrap();
+        std::fs::write(&path, [0x12, 0x00]).unwrap();
+        let mut c8 = Chip8::default();
+        assert_eq!(c8.current_rom_name(), None);
+        c8.load_rom(path.to_str().unwrap()).unwrap();
+        assert_eq!(c8.current_rom_name(), Some(name));
+        // A failed load keeps the running program
+        assert!(c8.load_rom("no/such/rom.ch8").is_err());
+        assert_eq!(c8.current_rom_name(), Some(name));
+        std::fs::remove_file(&path).unwrap();
+        c8.load_rom_from_slice(&[0x12, 0x00]).unwrap();
+        assert_eq!(c8.current_rom_name(), None);
+    }
+
+    // A bundle directory loads both its ROM and its config
+    #[test]
+    fn load_bundle() {
+        let dir = temp_path("bundle");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.to_str().unwrap();
+        let mut c8 = Chip8::default();
+        // Both files are required
+        std::fs::write(dir.join(BUNDLE_ROM_FILE), [0x60, 0x2A, 0x12, 0x02]).unwrap();
+        assert!(c8.load_bundle(path).is_err());
+        std::fs::write(
+            dir.join(BUNDLE_CONFIG_FILE),
+            "[quirks]\nshift_quirk = true\n[timing]\nclock_hz = 1200\n",
+        )
+        .unwrap();
+        c8.load_bundle(path).expect("failed to load bundle");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(c8.quirks().shift_quirk);
+        assert_eq!(c8.config().clock_hz(), 1200);
+        assert_eq!(c8.current_rom_name(), dir.file_name().unwrap().to_str());
+        assert_eq!(c8.step_frame(), 20);
+        assert_eq!(c8.cpu.register(0x0).unwrap(), 0x2A);
+    }
+
+    // Turbo frames count the delay timer down, unless turbo_affects_timers is off
+    #[test]
+    fn turbo_affects_timers() {
+        for (affects, expected_dt) in [(true, 6), (false, 10)] {
+            let ini =
+                format!("[timing]\nboot_turbo_frames = 5\nturbo_affects_timers = {affects}\n");
+            let mut c8 = chip8_with_config(&ini);
+            // LD V0, 10; LD DT, V0; JP 0x204
+            c8.load_rom_from_slice(&[0x60, 0x0A, 0xF0, 0x15, 0x12, 0x04])
+                .unwrap();
+            for _ in 0..5 {
+                c8.run_slice(Duration::ZERO);
+            }
+            // The timer is set during the first frame, after that frame's tick
+            assert_eq!(
+                c8.debug_info().dt,
+                expected_dt,
+                "turbo_affects_timers = {affects}"
+            );
+        }
+    }
+
+    // Runs of the same program under different shift quirks diverge at the first shift
+    #[test]
+    fn run_traced_divergence() {
+        // LD V0, K; RND V1, 0xFF; LD V2, 0x03; SHR V3, V2; ADD V3, 0x01; JP 0x20A
+        let program = [
+            0xF0, 0x0A, 0xC1, 0xFF, 0x62, 0x03, 0x83, 0x26, 0x73, 0x01, 0x12, 0x0A,
+        ];
+        let inputs = [(1, 0x7, KeyStatus::Pressed)];
+        let run = |shift_quirk| {
+            let mut c8 = Chip8::default();
+            c8.cpu.set_quirks(Quirks {
+                shift_quirk,
+                ..Default::default()
+            });
+            c8.seed_rng(1);
+            c8.load_rom_from_slice(&program).unwrap();
+            c8.run_traced(100, &inputs)
+        };
+        let (left, right) = (run(false), run(true));
+        assert_eq!(left.len(), 5);
+        assert_eq!(right.len(), 5);
+        assert_eq!(left[1].registers[0], 0x7);
+        assert!(first_divergence(&left, &left).is_none());
+        let divergence = first_divergence(&left, &right).expect("runs did not diverge");
+        assert_eq!(divergence.step, 3);
+        let (left, right) = (divergence.left.unwrap(), divergence.right.unwrap());
+        assert_eq!((left.pc, left.opcode), (0x206, 0x8326));
+        assert_eq!((left.registers[3], right.registers[3]), (0, 1));
+        // A run which stops early, here waiting for a key, diverges where it stopped
+        let mut c8 = Chip8::default();
+        c8.load_rom_from_slice(&program).unwrap();
+        let blocked = c8.run_traced(100, &[]);
+        assert_eq!(blocked.len(), 1);
+        let divergence = first_divergence(&blocked, &run(false)).unwrap();
+        assert_eq!(divergence.step, 1);
+        assert!(divergence.left.is_none());
+    }
+
+    // Stop at a jump to the jump's own address
+    #[test]
+    fn run_until_loop() {
+        let mut c8 = Chip8::default();
+        // LD V0, 0x00; ADD V0, 0x01; SE V0, 0x05; JP 0x202; JP 0x208
+        c8.cpu
+            .load_program_from_slice(&[0x60, 0x00, 0x70, 0x01, 0x30, 0x05, 0x12, 0x02, 0x12, 0x08])
+            .expect("failed to load program");
+        assert!(matches!(
+            c8.run_until_loop(10),
+            Err(CpuError::InstructionLimitReached)
+        ));
+        c8.run_until_loop(100).expect("loop not detected");
+        assert_eq!(c8.cpu.pc(), 0x208);
+        assert_eq!(c8.cpu.register(0x0).unwrap(), 5);
+    }
+
+    // A subroutine returns past its CALL, so the program reaches its final loop
+    #[test]
+    fn run_until_loop_call() {
+        let mut c8 = Chip8::default();
+        // CALL 0x206; ADD V0, 0x01; JP 0x204; ADD V0, 0x10; RET
+        c8.load_rom_from_slice(&[0x22, 0x06, 0x70, 0x01, 0x12, 0x04, 0x70, 0x10, 0x00, 0xEE])
+            .unwrap();
+        c8.run_until_loop(10).expect("loop not detected");
+        assert_eq!(c8.cpu.pc(), 0x204);
+        assert_eq!(c8.cpu.register(0x0).unwrap(), 0x11);
+    }
+
+    // A JP past 0xFFF jumps into the first 4KB, so it is not mistaken for a loop
+    #[test]
+    fn run_until_loop_extended_memory() {
+        let mut c8 = chip8_with_config("[quirks]\nextended_memory = true\n");
+        // LD V0, 0xFF; JP V0, 0xF03 to 0x1002, which holds JP 0x002
+        let mut program = vec![0x60, 0xFF, 0xBF, 0x03];
+        program.resize(0x1002 - 0x200, 0);
+        program.extend([0x10, 0x02]);
+        c8.load_rom_from_slice(&program).unwrap();
+        assert!(matches!(
+            c8.run_until_loop(3),
+            Err(CpuError::InstructionLimitReached)
+        ));
+        assert_eq!(c8.cpu.pc(), 0x002);
+
+        c8.load_rom_from_slice(&program).unwrap();
+        let trace = c8.run_traced(3, &[]);
+        assert_eq!(trace.last().map(|state| state.pc), Some(0x1002));
+    }
+
+    // Stepping a frame runs one frame of instructions and ticks the timers once, even while paused
+    #[test]
+    fn step_frame() {
+        let mut c8 = Chip8::default();
+        // LD V0, 0x05; LD ST, V0; then ADD V1, 0x01 over and over
+        let mut program = vec![0x60, 0x05, 0xF0, 0x18];
+        program.extend([0x71, 0x01].repeat(30));
+        c8.cpu
+            .load_program_from_slice(&program)
+            .expect("failed to load program");
+        c8.pause();
+        assert_eq!(c8.instructions_per_frame(), 10);
+        assert_eq!(c8.step_frame(), 10);
+        assert_eq!(c8.cpu.pc(), 0x200 + 2 * 10);
+        assert_eq!(c8.cpu.register(0x1).unwrap(), 8);
+        assert!(c8.paused());
+        assert_eq!(c8.step_frame(), 10);
+        assert_eq!(c8.cpu.register(0x1).unwrap(), 18);
+        assert_eq!(c8.cpu.sound_timer(), 4);
+    }
+
+    // The frame observer is called at the end of frames in which the screen changed
+    #[test]
+    fn on_frame() {
+        let mut c8 = Chip8::default();
+        let frames = Arc::new(Mutex::new(vec![]));
+        let observed = Arc::clone(&frames);
+        c8.on_frame(Box::new(move |frame| {
+            observed.lock().unwrap().push(frame[0]);
+        }));
+        // LD V0, 0x00; LD F, V0; DRW V1, V1, 5; then count V2 up to 30 before CLS
+        c8.cpu
+            .load_program_from_slice(&[
+                0x60, 0x00, 0xF0, 0x29, 0xD1, 0x15, 0x72, 0x01, 0x32, 0x1E, 0x12, 0x06, 0x00, 0xE0,
+                0x12, 0x0E,
+            ])
+            .expect("failed to load program");
+        c8.step_frame();
+        assert_eq!(*frames.lock().unwrap(), [0xF0]);
+        // Nothing is drawn while counting
+        c8.step_frame();
+        assert_eq!(frames.lock().unwrap().len(), 1);
+        for _ in 0..12 {
+            c8.tick(FRAME_DURATION);
+        }
+        assert_eq!(*frames.lock().unwrap(), [0xF0, 0x00]);
+    }
+
+    // Executing an unknown opcode halts with the opcode and its address
+    #[test]
+    fn halt_reason() {
+        let mut c8 = Chip8::default();
+        let (halt_tx, halt_rx) = mpsc::channel();
+        c8.connect_halt(halt_tx);
+        // LD V0, 0x01; invalid
+        c8.cpu
+            .load_program_from_slice(&[0x60, 0x01, 0xFF, 0xFF])
+            .expect("failed to load program");
+        c8.tick(cpu::CLOCK_SPEED * 3);
+        assert!(c8.paused());
+        let reason = halt_rx.try_recv().expect("no halt reason sent");
+        assert_eq!(reason.opcode, 0xFFFF);
+        assert_eq!(reason.pc, 0x202);
+        assert_eq!(
+            reason.to_string(),
+            "encountered unknown opcode 0xFFFF at 0x202"
+        );
+        assert!(halt_rx.try_recv().is_err());
+    }
+
+    // Two instances configured with different shift quirks run the same ROM independently
+    #[test]
+    fn new_with_config() {
+        let mut config = Cfg::default();
+        config.set_quirks(Quirks {
+            shift_quirk: true,
+            ..Default::default()
+        });
+        let mut vip = Chip8::new_with_config(config.clone());
+        config.set_quirks(Quirks::default());
+        let mut modern = Chip8::new_with_config(config);
+        assert!(vip.quirks().shift_quirk);
+        assert!(!modern.quirks().shift_quirk);
+        // LD V0, 0x10; LD V1, 0x03; SHR V0, V1
+        let program = [0x60, 0x10, 0x61, 0x03, 0x80, 0x16];
+        for c8 in [&mut vip, &mut modern] {
+            c8.load_rom_from_slice(&program).unwrap();
+            assert_eq!(c8.tick(cpu::CLOCK_SPEED * 3), 3);
+        }
+        assert_eq!(vip.debug_info().registers[0], 0x01);
+        assert_eq!(modern.debug_info().registers[0], 0x08);
+    }
+
+    // Each error policy's effect on a program with an unknown opcode between two loads
+    #[test]
+    fn error_policy() {
+        // LD V0, 0x01; invalid; LD V0, 0x02
+        let program = [0x60, 0x01, 0xFF, 0xFF, 0x60, 0x02];
+        let mut c8 = Chip8::default();
+        c8.load_rom_from_slice(&program).unwrap();
+        c8.set_error_policy(ErrorPolicy::Halt);
+        assert_eq!(c8.tick(cpu::CLOCK_SPEED * 3), 2);
+        assert!(c8.paused());
+        assert_eq!((c8.cpu.pc(), c8.cpu.register(0x0).unwrap()), (0x202, 0x01));
+
+        c8.load_rom_from_slice(&program).unwrap();
+        c8.resume();
+        c8.set_error_policy(ErrorPolicy::Continue);
+        assert_eq!(c8.tick(cpu::CLOCK_SPEED * 3), 3);
+        assert!(!c8.paused());
+        assert_eq!((c8.cpu.pc(), c8.cpu.register(0x0).unwrap()), (0x206, 0x02));
+
+        let errors = Arc::new(Mutex::new(vec![]));
+        let reported = Arc::clone(&errors);
+        c8.load_rom_from_slice(&program).unwrap();
+        c8.set_error_policy(ErrorPolicy::Callback(Box::new(move |e, pc| {
+            reported.lock().unwrap().push((e.to_string(), pc));
+        })));
+        assert_eq!(c8.tick(cpu::CLOCK_SPEED * 3), 2);
+        assert!(c8.paused());
+        assert_eq!(
+            *errors.lock().unwrap(),
+            [("encountered unknown opcode".to_string(), 0x202)]
+        );
+    }
+
+    // EXIT stops execution for good and reports why
+    #[test]
+    fn exit() {
+        let mut c8 = Chip8::default();
+        let (halt_tx, halt_rx) = mpsc::channel();
+        c8.connect_halt(halt_tx);
+        // LD V0, 0x01; EXIT; LD V0, 0x02
+        c8.load_rom_from_slice(&[0x60, 0x01, 0x00, 0xFD, 0x60, 0x02])
+            .unwrap();
+        assert_eq!(c8.tick(cpu::CLOCK_SPEED * 10), 2);
+        assert!(c8.halted());
+        assert!(!c8.paused());
+        let reason = halt_rx.try_recv().expect("no halt reason sent");
+        assert_eq!(reason.to_string(), "program exited with 0x00FD at 0x202");
+        assert_eq!(c8.tick(cpu::CLOCK_SPEED * 10), 0);
+        assert_eq!(c8.step_frame(), 0);
+        assert_eq!(c8.debug_info().registers[0], 0x01);
+    }
+
+    // Debug info reflects CPU state
+    #[test]
+    fn debug_info() {
+        let mut c8 = Chip8::default();
+        // LD V3, 0x07; LD I, 0x300
+        c8.cpu
+            .load_program_from_slice(&[0x63, 0x07, 0xA3, 0x00])
+            .expect("failed to load program");
+        c8.tick(cpu::CLOCK_SPEED * 2);
+        let info = c8.debug_info();
+        assert_eq!(info.pc, 0x204);
+        assert_eq!(info.i, 0x300);
+        let mut registers = [0; 16];
+        registers[3] = 0x07;
+        assert_eq!(info.registers, registers);
+    }
+
+    // A loop which never draws trips the watchdog, and drawing resets it
+    #[test]
+    fn watchdog() {
+        let mut c8 = chip8_with_config("[debug]\nwatchdog_instructions = 50\n");
+        // JP 0x200
+        c8.load_rom_from_slice(&[0x12, 0x00]).unwrap();
+        c8.tick(cpu::CLOCK_SPEED * 49);
+        assert!(!c8.possibly_hung());
+        c8.tick(cpu::CLOCK_SPEED);
+        assert!(c8.possibly_hung());
+        assert_eq!(c8.instructions_since_draw(), 50);
+
+        // DRW V0 V0 1, JP 0x200
+        c8.load_rom_from_slice(&[0xD0, 0x01, 0x12, 0x00]).unwrap();
+        c8.tick(cpu::CLOCK_SPEED * 99);
+        assert_eq!(c8.instructions_since_draw(), 0);
+        assert!(!c8.possibly_hung());
+    }
+
+    // Reloading picks up changes to the config file, and keeps the config if the file is gone
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn reload_config() {
+        use sdl2::keyboard::Keycode;
+        let path = temp_path("reload.ini");
+        std::fs::write(&path, "[keyboard_layout]\nA = 1\n").unwrap();
+        let mut c8 = Chip8::default();
+        c8.load_config(path.to_str().unwrap());
+        assert_eq!(c8.config().get_u8_from_keycode(Keycode::A), Some(&1));
+        assert!(!c8.quirks().shift_quirk);
+
+        std::fs::write(
+            &path,
+            "[keyboard_layout]\nB = 1\n[quirks]\nshift_quirk = true\n",
+        )
+        .unwrap();
+        c8.reload_config();
+        assert_eq!(c8.config().get_u8_from_keycode(Keycode::A), None);
+        assert_eq!(c8.config().get_u8_from_keycode(Keycode::B), Some(&1));
+        assert!(c8.quirks().shift_quirk);
+
+        std::fs::remove_file(&path).unwrap();
+        c8.reload_config();
+        assert_eq!(c8.config().get_u8_from_keycode(Keycode::B), Some(&1));
+        assert!(c8.quirks().shift_quirk);
+    }
+}
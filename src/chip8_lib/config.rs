@@ -1,8 +1,22 @@
+use crate::cpu::{MemoryFill, WriteProtect};
+use crate::display::{Palette, DEFAULT_PALETTE};
+use crate::quirks::Quirks;
 use configparser::ini::Ini;
-use log::{debug, error, warn};
+use log::warn;
+#[cfg(feature = "sdl")]
+use log::{debug, error};
+#[cfg(feature = "sdl")]
+use sdl2::controller::Axis;
+#[cfg(feature = "sdl")]
 use sdl2::keyboard::Keycode;
-use std::{collections::HashMap, env};
+#[cfg(feature = "sdl")]
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::io;
+use std::path::Path;
 
+#[cfg(feature = "sdl")]
 const DEFAULT_LAYOUT: [Keycode; 16] = [
     Keycode::X,
     Keycode::NUM_1,
@@ -22,24 +36,213 @@ const DEFAULT_LAYOUT: [Keycode; 16] = [
     Keycode::V,
 ];
 
+// Frames a released key keeps reading as pressed when sticky keys are enabled
+pub const DEFAULT_STICKY_FRAMES: u8 = 15;
+
+// Upper bound on instructions executed in a single frame, so that a stalled host
+// cannot make the interpreter try to catch up on an enormous backlog at once
+pub const DEFAULT_MAX_INSTRUCTIONS_PER_FRAME: usize = 1000;
+
+// Instructions executed per second
+pub const DEFAULT_CLOCK_HZ: u32 = 600;
+pub const CLOCK_HZ_RANGE: std::ops::RangeInclusive<u32> = 60..=100_000;
+// Instructions per frame set directly by ipf; 0 derives them from the clock speed
+pub const IPF_RANGE: std::ops::RangeInclusive<u32> = 0..=10_000;
+// Clock speed of the cosmac_vip profile, which approximates the effective speed of the
+// original interpreter
+pub const COSMAC_VIP_CLOCK_HZ: u32 = 700;
+
+// Instructions executed without drawing or reading input before a warning that the
+// program may be hung is logged
+pub const DEFAULT_WATCHDOG_INSTRUCTIONS: u64 = 10_000_000;
+
+// Intensity lost per frame by pixels which have been turned off, out of 255, when ghosting is enabled
+pub const DEFAULT_GHOST_DECAY: u8 = 64;
+
+// Screen redraws per second; timers run at 60Hz regardless
+pub const DEFAULT_RENDER_HZ: u32 = 60;
+pub const RENDER_HZ_RANGE: std::ops::RangeInclusive<u32> = 30..=360;
+
+/// Problem with an entry of the [keyboard_layout] heading
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LayoutWarning {
+    // Name which SDL does not recognize as a key; the entry is ignored
+    UnknownKey(String),
+    // Value which is not a number; the entry is ignored
+    InvalidValue { key: String, value: String },
+    // Value which is not a CHIP-8 key, i.e. above 0xF; the entry is ignored
+    OutOfRange { key: String, value: u8 },
+    // Key which is mapped more than once; the last entry wins
+    DuplicateKey(String),
+    // CHIP-8 key which several keys are mapped to
+    SharedChip8Key { chip8_key: u8, keys: Vec<String> },
+}
+
+impl fmt::Display for LayoutWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownKey(key) => write!(f, "Unknown key {key} in keyboard layout, ignoring."),
+            Self::InvalidValue { key, value } => {
+                write!(
+                    f,
+                    "Invalid CHIP-8 key {value} for {key} in keyboard layout, ignoring."
+                )
+            }
+            Self::OutOfRange { key, value } => write!(
+                f,
+                "CHIP-8 key {value} for {key} in keyboard layout is above 0xF, ignoring."
+            ),
+            Self::DuplicateKey(key) => {
+                write!(f, "Key {key} is mapped more than once in keyboard layout.")
+            }
+            Self::SharedChip8Key { chip8_key, keys } => write!(
+                f,
+                "CHIP-8 key {chip8_key:#X} is mapped to several keys: {}.",
+                keys.join(", ")
+            ),
+        }
+    }
+}
+
+/// Gamepad axis which presses a CHIP-8 key when pushed past a threshold
+#[cfg(feature = "sdl")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnalogBinding {
+    pub axis: Axis,
+    // Pushing the axis towards negative values presses the key, e.g. left on an X axis
+    pub negative: bool,
+    pub chip8_key: u8,
+    // Distance from the center, up to i16::MAX, at which the key is pressed
+    pub threshold: i16,
+}
+
+/// Shape of the beep played while the sound timer is active
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Waveform {
+    // The harsh tone of the original hardware
+    #[default]
+    Square,
+    Sine,
+    Triangle,
+    // Random levels, changing once per period
+    Noise,
+}
+
+/// Two keyboard keys which press a CHIP-8 key while both are held
+#[cfg(feature = "sdl")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Chord {
+    pub keys: [Keycode; 2],
+    pub chip8_key: u8,
+}
+
+#[derive(Clone)]
 pub struct Cfg {
+    #[cfg(feature = "sdl")]
     keyboard_layout: HashMap<Keycode, u8>,
+    // Pairs of keyboard keys mapped to CHIP-8 keys
+    #[cfg(feature = "sdl")]
+    chords: Vec<Chord>,
+    // Gamepad axes mapped to CHIP-8 keys
+    #[cfg(feature = "sdl")]
+    analog_bindings: Vec<AnalogBinding>,
+    // Problems found while parsing the keyboard layout
+    #[cfg(feature = "sdl")]
+    layout_warnings: Vec<LayoutWarning>,
+    quirks: Quirks,
+    clock_hz: u32,
+    // Instructions executed per 60Hz frame, overriding clock_hz / 60, including the clock
+    // of the cosmac_vip profile; 0 derives it from clock_hz
+    ipf: u32,
+    max_instructions_per_frame: usize,
+    // Frames run at full speed after loading, to get through slow intros; 0 disables
+    boot_turbo_frames: u64,
+    // Timers count down once per turbo frame rather than at 60Hz of real time
+    turbo_affects_timers: bool,
+    // Load programs without starting execution, e.g. to set breakpoints first
+    start_paused: bool,
+    // Warn after this many instructions without drawing or reading input; 0 disables the warning
+    watchdog_instructions: u64,
+    // Record how many frames each key is held for
+    track_key_hold: bool,
+    // Catch writes below the program entry point, e.g. to the font
+    write_protect: WriteProtect,
+    // Contents of memory outside the font and program when a program is loaded
+    memory_fill: MemoryFill,
+    // Beep pitch follows the sound timer value instead of staying fixed
+    variable_pitch: bool,
+    // Shape of the beep
+    waveform: Waveform,
+    // Nonzero sound timer values are raised to at least this many frames; 0 disables the clamp
+    min_beep_frames: u8,
+    // Released keys keep reading as pressed for sticky_frames frames
+    sticky_keys: bool,
+    sticky_frames: u8,
+    // Colors for the pixel states of the two bit planes
+    palette: Palette,
+    // Pixels which are turned off fade out by ghost_decay per frame, like CRT phosphor
+    ghosting: bool,
+    ghost_decay: u8,
+    // Scale the screen by whole factors only, keeping pixels square, when the window is resized
+    integer_scaling: bool,
+    // How often the frontend redraws the screen
+    render_hz: u32,
+    // Pause execution while the frontend window does not have focus
+    pause_on_focus_loss: bool,
 }
 
 impl Default for Cfg {
     fn default() -> Self {
-        let mut i: u8 = 0;
-        let layout = DEFAULT_LAYOUT
-            .iter()
-            .map(|val| {i += 1; (*val, i - 1)})
-            .collect::<HashMap<Keycode, u8>>();
         Self {
-            keyboard_layout: layout,
+            #[cfg(feature = "sdl")]
+            keyboard_layout: Cfg::default_layout(),
+            #[cfg(feature = "sdl")]
+            layout_warnings: vec![],
+            #[cfg(feature = "sdl")]
+            analog_bindings: vec![],
+            #[cfg(feature = "sdl")]
+            chords: vec![],
+            quirks: Quirks::default(),
+            clock_hz: DEFAULT_CLOCK_HZ,
+            ipf: 0,
+            max_instructions_per_frame: DEFAULT_MAX_INSTRUCTIONS_PER_FRAME,
+            boot_turbo_frames: 0,
+            turbo_affects_timers: true,
+            start_paused: false,
+            watchdog_instructions: DEFAULT_WATCHDOG_INSTRUCTIONS,
+            track_key_hold: false,
+            write_protect: WriteProtect::Off,
+            memory_fill: MemoryFill::Zero,
+            variable_pitch: false,
+            waveform: Waveform::Square,
+            min_beep_frames: 0,
+            sticky_keys: false,
+            sticky_frames: DEFAULT_STICKY_FRAMES,
+            palette: DEFAULT_PALETTE,
+            ghosting: false,
+            ghost_decay: DEFAULT_GHOST_DECAY,
+            integer_scaling: true,
+            render_hz: DEFAULT_RENDER_HZ,
+            pause_on_focus_loss: false,
         }
     }
 }
 
 impl Cfg {
+    /// Keyboard layout used when no config file is loaded: the left hand side of a
+    /// QWERTY keyboard, 1-4 down to Z-V, mapped to the COSMAC VIP keypad
+    #[cfg(feature = "sdl")]
+    pub fn default_layout() -> HashMap<Keycode, u8> {
+        let mut i: u8 = 0;
+        DEFAULT_LAYOUT
+            .iter()
+            .map(|val| {
+                i += 1;
+                (*val, i - 1)
+            })
+            .collect::<HashMap<Keycode, u8>>()
+    }
+    #[cfg(feature = "sdl")]
     pub fn get_u8_from_keycode(&self, k: Keycode) -> Option<&u8> {
         if self.keyboard_layout.is_empty() {
             error!("Keyboard layout is empty");
@@ -47,67 +250,943 @@ impl Cfg {
         }
         self.keyboard_layout.get(&k)
     }
+    /// Map a keyboard key to a CHIP-8 key, replacing any existing mapping of that keyboard key.
+    /// CHIP-8 keys above 0xF are rejected with a warning. Returns whether the mapping was set.
+    #[cfg(feature = "sdl")]
+    pub fn set_key_mapping(&mut self, keycode: Keycode, chip8_key: u8) -> bool {
+        if chip8_key > 0xF {
+            warn!("Cannot map {keycode} to CHIP-8 key {chip8_key:#X}, which is above 0xF.");
+            return false;
+        }
+        self.keyboard_layout.insert(keycode, chip8_key);
+        true
+    }
+    /// Remove the mapping of a keyboard key, returning the CHIP-8 key it was mapped to
+    #[cfg(feature = "sdl")]
+    pub fn clear_mapping(&mut self, keycode: Keycode) -> Option<u8> {
+        self.keyboard_layout.remove(&keycode)
+    }
+    /// Keyboard keys mapped to a CHIP-8 key, e.g. for showing the current bindings.
+    /// Sorted by keycode, so that the order is stable.
+    #[cfg(feature = "sdl")]
+    pub fn keycodes_for(&self, chip8_key: u8) -> Vec<Keycode> {
+        let mut keycodes: Vec<Keycode> = self
+            .keyboard_layout
+            .iter()
+            .filter(|(_, &val)| val == chip8_key)
+            .map(|(&keycode, _)| keycode)
+            .collect();
+        keycodes.sort_by_key(Keycode::into_i32);
+        keycodes
+    }
+    #[cfg(feature = "sdl")]
+    pub fn chords(&self) -> &[Chord] {
+        &self.chords
+    }
+    #[cfg(feature = "sdl")]
+    pub fn analog_bindings(&self) -> &[AnalogBinding] {
+        &self.analog_bindings
+    }
+    /// Problems found in the keyboard layout of the loaded config file, e.g. for the frontend to show
+    #[cfg(feature = "sdl")]
+    pub fn layout_warnings(&self) -> &[LayoutWarning] {
+        &self.layout_warnings
+    }
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+    pub fn clock_hz(&self) -> u32 {
+        self.clock_hz
+    }
+    pub fn ipf(&self) -> u32 {
+        self.ipf
+    }
+    pub fn max_instructions_per_frame(&self) -> usize {
+        self.max_instructions_per_frame
+    }
+    pub fn boot_turbo_frames(&self) -> u64 {
+        self.boot_turbo_frames
+    }
+    pub fn turbo_affects_timers(&self) -> bool {
+        self.turbo_affects_timers
+    }
+    pub fn start_paused(&self) -> bool {
+        self.start_paused
+    }
+    pub fn watchdog_instructions(&self) -> u64 {
+        self.watchdog_instructions
+    }
+    pub fn track_key_hold(&self) -> bool {
+        self.track_key_hold
+    }
+    pub fn write_protect(&self) -> WriteProtect {
+        self.write_protect
+    }
+    pub fn memory_fill(&self) -> MemoryFill {
+        self.memory_fill
+    }
+    pub fn variable_pitch(&self) -> bool {
+        self.variable_pitch
+    }
+    pub fn waveform(&self) -> Waveform {
+        self.waveform
+    }
+    pub fn min_beep_frames(&self) -> u8 {
+        self.min_beep_frames
+    }
+    pub fn sticky_keys(&self) -> bool {
+        self.sticky_keys
+    }
+    pub fn sticky_frames(&self) -> u8 {
+        self.sticky_frames
+    }
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+    pub fn ghosting(&self) -> bool {
+        self.ghosting
+    }
+    pub fn ghost_decay(&self) -> u8 {
+        self.ghost_decay
+    }
+    pub fn integer_scaling(&self) -> bool {
+        self.integer_scaling
+    }
+    pub fn render_hz(&self) -> u32 {
+        self.render_hz
+    }
+    pub fn pause_on_focus_loss(&self) -> bool {
+        self.pause_on_focus_loss
+    }
+    /// Re-read a config file from scratch, keeping the current config if the file cannot be found.
+    /// Returns whether the config was reloaded.
+    pub fn reload(&mut self, filepath: &str) -> bool {
+        if !Path::new(filepath).is_file() {
+            warn!("Config file {filepath} not found, keeping the current config.");
+            return false;
+        }
+        let mut config = Cfg::default();
+        config.load_config(filepath);
+        *self = config;
+        true
+    }
     /// Load a config file which defines a map of keys on keyboard to CHIP-8 layout
     /// Takes filepath as &String
+    /// If the file can't be read, every setting falls back to its default, so that
+    /// the interpreter still plays with the default keyboard layout.
     pub fn load_config(&mut self, filepath: &str) -> &mut Self {
         let mut config = Ini::new();
-        let mut path: String = match env::current_dir() {
-            Ok(val) => val.display().to_string(),
+        // Relative paths are resolved against the current directory
+        let path = match env::current_dir() {
+            Ok(val) => val.join(filepath),
             Err(e) => {
-                warn!("Unable to get current directory: [{e}]");
+                warn!("Unable to get current directory: [{e}]. Using default config.");
+                *self = Cfg::default();
                 return self;
             }
         };
-        path = path + "/" + filepath;
-        let layout: HashMap<Keycode, u8>;
-        // If config file is not found, revert to default keyboard layout
-        let raw_map = match config.load(path) {
-            Ok(val) => val,
-            Err(e) => {
-                warn!("Unable to load config file: [{e}]. Using default keyboard lyout.");
-                let mut i: u8 = 0;
-                layout = DEFAULT_LAYOUT
-                    .iter()
-                    .map(|val| {i += 1; (*val, i - 1)})
-                    .collect::<HashMap<Keycode, u8>>();
-                self.keyboard_layout = layout;
-                return self;
+        if let Err(e) = config.load(path) {
+            warn!("Unable to load config file: [{e}]. Using default config.");
+            *self = Cfg::default();
+            return self;
+        }
+        self.load_ini(&config)
+    }
+
+    // Read every heading of a parsed config, leaving defaults in place for missing entries
+    pub(crate) fn load_ini(&mut self, config: &Ini) -> &mut Self {
+        #[cfg(feature = "sdl")]
+        self.load_keyboard_layout(config);
+        #[cfg(feature = "sdl")]
+        self.load_keyboard_chords(config);
+        #[cfg(feature = "sdl")]
+        self.load_gamepad_analog(config);
+        self.load_quirks(config);
+        self.load_timing(config);
+        self.load_cosmac_vip(config);
+        self.load_debug(config);
+        self.load_audio(config);
+        self.load_accessibility(config);
+        self.load_display(config);
+        self
+    }
+
+    /// Write the keyboard layout, quirks and display settings to an INI file, e.g. from a
+    /// settings screen. Other entries already in the file are kept, but its comments are not.
+    pub fn save_config(&self, path: &str) -> io::Result<()> {
+        let mut config = Ini::new();
+        if Path::new(path).is_file() {
+            config
+                .load(path)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        #[cfg(feature = "sdl")]
+        {
+            let heading = "keyboard_layout";
+            config.remove_section(heading);
+            let mut layout: Vec<_> = self.keyboard_layout.iter().collect();
+            layout.sort_by_key(|(keycode, &chip8_key)| (chip8_key, keycode.name()));
+            for (keycode, chip8_key) in layout {
+                let name = layout_key_name(*keycode);
+                config.set(heading, &name, Some(chip8_key.to_string()));
             }
-        };
-        let heading = "keyboard_layout";
-        let parsed_heading = raw_map.get(heading);
+        }
+        let heading = "quirks";
+        let quirks = [
+            ("lores_dxy0", self.quirks.lores_dxy0),
+            ("strict", self.quirks.strict),
+            ("clip_sprites", self.quirks.clip_sprites),
+            ("shift_quirk", self.quirks.shift_quirk),
+            ("extended_memory", self.quirks.extended_memory),
+            ("vf_reset", self.quirks.vf_reset),
+            ("load_store_increment", self.quirks.load_store_increment),
+            ("display_wait", self.quirks.display_wait),
+            ("bit_planes", self.quirks.bit_planes),
+        ];
+        for (key, val) in quirks {
+            config.set(heading, key, Some(val.to_string()));
+        }
+        // The switch overrides the quirks above on load, so it must agree with them
+        let cosmac_vip = self.quirks == Quirks::cosmac_vip();
+        config.set(heading, "cosmac_vip", Some(cosmac_vip.to_string()));
+        let heading = "display";
+        let colors: Vec<String> = self.palette.iter().map(|c| format!("{c:06X}")).collect();
+        config.set(heading, "colors", Some(colors.join(", ")));
+        config.set(heading, "ghosting", Some(self.ghosting.to_string()));
+        config.set(heading, "ghost_decay", Some(self.ghost_decay.to_string()));
+        let integer_scaling = self.integer_scaling.to_string();
+        config.set(heading, "integer_scaling", Some(integer_scaling));
+        config.set(heading, "render_hz", Some(self.render_hz.to_string()));
+        let pause_on_focus_loss = self.pause_on_focus_loss.to_string();
+        config.set(heading, "pause_on_focus_loss", Some(pause_on_focus_loss));
+        config.write(path)
+    }
 
-        match parsed_heading {
+    // Read the [keyboard_layout] heading, which maps keyboard keys to CHIP-8 keys
+    #[cfg(feature = "sdl")]
+    fn load_keyboard_layout(&mut self, config: &Ini) {
+        let heading = "keyboard_layout";
+        match config.get_map_ref().get(heading) {
             Some(map) => {
                 debug!("Loaded heading: {heading} from config file");
-                layout = map
-                    .iter()
-                    .map(
-                        |(key, val)|
-                        {
-                            let mut k = Keycode::NUM_0;
-                            match Keycode::from_name(key) {
-                                Some(val) => k = val,
-                                None => { warn!("Failed to parse config entry to SDL keycode. Controls may not work as expected.") ; }
-                            };
-                            let v = val.as_ref().unwrap_or(&u8::MAX.to_string()).parse::<u8>().unwrap();
-                            debug!("Mapping {k} with value: {v}");
-                            (k, v)
-                        }
-                    )
-                    .collect::<HashMap<Keycode, u8>>();
-                // Validate the keys
-                for (_, val) in layout.iter() {
-                    if *val == u8::MAX {
-                        warn!("Unable to extract key value from config file.")
-                    }
+                let (layout, warnings) = parse_keyboard_layout(map);
+                for warning in &warnings {
+                    warn!("{warning}");
+                }
+                self.layout_warnings = warnings;
+                if layout.is_empty() {
+                    error!("No valid keys in {heading}, using default keyboard layout.");
+                    self.keyboard_layout = Cfg::default_layout();
+                } else {
+                    self.keyboard_layout = layout;
                 }
-                self.keyboard_layout = layout.clone();
             }
             None => {
-                error!("Unable to load {heading} from config file");
+                error!("Unable to load {heading} from config file, using default keyboard layout.");
+                self.keyboard_layout = Cfg::default_layout();
             }
         }
-        self
+    }
+
+    // Read the [keyboard_chords] heading, which maps pairs of keyboard keys to CHIP-8 keys
+    #[cfg(feature = "sdl")]
+    fn load_keyboard_chords(&mut self, config: &Ini) {
+        let Some(map) = config.get_map_ref().get("keyboard_chords") else {
+            return;
+        };
+        let mut entries: Vec<_> = map.iter().collect();
+        entries.sort();
+        self.chords = entries
+            .into_iter()
+            .filter_map(|(keys, val)| parse_chord(keys, val.as_deref().unwrap_or_default()))
+            .collect();
+    }
+
+    // Read the [gamepad_analog] heading, which maps gamepad axes to CHIP-8 keys
+    #[cfg(feature = "sdl")]
+    fn load_gamepad_analog(&mut self, config: &Ini) {
+        let Some(map) = config.get_map_ref().get("gamepad_analog") else {
+            return;
+        };
+        let mut entries: Vec<_> = map.iter().collect();
+        entries.sort();
+        self.analog_bindings = entries
+            .into_iter()
+            .filter_map(|(axis, val)| {
+                parse_analog_binding(axis, val.as_deref().unwrap_or_default())
+            })
+            .collect();
+    }
+
+    // Read the [quirks] heading, leaving defaults in place for missing entries
+    fn load_quirks(&mut self, config: &Ini) {
+        let heading = "quirks";
+        load_bool(config, heading, "lores_dxy0", &mut self.quirks.lores_dxy0);
+        load_bool(config, heading, "strict", &mut self.quirks.strict);
+        load_bool(
+            config,
+            heading,
+            "clip_sprites",
+            &mut self.quirks.clip_sprites,
+        );
+        load_bool(config, heading, "shift_quirk", &mut self.quirks.shift_quirk);
+        load_bool(
+            config,
+            heading,
+            "extended_memory",
+            &mut self.quirks.extended_memory,
+        );
+        load_bool(config, heading, "vf_reset", &mut self.quirks.vf_reset);
+        load_bool(
+            config,
+            heading,
+            "load_store_increment",
+            &mut self.quirks.load_store_increment,
+        );
+        load_bool(
+            config,
+            heading,
+            "display_wait",
+            &mut self.quirks.display_wait,
+        );
+        load_bool(config, heading, "bit_planes", &mut self.quirks.bit_planes);
+    }
+
+    // Read the cosmac_vip switch under [quirks], which overrides the individual quirks
+    // and the clock speed with those of the original COSMAC VIP interpreter
+    fn load_cosmac_vip(&mut self, config: &Ini) {
+        let mut cosmac_vip = false;
+        load_bool(config, "quirks", "cosmac_vip", &mut cosmac_vip);
+        if cosmac_vip {
+            self.quirks = Quirks::cosmac_vip();
+            self.clock_hz = COSMAC_VIP_CLOCK_HZ;
+        }
+    }
+
+    // Read the [timing] heading, leaving defaults in place for missing entries
+    fn load_timing(&mut self, config: &Ini) {
+        let heading = "timing";
+        match config.getuint(heading, "clock_hz") {
+            Ok(Some(hz)) => match u32::try_from(hz) {
+                Ok(hz) if CLOCK_HZ_RANGE.contains(&hz) => self.clock_hz = hz,
+                _ => warn!(
+                    "clock_hz must be between {} and {}, ignoring {hz}.",
+                    CLOCK_HZ_RANGE.start(),
+                    CLOCK_HZ_RANGE.end()
+                ),
+            },
+            Ok(None) => {}
+            Err(e) => warn!("Unable to parse clock_hz from config file: [{e}]"),
+        }
+        match config.getuint(heading, "ipf") {
+            Ok(Some(val)) => match u32::try_from(val) {
+                Ok(val) if IPF_RANGE.contains(&val) => self.ipf = val,
+                _ => warn!(
+                    "ipf must be between {} and {}, ignoring {val}.",
+                    IPF_RANGE.start(),
+                    IPF_RANGE.end()
+                ),
+            },
+            Ok(None) => {}
+            Err(e) => warn!("Unable to parse ipf from config file: [{e}]"),
+        }
+        match config.getuint(heading, "max_instructions_per_frame") {
+            Ok(Some(0)) => warn!("max_instructions_per_frame must be nonzero, ignoring."),
+            Ok(Some(val)) => self.max_instructions_per_frame = val as usize,
+            Ok(None) => {}
+            Err(e) => warn!("Unable to parse max_instructions_per_frame from config file: [{e}]"),
+        }
+        if self.ipf as usize > self.max_instructions_per_frame {
+            warn!(
+                "ipf {} exceeds max_instructions_per_frame {}, only that many will run per frame.",
+                self.ipf, self.max_instructions_per_frame
+            );
+        }
+        match config.getuint(heading, "boot_turbo_frames") {
+            Ok(Some(val)) => self.boot_turbo_frames = val,
+            Ok(None) => {}
+            Err(e) => warn!("Unable to parse boot_turbo_frames from config file: [{e}]"),
+        }
+        load_bool(
+            config,
+            heading,
+            "turbo_affects_timers",
+            &mut self.turbo_affects_timers,
+        );
+    }
+
+    // Read the [debug] heading, leaving defaults in place for missing entries
+    fn load_debug(&mut self, config: &Ini) {
+        let heading = "debug";
+        load_bool(config, heading, "start_paused", &mut self.start_paused);
+        match config.getuint(heading, "watchdog_instructions") {
+            Ok(Some(val)) => self.watchdog_instructions = val,
+            Ok(None) => {}
+            Err(e) => warn!("Unable to parse watchdog_instructions from config file: [{e}]"),
+        }
+        load_bool(config, heading, "track_key_hold", &mut self.track_key_hold);
+        if let Some(mode) = config.get(heading, "write_protect") {
+            match mode.to_lowercase().as_str() {
+                "off" => self.write_protect = WriteProtect::Off,
+                "skip" => self.write_protect = WriteProtect::Skip,
+                "error" => self.write_protect = WriteProtect::Error,
+                _ => warn!("write_protect must be one of off, skip or error, ignoring {mode}."),
+            }
+        }
+        if let Some(fill) = config.get(heading, "memory_fill") {
+            match fill.to_lowercase().as_str() {
+                "zero" => self.memory_fill = MemoryFill::Zero,
+                "ones" => self.memory_fill = MemoryFill::Ones,
+                "random" => {
+                    let seed = match config.getuint(heading, "memory_fill_seed") {
+                        Ok(seed) => seed.unwrap_or_default(),
+                        Err(e) => {
+                            warn!("Unable to parse memory_fill_seed from config file: [{e}]");
+                            0
+                        }
+                    };
+                    self.memory_fill = MemoryFill::Random(seed);
+                }
+                _ => warn!("memory_fill must be one of zero, ones or random, ignoring {fill}."),
+            }
+        }
+    }
+
+    // Read the [audio] heading, leaving defaults in place for missing entries
+    fn load_audio(&mut self, config: &Ini) {
+        let heading = "audio";
+        load_bool(config, heading, "variable_pitch", &mut self.variable_pitch);
+        if let Some(waveform) = config.get(heading, "waveform") {
+            match waveform.to_lowercase().as_str() {
+                "square" => self.waveform = Waveform::Square,
+                "sine" => self.waveform = Waveform::Sine,
+                "triangle" => self.waveform = Waveform::Triangle,
+                "noise" => self.waveform = Waveform::Noise,
+                _ => warn!(
+                    "waveform must be one of square, sine, triangle or noise, ignoring {waveform}."
+                ),
+            }
+        }
+        load_u8(
+            config,
+            heading,
+            "min_beep_frames",
+            &mut self.min_beep_frames,
+        );
+    }
+
+    // Read the [display] heading, leaving defaults in place for missing entries
+    fn load_display(&mut self, config: &Ini) {
+        let heading = "display";
+        if let Some(colors) = config.get(heading, "colors") {
+            self.palette = parse_palette(&colors);
+        }
+        load_bool(config, heading, "ghosting", &mut self.ghosting);
+        load_u8(config, heading, "ghost_decay", &mut self.ghost_decay);
+        if self.ghost_decay == 0 {
+            warn!("ghost_decay must be nonzero, ignoring.");
+            self.ghost_decay = DEFAULT_GHOST_DECAY;
+        }
+        load_bool(
+            config,
+            heading,
+            "integer_scaling",
+            &mut self.integer_scaling,
+        );
+        match config.getuint(heading, "render_hz") {
+            Ok(Some(hz)) => match u32::try_from(hz) {
+                Ok(hz) if RENDER_HZ_RANGE.contains(&hz) => self.render_hz = hz,
+                _ => warn!(
+                    "render_hz must be between {} and {}, ignoring {hz}.",
+                    RENDER_HZ_RANGE.start(),
+                    RENDER_HZ_RANGE.end()
+                ),
+            },
+            Ok(None) => {}
+            Err(e) => warn!("Unable to parse render_hz from config file: [{e}]"),
+        }
+        load_bool(
+            config,
+            heading,
+            "pause_on_focus_loss",
+            &mut self.pause_on_focus_loss,
+        );
+    }
+
+    // Read the [accessibility] heading, leaving defaults in place for missing entries
+    fn load_accessibility(&mut self, config: &Ini) {
+        let heading = "accessibility";
+        load_bool(config, heading, "sticky_keys", &mut self.sticky_keys);
+        load_u8(config, heading, "sticky_frames", &mut self.sticky_frames);
+    }
+}
+
+// Build a keyboard layout from [keyboard_layout] entries of key name and CHIP-8 key.
+// Invalid entries are left out and reported along with conflicting ones.
+#[cfg(feature = "sdl")]
+fn parse_keyboard_layout(
+    map: &HashMap<String, Option<String>>,
+) -> (HashMap<Keycode, u8>, Vec<LayoutWarning>) {
+    let mut layout = HashMap::new();
+    let mut warnings = vec![];
+    // Sort entries so that conflicts are resolved and reported in a stable order
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort();
+    for (key, val) in entries {
+        let Some(keycode) = parse_layout_key(key) else {
+            warnings.push(LayoutWarning::UnknownKey(key.clone()));
+            continue;
+        };
+        let val = val.clone().unwrap_or_default();
+        let Ok(chip8_key) = val.parse::<u8>() else {
+            warnings.push(LayoutWarning::InvalidValue {
+                key: key.clone(),
+                value: val,
+            });
+            continue;
+        };
+        if chip8_key > 0xF {
+            warnings.push(LayoutWarning::OutOfRange {
+                key: key.clone(),
+                value: chip8_key,
+            });
+            continue;
+        }
+        debug!("Mapping {keycode} with value: {chip8_key}");
+        if layout.insert(keycode, chip8_key).is_some() {
+            warnings.push(LayoutWarning::DuplicateKey(keycode.name()));
+        }
+    }
+    for chip8_key in 0..=0xF {
+        let mut keys: Vec<String> = layout
+            .iter()
+            .filter(|(_, &val)| val == chip8_key)
+            .map(|(keycode, _)| keycode.name())
+            .collect();
+        if keys.len() > 1 {
+            keys.sort();
+            warnings.push(LayoutWarning::SharedChip8Key { chip8_key, keys });
+        }
+    }
+    (layout, warnings)
+}
+
+// Name a key is written under in [keyboard_layout]. Names the INI format cannot hold,
+// such as ";" or "=", are written as the SDL keycode in hex instead, e.g. 0x3B.
+#[cfg(feature = "sdl")]
+fn layout_key_name(keycode: Keycode) -> String {
+    let name = keycode.name();
+    if name.is_empty() || name.trim() != name || name.contains(['=', ':', ';', '#', '[', ']']) {
+        format!("{:#X}", keycode.into_i32())
+    } else {
+        name
+    }
+}
+
+// Key of a [keyboard_layout] entry: a key name, or an SDL keycode in hex as written
+// by layout_key_name
+#[cfg(feature = "sdl")]
+fn parse_layout_key(key: &str) -> Option<Keycode> {
+    Keycode::from_name(key).or_else(|| {
+        let hex = key.strip_prefix("0x").or_else(|| key.strip_prefix("0X"))?;
+        Keycode::from_i32(i32::from_str_radix(hex, 16).ok()?)
+    })
+}
+
+// Parse a [keyboard_chords] entry: two key names joined by '+', mapped to a CHIP-8 key,
+// e.g. "q + w = 5"
+#[cfg(feature = "sdl")]
+fn parse_chord(keys: &str, value: &str) -> Option<Chord> {
+    let Some((first, second)) = keys.split_once('+') else {
+        warn!("Chord {keys} must be two keys joined by '+', ignoring.");
+        return None;
+    };
+    let (Some(first), Some(second)) = (
+        Keycode::from_name(first.trim()),
+        Keycode::from_name(second.trim()),
+    ) else {
+        warn!("Unknown key in chord {keys}, ignoring.");
+        return None;
+    };
+    match value.trim().parse::<u8>() {
+        Ok(chip8_key) if chip8_key <= 0xF => Some(Chord {
+            keys: [first, second],
+            chip8_key,
+        }),
+        _ => {
+            warn!("Chord {keys} must map to a CHIP-8 key up to 15, ignoring {value}.");
+            None
+        }
+    }
+}
+
+// Parse a [gamepad_analog] entry: an SDL axis name with an optional + or - for the direction,
+// mapped to "chip8_key, threshold", e.g. "leftx- = 4, 16000"
+#[cfg(feature = "sdl")]
+fn parse_analog_binding(axis: &str, value: &str) -> Option<AnalogBinding> {
+    let (name, negative) = match axis.strip_suffix('-') {
+        Some(name) => (name, true),
+        None => (axis.strip_suffix('+').unwrap_or(axis), false),
+    };
+    let Some(axis) = Axis::from_string(name) else {
+        warn!("Unknown gamepad axis {axis} in config file, ignoring.");
+        return None;
+    };
+    let parsed = value.split_once(',').and_then(|(key, threshold)| {
+        Some((
+            key.trim().parse::<u8>().ok()?,
+            threshold.trim().parse::<i16>().ok()?,
+        ))
+    });
+    match parsed {
+        Some((chip8_key, threshold)) if chip8_key <= 0xF && threshold > 0 => Some(AnalogBinding {
+            axis,
+            negative,
+            chip8_key,
+            threshold,
+        }),
+        _ => {
+            warn!(
+                "Gamepad axis {name} must map to \"key, threshold\" with a key up to 15 \
+                 and a positive threshold, ignoring {value}."
+            );
+            None
+        }
+    }
+}
+
+// Parse a comma separated list of up to four hex colors, e.g. "000000, #00FF00".
+// Missing or invalid colors keep their default.
+fn parse_palette(value: &str) -> Palette {
+    let mut palette = DEFAULT_PALETTE;
+    let colors: Vec<&str> = value.split(',').map(str::trim).collect();
+    if colors.len() > palette.len() {
+        warn!(
+            "Palette has more than {} colors, ignoring the rest.",
+            palette.len()
+        );
+    }
+    for (color, entry) in colors.iter().zip(palette.iter_mut()) {
+        match u32::from_str_radix(color.trim_start_matches('#'), 16) {
+            Ok(val) if val <= 0xFFFFFF => *entry = val,
+            _ => warn!("Unable to parse color {color} from config file, using default."),
+        }
+    }
+    palette
+}
+
+// Overwrite val with a boolean config entry if it is present and valid
+fn load_bool(config: &Ini, heading: &str, key: &str, val: &mut bool) {
+    match config.getboolcoerce(heading, key) {
+        Ok(Some(v)) => *val = v,
+        Ok(None) => {}
+        Err(e) => warn!("Unable to parse {key} from config file: [{e}]"),
+    }
+}
+
+// Overwrite val with an integer config entry if it is present and fits in a u8
+fn load_u8(config: &Ini, heading: &str, key: &str, val: &mut u8) {
+    match config.getuint(heading, key) {
+        Ok(Some(v)) => match u8::try_from(v) {
+            Ok(v) => *val = v,
+            Err(_) => warn!("{key} must be at most {}, ignoring.", u8::MAX),
+        },
+        Ok(None) => {}
+        Err(e) => warn!("Unable to parse {key} from config file: [{e}]"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "sdl")]
+    use crate::test_util::temp_path;
+
+    // Config read from INI source, without going through a file
+    fn cfg_from(source: &str) -> Cfg {
+        let mut config = Ini::new();
+        config.read(source.to_string()).expect("invalid INI");
+        let mut cfg = Cfg::default();
+        cfg.load_ini(&config);
+        cfg
+    }
+
+    // Parse a palette with all four colors
+    #[test]
+    fn parse_palette_four_colors() {
+        assert_eq!(
+            parse_palette("#996600, FFCC00, #ff6600,662200"),
+            [0x996600, 0xFFCC00, 0xFF6600, 0x662200]
+        );
+    }
+
+    // A default config maps the default keys to every CHIP-8 key
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn default_layout() {
+        let cfg = Cfg::default();
+        assert_eq!(cfg.get_u8_from_keycode(Keycode::X), Some(&0x0));
+        assert_eq!(cfg.get_u8_from_keycode(Keycode::NUM_1), Some(&0x1));
+        assert_eq!(cfg.get_u8_from_keycode(Keycode::W), Some(&0x5));
+        assert_eq!(cfg.get_u8_from_keycode(Keycode::V), Some(&0xF));
+        assert_eq!(cfg.get_u8_from_keycode(Keycode::P), None);
+        let mut keys: Vec<u8> = Cfg::default_layout().into_values().collect();
+        keys.sort();
+        assert_eq!(keys, (0x0..=0xF).collect::<Vec<u8>>());
+    }
+
+    // A missing config file, or one without usable keys, leaves the default layout in place
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn load_config_missing_file() {
+        let mut cfg = Cfg::default();
+        cfg.clear_mapping(Keycode::X);
+        cfg.quirks.strict = true;
+        cfg.load_config("no/such/dir/config.ini");
+        assert_eq!(cfg.keyboard_layout, Cfg::default_layout());
+        assert_eq!(cfg.quirks(), Quirks::default());
+        assert_eq!(cfg.render_hz(), DEFAULT_RENDER_HZ);
+
+        let cfg = cfg_from("[keyboard_layout]\nnotakey = 1\n");
+        assert_eq!(cfg.keyboard_layout, Cfg::default_layout());
+        assert_eq!(cfg.layout_warnings().len(), 1);
+    }
+
+    // The cosmac_vip switch sets exactly the COSMAC VIP quirks and clock,
+    // overriding individual entries
+    #[test]
+    fn cosmac_vip_profile() {
+        let cfg = cfg_from(
+            "[quirks]\ncosmac_vip = true\nlores_dxy0 = true\nvf_reset = false\n\
+             [timing]\nclock_hz = 1000\n",
+        );
+        assert_eq!(
+            cfg.quirks(),
+            Quirks {
+                lores_dxy0: false,
+                strict: false,
+                clip_sprites: true,
+                shift_quirk: true,
+                extended_memory: false,
+                vf_reset: true,
+                load_store_increment: true,
+                display_wait: true,
+                bit_planes: false,
+            }
+        );
+        assert_eq!(cfg.clock_hz(), COSMAC_VIP_CLOCK_HZ);
+        assert_eq!(Cfg::default().clock_hz(), DEFAULT_CLOCK_HZ);
+    }
+
+    // Mappings can be added, overridden and cleared at runtime
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn set_key_mapping() {
+        let mut cfg = Cfg::default();
+        assert_eq!(cfg.get_u8_from_keycode(Keycode::P), None);
+        assert!(cfg.set_key_mapping(Keycode::P, 0x5));
+        assert_eq!(cfg.get_u8_from_keycode(Keycode::P), Some(&0x5));
+        assert!(cfg.set_key_mapping(Keycode::P, 0xA));
+        assert_eq!(cfg.get_u8_from_keycode(Keycode::P), Some(&0xA));
+        assert!(!cfg.set_key_mapping(Keycode::P, 0x10));
+        assert_eq!(cfg.get_u8_from_keycode(Keycode::P), Some(&0xA));
+        assert_eq!(cfg.clear_mapping(Keycode::P), Some(0xA));
+        assert_eq!(cfg.get_u8_from_keycode(Keycode::P), None);
+        assert_eq!(cfg.clear_mapping(Keycode::P), None);
+    }
+
+    // Saved settings are read back by load_config, and other entries in the file are kept.
+    // Quirks changed from the cosmac_vip profile are not overridden by it on reload.
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn save_config_round_trip() {
+        let path = temp_path("save_config.ini");
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "[keyboard_layout]\nQ = 4\n[timing]\nboot_turbo_frames = 7\n\
+             [quirks]\ncosmac_vip = true\n",
+        )
+        .unwrap();
+        let mut cfg = Cfg::default();
+        cfg.load_config(path);
+        assert_eq!(cfg.quirks(), Quirks::cosmac_vip());
+        assert!(cfg.set_key_mapping(Keycode::P, 0xA));
+        cfg.clear_mapping(Keycode::Q);
+        cfg.quirks.vf_reset = false;
+        cfg.palette[1] = 0x12AB00;
+        cfg.save_config(path).expect("save_config failed");
+        let mut reloaded = Cfg::default();
+        reloaded.load_config(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(reloaded.get_u8_from_keycode(Keycode::P), Some(&0xA));
+        assert_eq!(reloaded.get_u8_from_keycode(Keycode::Q), None);
+        assert!(reloaded.quirks().shift_quirk);
+        assert_eq!(reloaded.quirks(), cfg.quirks());
+        assert_eq!(reloaded.palette(), cfg.palette());
+        assert_eq!(reloaded.boot_turbo_frames(), 7);
+    }
+
+    // Keys whose names the INI format cannot hold are saved by keycode and read back
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn save_config_special_key_names() {
+        let path = temp_path("save_special_keys.ini");
+        let path = path.to_str().unwrap();
+        let mut cfg = Cfg::default();
+        // Rebind CHIP-8 keys 1 to 4 rather than sharing them with their default keys
+        for keycode in [Keycode::NUM_1, Keycode::NUM_2, Keycode::NUM_3, Keycode::Q] {
+            cfg.clear_mapping(keycode);
+        }
+        let keys = [
+            (Keycode::SEMICOLON, 0x1),
+            (Keycode::EQUALS, 0x2),
+            (Keycode::HASH, 0x3),
+            (Keycode::LEFTBRACKET, 0x4),
+        ];
+        for (keycode, chip8_key) in keys {
+            assert!(cfg.set_key_mapping(keycode, chip8_key));
+        }
+        assert_eq!(layout_key_name(Keycode::SEMICOLON), "0x3B");
+        assert_eq!(layout_key_name(Keycode::Q), "Q");
+        cfg.save_config(path).expect("save_config failed");
+        let mut reloaded = Cfg::default();
+        reloaded.load_config(path);
+        std::fs::remove_file(path).unwrap();
+        assert!(reloaded.layout_warnings().is_empty());
+        assert_eq!(reloaded.keyboard_layout, cfg.keyboard_layout);
+    }
+
+    // Look up the keyboard keys bound to each CHIP-8 key
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn keycodes_for() {
+        let mut cfg = Cfg::default();
+        assert_eq!(cfg.keycodes_for(0x5), [Keycode::W]);
+        assert_eq!(cfg.keycodes_for(0xF), [Keycode::V]);
+        cfg.set_key_mapping(Keycode::UP, 0x5);
+        assert_eq!(cfg.keycodes_for(0x5), [Keycode::W, Keycode::UP]);
+        cfg.clear_mapping(Keycode::V);
+        assert!(cfg.keycodes_for(0xF).is_empty());
+        assert!(cfg.keycodes_for(0x10).is_empty());
+    }
+
+    // Chords are two known keys joined by '+' and mapped to a CHIP-8 key
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn parse_chords() {
+        assert_eq!(
+            parse_chord("q + w", "5"),
+            Some(Chord {
+                keys: [Keycode::Q, Keycode::W],
+                chip8_key: 0x5
+            })
+        );
+        assert_eq!(parse_chord("q", "5"), None);
+        assert_eq!(parse_chord("q + nokey", "5"), None);
+        assert_eq!(parse_chord("q + w", "16"), None);
+    }
+
+    // render_hz outside the supported range keeps the default
+    #[test]
+    fn render_hz_range() {
+        let mut config = Ini::new();
+        config.set("display", "render_hz", Some("144".to_owned()));
+        let mut cfg = Cfg::default();
+        cfg.load_display(&config);
+        assert_eq!(cfg.render_hz(), 144);
+        for invalid in ["0", "1000", "fast"] {
+            config.set("display", "render_hz", Some(invalid.to_owned()));
+            let mut cfg = Cfg::default();
+            cfg.load_display(&config);
+            assert_eq!(cfg.render_hz(), DEFAULT_RENDER_HZ);
+        }
+    }
+
+    // ipf outside the supported range keeps the default, and overrides the cosmac_vip clock
+    #[test]
+    fn ipf_range() {
+        assert_eq!(cfg_from("[timing]\nipf = 15\n").ipf(), 15);
+        for invalid in ["10001", "99999999999", "fast"] {
+            let cfg = cfg_from(&format!("[timing]\nipf = {invalid}\n"));
+            assert_eq!(cfg.ipf(), 0);
+        }
+        let cfg = cfg_from("[quirks]\ncosmac_vip = true\n[timing]\nipf = 20\n");
+        assert_eq!(cfg.clock_hz(), COSMAC_VIP_CLOCK_HZ);
+        assert_eq!(cfg.ipf(), 20);
+    }
+
+    // Build a layout map from config entries
+    #[cfg(feature = "sdl")]
+    fn layout_map(entries: &[(&str, &str)]) -> HashMap<String, Option<String>> {
+        entries
+            .iter()
+            .map(|(key, val)| (key.to_string(), Some(val.to_string())))
+            .collect()
+    }
+
+    // Keys mapped to the same CHIP-8 key, or the same key mapped twice, are reported
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn keyboard_layout_conflicts() {
+        // "space" and "Space" name the same key
+        let map = layout_map(&[
+            ("q", "4"),
+            ("w", "4"),
+            ("e", "6"),
+            ("space", "1"),
+            ("Space", "2"),
+        ]);
+        let (layout, warnings) = parse_keyboard_layout(&map);
+        assert_eq!(layout.len(), 4);
+        assert_eq!(layout[&Keycode::SPACE], 1);
+        assert_eq!(
+            warnings,
+            [
+                LayoutWarning::DuplicateKey("Space".to_string()),
+                LayoutWarning::SharedChip8Key {
+                    chip8_key: 4,
+                    keys: vec!["Q".to_string(), "W".to_string()]
+                },
+            ]
+        );
+    }
+
+    // Values which are not CHIP-8 keys, and unknown keys, are left out and reported
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn keyboard_layout_invalid() {
+        let map = layout_map(&[("q", "16"), ("w", "five"), ("nokey", "1"), ("e", "15")]);
+        let (layout, warnings) = parse_keyboard_layout(&map);
+        assert_eq!(layout.len(), 1);
+        assert_eq!(layout[&Keycode::E], 0xF);
+        assert_eq!(
+            warnings,
+            [
+                LayoutWarning::UnknownKey("nokey".to_string()),
+                LayoutWarning::OutOfRange {
+                    key: "q".to_string(),
+                    value: 16
+                },
+                LayoutWarning::InvalidValue {
+                    key: "w".to_string(),
+                    value: "five".to_string()
+                },
+            ]
+        );
+    }
+
+    // Missing and invalid colors keep their default
+    #[test]
+    fn parse_palette_defaults() {
+        let palette = parse_palette("FFFFFF");
+        assert_eq!(palette[0], 0xFFFFFF);
+        assert_eq!(palette[1..], DEFAULT_PALETTE[1..]);
+        let palette = parse_palette("101010, green, 1000000");
+        assert_eq!(palette[0], 0x101010);
+        assert_eq!(palette[1..], DEFAULT_PALETTE[1..]);
     }
 }
@@ -1,21 +1,35 @@
-use log::{error, info, warn};
+use log::{error, info, log_enabled, warn, Level};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
+use std::ops::Range;
 use std::time::Duration;
 use thiserror::Error;
 
-use crate::display::DisplayController;
+use crate::asm;
+use crate::display::{DisplayController, DEFAULT_PLANE_MASK, FRAME_SIZE};
 use crate::input::InputController;
+use crate::opcode::{Opcode, OpcodeFamily};
+use crate::quirks::Quirks;
 
-const MEMORY_SIZE: usize = 4096;
+pub const MEMORY_SIZE: usize = 4096;
+// Memory size with the extended_memory quirk, as in XO-CHIP
+pub const EXTENDED_MEMORY_SIZE: usize = 0x10000;
 const REGISTER_COUNT: usize = 16;
 // Maximum 16 nested subroutines
 const STACK_SIZE: usize = 16;
 // Memory address from where the font is stored; by convention this is 0x50
 pub const FONT_START_ADDR: usize = 0x50;
 pub const PROGRAM_ENTRY_POINT: usize = 0x200;
+// Size of the XO-CHIP audio pattern buffer in bytes; each bit is one sample
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+// XO-CHIP pitch register value at which the pattern plays at 4000 samples per second
+pub const DEFAULT_PITCH: u8 = 64;
 
-// CHIP-8 runs at approx. 600hz
+// CHIP-8 runs at approx. 600hz; the clock_hz config entry overrides this
 pub const CLOCK_SPEED: Duration = Duration::from_nanos(1_000_000_000 / 600);
 // Timers run at 60hz
 pub const TIMER_TICK: i64 = 1_000_000_000 / 60;
@@ -52,6 +66,18 @@ pub enum CpuError {
     MemoryOutOfBounds,
     #[error("attempted to access a register which does not exist")]
     InvalidRegister,
+    #[error("attempted to load font sprite for a value which is not a hexadecimal digit")]
+    InvalidDigit,
+    #[error("attempted to call machine code routine, which is not supported")]
+    UnsupportedSysCall,
+    #[error("instruction limit reached before the program jumped to itself")]
+    InstructionLimitReached,
+    #[error("attempted to write to write protected memory")]
+    WriteProtected,
+    #[error("attempted to check a key which is not on the keypad")]
+    InvalidKey,
+    #[error("font does not fit between the given address and the program entry point")]
+    InvalidFontAddress,
 }
 
 // Error handling
@@ -61,13 +87,146 @@ pub enum IOError {
     FileOpenError,
     #[error("could not read file")]
     FileReadError,
+    #[error("program does not fit in memory")]
+    ProgramTooLarge,
+}
+
+/// What happens when the program writes below the entry point, where the font and
+/// the interpreter's reserved memory live
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WriteProtect {
+    // Writes go ahead, as on real hardware
+    #[default]
+    Off,
+    // Writes are skipped with a warning, and execution continues
+    Skip,
+    // Writes fail with CpuError::WriteProtected
+    Error,
+}
+
+/// Contents of memory outside the font and the loaded program after a reset
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryFill {
+    #[default]
+    Zero,
+    // Every byte is 0xFF
+    Ones,
+    // Pseudorandom bytes from the given seed, for finding reads of uninitialized memory
+    Random(u64),
+}
+
+// Snapshot of CPU state for external tooling, serialized as JSON
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct CpuState<'a> {
+    pc: u16,
+    sp: usize,
+    i: u16,
+    dt: u8,
+    st: u8,
+    registers: [u8; REGISTER_COUNT],
+    stack: &'a [u16],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<&'a [u8]>,
+}
+
+// Instruction handler, called with the instruction being executed
+type Handler = fn(&mut Cpu, u16) -> Result<(), CpuError>;
+
+// Mask out the operands of an instruction, leaving only the bits which identify the opcode
+pub(crate) fn opcode_pattern(inst: u16) -> u16 {
+    match inst & 0xF000 {
+        0x0000 => match inst {
+            0x00E0 | 0x00EE | 0x00FD | 0x00FE | 0x00FF => inst,
+            _ => 0x0000,
+        },
+        0x5000 | 0x8000 | 0x9000 => inst & 0xF00F,
+        0xE000 | 0xF000 => inst & 0xF0FF,
+        _ => inst & 0xF000,
+    }
+}
+
+// Registers Vx through Vy of a 5xyn instruction, from x to y, which may count down
+fn register_range(inst: u16) -> Vec<usize> {
+    let x = ((inst & 0x0F00) >> 8) as usize;
+    let y = ((inst & 0x00F0) >> 4) as usize;
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
+    }
+}
+
+/// Read the binary instructions of a program from the given file
+pub fn read_program(filename: &str) -> Result<Vec<u8>, IOError> {
+    let mut buffer: Vec<u8> = vec![];
+    let mut file = File::open(filename);
+    match file {
+        Ok(f) => file = Ok(f),
+        _ => {
+            return Err(IOError::FileOpenError);
+        }
+    }
+
+    match file.unwrap().read_to_end(&mut buffer) {
+        Ok(b) => {
+            info!("Read {b} bytes from {filename}.");
+        }
+        Err(_) => {
+            return Err(IOError::FileReadError);
+        }
+    };
+    Ok(buffer)
+}
+
+// Changes made by one instruction, kept so that it can be undone.
+// Small state is kept whole; registers, memory and the frame buffer only where they changed.
+struct UndoEntry {
+    pc: u16,
+    i: u16,
+    dt: u8,
+    st: u8,
+    halted: bool,
+    blocking: bool,
+    reg_to_write: Option<u8>,
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
+    hires: bool,
+    plane_mask: u8,
+    // Previous values of changed registers, memory bytes and frame buffer bytes, by index
+    registers: Vec<(usize, u8)>,
+    memory: Vec<(usize, u8)>,
+    frame: Vec<(usize, u8)>,
+    // Previous stack, if the instruction pushed or popped
+    stack: Option<Vec<u16>>,
+}
+
+// State before an instruction, compared with the state after it to build an UndoEntry
+struct UndoCapture {
+    entry: UndoEntry,
+    reg: [u8; REGISTER_COUNT],
+    stk: Vec<u16>,
+    // Instructions only write memory at I, and at most 16 bytes of it
+    mem_start: usize,
+    mem: Vec<u8>,
+    frame: [u8; FRAME_SIZE],
+}
+
+// Previous values of the bytes which differ between before and after, with their
+// indices offset by start
+fn changed_bytes(before: &[u8], after: &[u8], start: usize) -> Vec<(usize, u8)> {
+    before
+        .iter()
+        .zip(after)
+        .enumerate()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(n, (old, _))| (start + n, *old))
+        .collect()
 }
 
 pub struct Cpu {
     // Program counter
     pc: u16,
-    // Stack pointer
-    sp: i16,
     // Delay timer
     dt: u8,
     dt_delta: i64,
@@ -78,82 +237,331 @@ pub struct Cpu {
     i: u16,
     // General purpose registers
     reg: [u8; REGISTER_COUNT],
-    // Memory space; maximum 4KB
-    mem: [u8; MEMORY_SIZE],
+    // Memory space; 4KB, or 64KB with the extended_memory quirk
+    mem: Vec<u8>,
+    // Address the font is loaded at, FONT_START_ADDR unless relocated
+    font_addr: usize,
     // Stack; holds maximum of 16 addresses
     stk: Vec<u16>,
     pub dct: DisplayController,
     pub ict: InputController,
     paused: bool,
+    // The program has exited with 00FD and no further instructions run
+    halted: bool,
+    // A sprite was drawn with the display_wait quirk, so execution waits for the next frame
+    vblank_wait: bool,
     blocking: bool,
-    reg_to_write: Option<u8>
+    reg_to_write: Option<u8>,
+    quirks: Quirks,
+    // Minimum value for a nonzero sound timer set by the program; 0 disables the clamp
+    min_beep_frames: u8,
+    // Handling of writes below the program entry point
+    write_protect: WriteProtect,
+    // Contents of memory after a reset
+    memory_fill: MemoryFill,
+    // Source of RND values, which can be seeded for repeatable runs
+    rng: StdRng,
+    // XO-CHIP audio pattern, loaded with F002; None until a program loads one,
+    // in which case the sound timer plays the classic beep
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    // XO-CHIP playback rate of the audio pattern, set with Fx3A
+    pitch: u8,
+    // Times each opcode_pattern has been executed; only kept while profiling
+    opcode_histogram: Option<HashMap<u16, u64>>,
+    // Families of instructions written to the trace log; None logs every instruction
+    trace_filter: Option<HashSet<OpcodeFamily>>,
+    // Addresses whose writes by the program are logged, for debugging
+    memory_watches: HashSet<u16>,
+    // Changes made by the most recent instructions, newest last; only kept while undo is enabled
+    undo_journal: Option<VecDeque<UndoEntry>>,
+    // Maximum number of instructions kept in the undo journal
+    undo_depth: usize,
+    // Messages logged through log, with their level, while a test is capturing them
+    #[cfg(test)]
+    log_capture: Option<Vec<(Level, String)>>,
 }
 
 impl Default for Cpu {
     fn default() -> Self {
         let mut ret = Self {
             pc: 0,
-            sp: 0,
             dt: 0,
             dt_delta: TIMER_TICK,
             st: 0,
             st_delta: TIMER_TICK,
             i: 0,
             reg: [0; REGISTER_COUNT],
-            mem: [0; MEMORY_SIZE],
+            mem: vec![0; MEMORY_SIZE],
+            font_addr: FONT_START_ADDR,
             stk: vec![],
             dct: DisplayController::default(),
             ict: InputController::default(),
             paused: false,
+            halted: false,
+            vblank_wait: false,
             blocking: false,
-            reg_to_write: None, 
+            reg_to_write: None,
+            quirks: Quirks::default(),
+            min_beep_frames: 0,
+            write_protect: WriteProtect::Off,
+            memory_fill: MemoryFill::Zero,
+            rng: StdRng::from_entropy(),
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            opcode_histogram: None,
+            trace_filter: None,
+            memory_watches: HashSet::new(),
+            undo_journal: None,
+            undo_depth: 0,
+            #[cfg(test)]
+            log_capture: None,
         };
-        &ret.load_font();
+        ret.load_font();
         ret
     }
 }
 
+// Registers, timers and stack pointer on one line, leaving out memory and peripherals
+impl fmt::Debug for Cpu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Cpu {{ pc: {:#05X}, i: {:#05X}, sp: {}, dt: {}, st: {}, v: [",
+            self.pc,
+            self.i,
+            self.sp(),
+            self.dt,
+            self.st
+        )?;
+        for (x, val) in self.reg.iter().enumerate() {
+            if x > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{val:02X}")?;
+        }
+        write!(f, "] }}")
+    }
+}
+
 impl Cpu {
     // Map font to memory
     fn load_font(&mut self) {
-        for i in FONT_START_ADDR..FONT_START_ADDR + FONT.len() {
-            self.mem[i] = FONT[i - FONT_START_ADDR];
+        let range = self.font_range();
+        self.mem[range].copy_from_slice(&FONT);
+    }
+
+    // Memory occupied by the font
+    fn font_range(&self) -> Range<usize> {
+        self.font_addr..self.font_addr + FONT.len()
+    }
+
+    /// Move the font to the given address, which LD F, Vx then points I into.
+    /// The font must fit below the program entry point.
+    /// The old copy is cleared, and the font stays at the new address across resets.
+    pub fn set_font_address(&mut self, addr: usize) -> Result<(), CpuError> {
+        // Compared this way round so that huge addresses cannot overflow
+        if addr > PROGRAM_ENTRY_POINT - FONT.len() {
+            return Err(CpuError::InvalidFontAddress);
         }
+        let old = self.font_range();
+        self.mem[old].fill(0);
+        self.font_addr = addr;
+        self.load_font();
+        Ok(())
     }
 
-    /// Takes a filename string and attempts to load the binary instructions
-    /// to the usual entry point, 0x200
-    pub fn load_program(&mut self, filename: &str) -> Result<(), IOError> {
-        let mut buffer: [u8; MEMORY_SIZE - PROGRAM_ENTRY_POINT] =
-            [0; MEMORY_SIZE - PROGRAM_ENTRY_POINT];
-        let mut file = File::open(filename);
-        match file {
-            Ok(f) => file = Ok(f),
-            _ => {
-                return Err(IOError::FileOpenError);
-            }
+    /// Copies the given binary instructions to the usual entry point, 0x200,
+    /// and points the program counter at it
+    pub fn load_program_from_slice(&mut self, program: &[u8]) -> Result<(), IOError> {
+        if program.len() > self.memory_size() - PROGRAM_ENTRY_POINT {
+            return Err(IOError::ProgramTooLarge);
         }
+        self.mem[PROGRAM_ENTRY_POINT..PROGRAM_ENTRY_POINT + program.len()].copy_from_slice(program);
+        self.pc = PROGRAM_ENTRY_POINT as u16;
+        Ok(())
+    }
 
-        match file.unwrap().read(&mut buffer) {
-            Ok(b) => {
-                info!("Read {b} bytes from {filename}.");
-            }
-            Err(_) => {
-                return Err(IOError::FileReadError);
-            }
-        };
-        self.mem[PROGRAM_ENTRY_POINT..MEMORY_SIZE].copy_from_slice(&buffer);
+    /// Instruction at the program counter, i.e. the next one to be executed
+    pub fn current_instruction(&self) -> u16 {
+        // Pack two contiguous 8-bit segments in memory into 16-bit instruction
+        let pc = self.pc as usize;
+        let high = self.mem.get(pc).copied().unwrap_or_default() as u16;
+        let low = self.mem.get(pc + 1).copied().unwrap_or_default() as u16;
+        (high << 8) | low
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn index(&self) -> u16 {
+        self.i
+    }
+
+    /// Remaining frames on the delay timer
+    pub fn delay_timer(&self) -> u8 {
+        self.dt
+    }
+
+    /// Remaining frames on the sound timer; the beeper sounds while this is nonzero
+    pub fn sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    /// Value of general purpose register Vx
+    #[cfg(test)]
+    pub fn register(&self, x: usize) -> Result<u8, CpuError> {
+        self.reg.get(x).copied().ok_or(CpuError::InvalidRegister)
+    }
+
+    /// Copy of all general purpose registers, V0 first
+    pub fn registers(&self) -> [u8; REGISTER_COUNT] {
+        self.reg
+    }
+
+    /// Set general purpose register Vx
+    #[cfg(feature = "testing")]
+    pub fn set_register(&mut self, x: usize, val: u8) -> Result<(), CpuError> {
+        let reg = self.reg.get_mut(x).ok_or(CpuError::InvalidRegister)?;
+        *reg = val;
         Ok(())
     }
 
+    /// Set the byte of memory at addr
+    pub fn set_memory(&mut self, addr: usize, val: u8) -> Result<(), CpuError> {
+        let byte = self.mem.get_mut(addr).ok_or(CpuError::MemoryOutOfBounds)?;
+        *byte = val;
+        Ok(())
+    }
+
+    #[cfg(feature = "testing")]
+    pub fn set_index(&mut self, val: u16) {
+        self.i = val;
+    }
+
+    /// Set all general purpose registers and the index register at once,
+    /// e.g. to reproduce a reported bug which only shows with particular register values.
+    /// Call after loading a program, as loading resets the registers.
+    pub fn preset(&mut self, registers: [u8; REGISTER_COUNT], i: u16) {
+        self.reg = registers;
+        self.i = i;
+    }
+
+    /// XO-CHIP audio pattern, if the program has loaded one
+    pub fn audio_pattern(&self) -> Option<[u8; AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern
+    }
+
+    /// XO-CHIP pitch register, which sets the playback rate of the audio pattern
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Changing extended_memory resizes memory, so quirks should be set before loading a program
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.dct.set_clip_sprites(quirks.clip_sprites);
+        if !quirks.bit_planes {
+            self.dct.set_plane_mask(DEFAULT_PLANE_MASK);
+        }
+        let size = if quirks.extended_memory {
+            EXTENDED_MEMORY_SIZE
+        } else {
+            MEMORY_SIZE
+        };
+        self.mem.resize(size, 0);
+    }
+
+    /// Size of memory in bytes, which depends on the extended_memory quirk
+    pub fn memory_size(&self) -> usize {
+        self.mem.len()
+    }
+
+    pub fn set_min_beep_frames(&mut self, frames: u8) {
+        self.min_beep_frames = frames;
+    }
+
+    pub fn set_write_protect(&mut self, write_protect: WriteProtect) {
+        self.write_protect = write_protect;
+    }
+
+    /// Set what memory is filled with on the next reset
+    pub fn set_memory_fill(&mut self, memory_fill: MemoryFill) {
+        self.memory_fill = memory_fill;
+    }
+
+    /// Make RND produce the same sequence of values on every run with the same seed
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Return to the power-on state, keeping settings such as quirks.
+    /// Registers, timers, the stack and the screen are cleared, and memory is filled
+    /// according to the memory fill setting before the font is loaded again.
+    pub fn reset(&mut self) {
+        self.pc = 0;
+        self.dt = 0;
+        self.dt_delta = TIMER_TICK;
+        self.st = 0;
+        self.st_delta = TIMER_TICK;
+        self.i = 0;
+        self.reg = [0; REGISTER_COUNT];
+        self.stk.clear();
+        self.halted = false;
+        self.vblank_wait = false;
+        self.blocking = false;
+        self.reg_to_write = None;
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        if let Some(journal) = &mut self.undo_journal {
+            journal.clear();
+        }
+        self.dct.set_hires(false);
+        self.dct.set_plane_mask(DEFAULT_PLANE_MASK);
+        match self.memory_fill {
+            MemoryFill::Zero => self.mem.fill(0),
+            MemoryFill::Ones => self.mem.fill(0xFF),
+            MemoryFill::Random(seed) => StdRng::seed_from_u64(seed).fill(&mut self.mem[..]),
+        }
+        self.load_font();
+    }
+
     pub fn pause(&mut self) {
         self.paused = true;
     }
 
+    // Resume execution. Time elapsed towards the next timer tick before pausing is
+    // discarded, so that timers don't jump as soon as execution resumes.
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.dt_delta = TIMER_TICK;
+        self.st_delta = TIMER_TICK;
+    }
+
     pub fn paused(&self) -> bool {
         self.paused
     }
 
+    /// Whether the program has exited with 00FD, unlike a pause, which can be resumed
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Whether a draw under the display_wait quirk is waiting for the next frame.
+    /// Instructions can still be executed; it is up to the caller to hold off until vblank.
+    pub fn waiting_for_vblank(&self) -> bool {
+        self.vblank_wait
+    }
+
+    /// Signal the start of a new 60Hz frame, ending any wait for it
+    pub fn vblank(&mut self) {
+        self.vblank_wait = false;
+    }
+
     pub fn is_blocking(&self) -> bool {
         self.blocking
     }
@@ -169,9 +577,27 @@ impl Cpu {
         self.blocking = false;
     }
 
+    /// Returns a JSON snapshot of registers, program counter, index, timers and stack.
+    /// Memory is only included if include_memory is set, as it is by far the largest part.
+    #[cfg(feature = "serde")]
+    pub fn state_json(&self, include_memory: bool) -> String {
+        let state = CpuState {
+            pc: self.pc,
+            sp: self.sp(),
+            i: self.i,
+            dt: self.dt,
+            st: self.st,
+            registers: self.reg,
+            stack: &self.stk,
+            memory: include_memory.then_some(&self.mem[..]),
+        };
+        serde_json::to_string(&state).expect("CPU state is always serializable")
+    }
+
     pub fn timer_tick(&mut self, delta: Duration) {
-        self.dt_delta -= delta.as_nanos() as i64;
-        self.st_delta -= delta.as_nanos() as i64;
+        let nanos = i64::try_from(delta.as_nanos()).unwrap_or(i64::MAX);
+        self.dt_delta = self.dt_delta.saturating_sub(nanos);
+        self.st_delta = self.st_delta.saturating_sub(nanos);
         if self.dt_delta <= 0 && self.dt > 0 {
             self.dt_delta = TIMER_TICK;
             self.dt -= 1;
@@ -182,96 +608,280 @@ impl Cpu {
         }
     }
 
-    /// Run the current instruction pointed to by PC
-    pub fn exec_routine(&mut self) -> Result<(), CpuError> {
-        let result: Result<(), CpuError>;
-        // Pack two contiguous 8-bit segments in memory into 16-bit instruction
-        let mut inst: u16 = self.mem[self.pc as usize] as u16;
-        inst <<= 8;
-        inst |= self.mem[self.pc as usize + 1] as u16;
-        match inst {
-            0x00E0 => result = self.cls(),
-            0x00EE => result = self.ret(),
-            0x1000..0x1FFF => result = self.jp(inst),
-            0x2000..0x2FFF => result = self.call(inst),
-            0x3000..0x3FFF => result = self.sexb(inst),
-            0x4000..0x4FFF => result = self.snexb(inst),
-            0x5000..0x5FFF => {
-                if inst & 0x000F != 0 {
-                    return Err(CpuError::UnknownOpcode);
-                };
-                result = self.sexy(inst);
+    /// Start or stop counting executed opcodes.
+    /// Stopping discards the counts collected so far.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.opcode_histogram = enabled.then(HashMap::new);
+    }
+
+    /// Number of times each opcode has been executed since profiling was enabled,
+    /// keyed by the opcode with its operands masked out (e.g. 0x8004 for 8xy4).
+    pub fn coverage_report(&self) -> Option<&HashMap<u16, u64>> {
+        self.opcode_histogram.as_ref()
+    }
+
+    /// Only write instructions of the given families to the trace log, or every instruction
+    /// with None. Instructions are traced at the trace log level.
+    pub fn set_trace_filter(&mut self, filter: Option<HashSet<OpcodeFamily>>) {
+        self.trace_filter = filter;
+    }
+
+    /// Log every write by the program to the given address, with its old and new value
+    /// and the address of the instruction which wrote it
+    pub fn watch_memory(&mut self, addr: u16) {
+        self.memory_watches.insert(addr);
+    }
+
+    /// Stop logging writes to an address watched with watch_memory
+    pub fn unwatch_memory(&mut self, addr: u16) {
+        self.memory_watches.remove(&addr);
+    }
+
+    // Whether an instruction passes the trace filter
+    fn traced(&self, inst: u16) -> bool {
+        match &self.trace_filter {
+            Some(filter) => Opcode::decode(inst).is_ok_and(|op| filter.contains(&op.family())),
+            None => true,
+        }
+    }
+
+    /// Keep the changes made by the last depth instructions, so that they can be undone
+    /// one at a time with undo_instruction. A depth of 0 stops recording and discards the journal.
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.undo_depth = depth;
+        match &mut self.undo_journal {
+            _ if depth == 0 => self.undo_journal = None,
+            Some(journal) => {
+                let excess = journal.len().saturating_sub(depth);
+                journal.drain(..excess);
             }
-            0x6000..0x6FFF => result = self.ldxb(inst),
-            0x7000..0x7FFF => result = self.addxb(inst),
-            0x8000..0x8FFF => match inst & 0x000F {
-                0x0 => result = self.ldxy(inst),
-                0x1 => result = self.orxy(inst),
-                0x2 => result = self.andxy(inst),
-                0x3 => result = self.xorxy(inst),
-                0x4 => result = self.addxy(inst),
-                0x5 => result = self.subxy(inst),
-                0x6 => result = self.shrx(inst),
-                0x7 => result = self.subnxy(inst),
-                0xE => result = self.shlx(inst),
-                _ => return Err(CpuError::UnknownOpcode),
+            None => self.undo_journal = Some(VecDeque::with_capacity(depth)),
+        }
+    }
+
+    /// Revert the most recently executed instruction still in the undo journal.
+    /// Registers, memory, the stack, timers and the screen are restored; the RNG and
+    /// input state are not. Returns false if there is nothing left to undo.
+    pub fn undo_instruction(&mut self) -> bool {
+        let Some(entry) = self.undo_journal.as_mut().and_then(VecDeque::pop_back) else {
+            return false;
+        };
+        self.pc = entry.pc;
+        self.i = entry.i;
+        self.dt = entry.dt;
+        self.st = entry.st;
+        self.halted = entry.halted;
+        self.blocking = entry.blocking;
+        self.reg_to_write = entry.reg_to_write;
+        self.audio_pattern = entry.audio_pattern;
+        self.pitch = entry.pitch;
+        for (x, val) in entry.registers {
+            self.reg[x] = val;
+        }
+        for (addr, val) in entry.memory {
+            self.mem[addr] = val;
+        }
+        if let Some(stack) = entry.stack {
+            self.stk = stack;
+        }
+        // Changing resolution clears the screen, so put the frame back afterwards
+        let mut frame = self.dct.snapshot();
+        if self.dct.hires() != entry.hires {
+            self.dct.set_hires(entry.hires);
+        }
+        for (idx, val) in entry.frame {
+            frame[idx] = val;
+        }
+        self.dct.restore(frame);
+        self.dct.set_plane_mask(entry.plane_mask);
+        true
+    }
+
+    // Copy the state an instruction may change, for comparing after it has run
+    fn undo_capture(&self) -> UndoCapture {
+        let mem_start = (self.i as usize).min(self.memory_size());
+        let mem_end = (mem_start + REGISTER_COUNT).min(self.memory_size());
+        UndoCapture {
+            entry: UndoEntry {
+                pc: self.pc,
+                i: self.i,
+                dt: self.dt,
+                st: self.st,
+                halted: self.halted,
+                blocking: self.blocking,
+                reg_to_write: self.reg_to_write,
+                audio_pattern: self.audio_pattern,
+                pitch: self.pitch,
+                hires: self.dct.hires(),
+                plane_mask: self.dct.plane_mask(),
+                registers: vec![],
+                memory: vec![],
+                frame: vec![],
+                stack: None,
             },
-            0x9000..0x9FFF => {
-                if inst & 0x000F != 0 {
-                    return Err(CpuError::UnknownOpcode);
-                };
-                result = self.snexy(inst);
+            reg: self.reg,
+            stk: self.stk.clone(),
+            mem_start,
+            mem: self.mem[mem_start..mem_end].to_vec(),
+            frame: self.dct.snapshot(),
+        }
+    }
+
+    // Add what changed since capture to the undo journal, dropping the oldest entry when full
+    fn undo_record(&mut self, capture: UndoCapture) {
+        let mut entry = capture.entry;
+        entry.registers = changed_bytes(&capture.reg, &self.reg, 0);
+        let mem_end = capture.mem_start + capture.mem.len();
+        entry.memory = changed_bytes(
+            &capture.mem,
+            &self.mem[capture.mem_start..mem_end],
+            capture.mem_start,
+        );
+        entry.frame = changed_bytes(&capture.frame, &self.dct.snapshot(), 0);
+        if capture.stk != self.stk {
+            entry.stack = Some(capture.stk);
+        }
+        let depth = self.undo_depth;
+        if let Some(journal) = &mut self.undo_journal {
+            if journal.len() == depth {
+                journal.pop_front();
             }
-            0xA000..0xAFFF => result = self.ldi(inst),
-            0xB000..0xBFFF => result = self.jp0(inst),
-            0xC000..0xCFFF => result = self.rndx(inst),
-            0xD000..0xDFFF => result = self.drwxy(inst),
-            0xE000..0xEFFF => match inst & 0x00FF {
-                0x009E => result = self.skpx(inst),
-                0x00A1 => result = self.sknpx(inst),
-                _ => return Err(CpuError::UnknownOpcode),
-            },
-            0xF000..0xFFFF => match inst & 0x00FF {
-                0x0007 => result = self.ldxdt(inst),
-                0x000A => result = self.ldxk(inst),
-                0x0015 => result = self.lddtx(inst),
-                0x0018 => result = self.ldstx(inst),
-                0x001E => result = self.addix(inst),
-                0x0029 => result = self.ldfx(inst),
-                0x0033 => result = self.ldbx(inst),
-                0x0055 => result = self.ldiax(inst),
-                0x0065 => result = self.ldxia(inst), 
-                _ => return Err(CpuError::UnknownOpcode),
-            },
+            journal.push_back(entry);
+        }
+    }
 
-            ..=u16::MAX => return Err(CpuError::UnknownOpcode),
+    /// Run the current instruction pointed to by PC
+    pub fn exec_routine(&mut self) -> Result<(), CpuError> {
+        let inst = self.current_instruction();
+        if let Some(histogram) = &mut self.opcode_histogram {
+            *histogram.entry(opcode_pattern(inst)).or_insert(0) += 1;
+        }
+        if self.log_enabled(Level::Trace) && self.traced(inst) {
+            let message = format!("{:#05X}: {}", self.pc, asm::disassemble(inst));
+            self.log(Level::Trace, message);
+        }
+        let capture = self.undo_journal.is_some().then(|| self.undo_capture());
+        let result = match Self::decode(inst) {
+            Some(handler) => handler(self, inst),
+            None => Err(CpuError::UnknownOpcode),
+        };
+        // Failed instructions are not recorded, as they are not meant to have run
+        if let (Some(capture), Ok(())) = (capture, &result) {
+            self.undo_record(capture);
         }
         result
     }
 
+    /// Whether an instruction maps to a handler, without executing it.
+    /// SYS is implemented as a no-op outside of strict mode, so it counts as implemented.
+    pub fn is_implemented(opcode: u16) -> bool {
+        Self::decode(opcode).is_some()
+    }
+
+    // Find the handler for an instruction
+    fn decode(inst: u16) -> Option<Handler> {
+        let handler: Handler = match Opcode::decode(inst).ok()? {
+            Opcode::Cls => |c, _| c.cls(),
+            Opcode::Ret => |c, _| c.ret(),
+            Opcode::Exit => |c, _| c.exit(),
+            Opcode::Low => |c, _| c.low(),
+            Opcode::High => |c, _| c.high(),
+            Opcode::Sys { .. } => Self::sys,
+            Opcode::Jp { .. } => Self::jp,
+            Opcode::Call { .. } => Self::call,
+            Opcode::SeVxByte { .. } => Self::sexb,
+            Opcode::SneVxByte { .. } => Self::snexb,
+            Opcode::SeVxVy { .. } => Self::sexy,
+            Opcode::SaveVxVy { .. } => Self::savexy,
+            Opcode::LoadVxVy { .. } => Self::loadxy,
+            Opcode::LdVxByte { .. } => Self::ldxb,
+            Opcode::AddVxByte { .. } => Self::addxb,
+            Opcode::LdVxVy { .. } => Self::ldxy,
+            Opcode::Or { .. } => Self::orxy,
+            Opcode::And { .. } => Self::andxy,
+            Opcode::Xor { .. } => Self::xorxy,
+            Opcode::AddVxVy { .. } => Self::addxy,
+            Opcode::Sub { .. } => Self::subxy,
+            Opcode::Shr { .. } => Self::shrx,
+            Opcode::Subn { .. } => Self::subnxy,
+            Opcode::Shl { .. } => Self::shlx,
+            Opcode::SneVxVy { .. } => Self::snexy,
+            Opcode::LdI { .. } => Self::ldi,
+            Opcode::JpV0 { .. } => Self::jp0,
+            Opcode::Rnd { .. } => Self::rndx,
+            Opcode::Drw { .. } => Self::drwxy,
+            Opcode::Skp { .. } => Self::skpx,
+            Opcode::Sknp { .. } => Self::sknpx,
+            Opcode::Plane { .. } => Self::plane,
+            Opcode::LdILong => |c, _| c.ldilong(),
+            Opcode::Audio => |c, _| c.audio(),
+            Opcode::LdVxDt { .. } => Self::ldxdt,
+            Opcode::LdVxK { .. } => Self::ldxk,
+            Opcode::LdDtVx { .. } => Self::lddtx,
+            Opcode::LdStVx { .. } => Self::ldstx,
+            Opcode::AddIVx { .. } => Self::addix,
+            Opcode::LdFVx { .. } => Self::ldfx,
+            Opcode::LdBVx { .. } => Self::ldbx,
+            Opcode::Pitch { .. } => Self::pitchx,
+            Opcode::LdIVx { .. } => Self::ldiax,
+            Opcode::LdVxI { .. } => Self::ldxia,
+        };
+        Some(handler)
+    }
+
+    /// Move past the current instruction without executing it, e.g. to recover from an error
+    pub fn skip_instruction(&mut self) -> Result<(), CpuError> {
+        self.increment_pc()
+    }
+
     // Advance program counter by 16 bits
-    // Constraints: PC must not be greater 4096, as this exceeds the memory limit of 4KB.
+    // Constraints: PC must stay within memory: 4KB, or 64KB with the extended_memory quirk.
+    // The program counter is left unchanged if it would go out of bounds.
     fn increment_pc(&mut self) -> Result<(), CpuError> {
-        self.pc += 2;
-        if self.pc >= MEMORY_SIZE as u16 {
-            return Err(CpuError::MemoryOutOfBounds);
+        match self.pc.checked_add(2) {
+            Some(pc) if (pc as usize) < self.memory_size() => {
+                self.pc = pc;
+                Ok(())
+            }
+            _ => Err(CpuError::MemoryOutOfBounds),
         }
-        Ok(())
     }
 
-    // Increment stack pointer by 1
-    // Constraints: SP must not exceed 15, because only 16 nested subroutines are allowed.
-    fn increment_sp(&mut self) -> Result<(), CpuError> {
-        self.sp += 1;
-        if self.sp >= STACK_SIZE as i16 {
+    // Advance program counter past the next instruction, for the skip instructions.
+    // With extended memory that can be XO-CHIP's F000 nnnn, which is 4 bytes long.
+    fn skip_next(&mut self) -> Result<(), CpuError> {
+        if self.quirks.extended_memory && self.word_at(self.pc as usize + 2) == Some(0xF000) {
+            self.increment_pc()?;
+        }
+        self.increment_pc()
+    }
+
+    // Big-endian 16-bit word in memory, if it is within memory
+    fn word_at(&self, addr: usize) -> Option<u16> {
+        let bytes = self.mem.get(addr..addr + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    // Stack pointer, which is the number of return addresses on the stack.
+    // It is derived from the stack, so that the two can never disagree.
+    fn sp(&self) -> usize {
+        self.stk.len()
+    }
+
+    // Push a return address onto the stack
+    // Constraints: SP must not exceed 16, because only 16 nested subroutines are allowed.
+    // The stack is left unchanged on overflow.
+    fn push_stack(&mut self, addr: u16) -> Result<(), CpuError> {
+        if self.sp() >= STACK_SIZE {
             return Err(CpuError::StackOverflow);
         }
+        self.stk.push(addr);
         Ok(())
     }
 
     /// Opcode 0x00E0 - CLS
     ///
-    /// Clears the screen.
+    /// Clears the screen, or with XO-CHIP only the selected planes.
     fn cls(&mut self) -> Result<(), CpuError> {
         self.dct.clear_screen();
         self.increment_pc()
@@ -285,13 +895,48 @@ impl Cpu {
         match self.stk.pop() {
             Some(val) => {
                 self.pc = val;
-                self.sp -= 1;
+                Ok(())
             }
-            None => return Err(CpuError::EmptyStack),
+            None => Err(CpuError::EmptyStack),
         }
+    }
+
+    /// Opcode 0x00FD - EXIT (SCHIP)
+    ///
+    /// Exit the interpreter. The program counter stays at this instruction.
+    fn exit(&mut self) -> Result<(), CpuError> {
+        info!("Program exited at {:#05X}.", self.pc);
+        self.halted = true;
         Ok(())
     }
 
+    /// Opcode 0x00FE - LOW (SCHIP)
+    ///
+    /// Disable high resolution graphics mode.
+    fn low(&mut self) -> Result<(), CpuError> {
+        self.dct.set_hires(false);
+        self.increment_pc()
+    }
+
+    /// Opcode 0x00FF - HIGH (SCHIP)
+    ///
+    /// Enable high resolution graphics mode.
+    fn high(&mut self) -> Result<(), CpuError> {
+        self.dct.set_hires(true);
+        self.increment_pc()
+    }
+
+    /// Opcode 0x0nnn - SYS addr
+    ///
+    /// Jump to a machine code routine at nnn.
+    /// This is ignored by modern interpreters, unless in strict mode, where it is an error.
+    fn sys(&mut self, _inst: u16) -> Result<(), CpuError> {
+        if self.quirks.strict {
+            return Err(CpuError::UnsupportedSysCall);
+        }
+        self.increment_pc()
+    }
+
     /// Opcode 0x1nnn - JP addr
     ///
     /// The interpreter sets the program counter to nnn.
@@ -304,12 +949,12 @@ impl Cpu {
     /// Opcode 0x2nnn - CALL addr
     ///
     /// Call subroutine at nnn.
-    /// The interpreter increments the stack pointer, then puts the current PC on the top of the stack.
-    /// PC is then set to nnn.
+    /// The interpreter increments the stack pointer, then puts the address of the next
+    /// instruction on the top of the stack, for RET to return to. PC is then set to nnn.
     fn call(&mut self, inst: u16) -> Result<(), CpuError> {
         let addr = inst & 0x0FFF;
-        self.increment_sp()?;
-        self.stk.push(self.pc);
+        let return_addr = self.pc.checked_add(2).ok_or(CpuError::MemoryOutOfBounds)?;
+        self.push_stack(return_addr)?;
         self.pc = addr;
         Ok(())
     }
@@ -323,7 +968,7 @@ impl Cpu {
         let x = ((inst & 0x0F00) >> 8) as usize;
         let kk = inst as u8;
         if self.reg[x] == kk {
-            self.increment_pc()?;
+            self.skip_next()?;
         }
         self.increment_pc()?;
         Ok(())
@@ -338,7 +983,7 @@ impl Cpu {
         let x = (inst & 0x0F00) >> 8;
         let kk = inst & 0x00FF;
         if self.reg[x as usize] != kk as u8 {
-            self.increment_pc()?;
+            self.skip_next()?;
         }
         self.increment_pc()?;
         Ok(())
@@ -353,7 +998,38 @@ impl Cpu {
         let x = ((inst & 0x0F00) >> 8) as usize;
         let y = ((inst & 0x00F0) >> 4) as usize;
         if self.reg[x] == self.reg[y] {
-            self.increment_pc()?;
+            self.skip_next()?;
+        }
+        self.increment_pc()?;
+        Ok(())
+    }
+
+    /// Opcode 0x5xy2 - SAVE Vx, Vy (XO-CHIP)
+    ///
+    /// Store registers Vx through Vy in memory starting at location I.
+    /// If x is greater than y, the registers are stored in descending order. I is not changed.
+    fn savexy(&mut self, inst: u16) -> Result<(), CpuError> {
+        let registers = register_range(inst);
+        let range = self.mem_range_at_i(registers.len())?;
+        if !self.write_allowed(&range)? {
+            return self.increment_pc();
+        }
+        for (addr, x) in range.zip(registers) {
+            self.write_memory(addr, self.reg[x]);
+        }
+        self.increment_pc()?;
+        Ok(())
+    }
+
+    /// Opcode 0x5xy3 - LOAD Vx, Vy (XO-CHIP)
+    ///
+    /// Read registers Vx through Vy from memory starting at location I.
+    /// If x is greater than y, the registers are read in descending order. I is not changed.
+    fn loadxy(&mut self, inst: u16) -> Result<(), CpuError> {
+        let registers = register_range(inst);
+        let range = self.mem_range_at_i(registers.len())?;
+        for (addr, x) in range.zip(registers) {
+            self.reg[x] = self.mem[addr];
         }
         self.increment_pc()?;
         Ok(())
@@ -378,7 +1054,7 @@ impl Cpu {
     fn addxb(&mut self, inst: u16) -> Result<(), CpuError> {
         let x = ((inst & 0x0F00) >> 8) as usize;
         let kk = inst as u8;
-        self.reg[x] += kk;
+        self.reg[x] = self.reg[x].wrapping_add(kk);
         self.increment_pc()?;
         Ok(())
     }
@@ -395,6 +1071,13 @@ impl Cpu {
         Ok(())
     }
 
+    // With the vf_reset quirk, the logic instructions leave VF at 0
+    fn logic_vf_reset(&mut self) {
+        if self.quirks.vf_reset {
+            self.reg[0xF] = 0;
+        }
+    }
+
     /// Opcode 0x8xy1 - OR Vx, Vy
     ///
     /// Set Vx = Vx OR Vy.
@@ -403,6 +1086,7 @@ impl Cpu {
         let x = ((inst & 0x0F00) >> 8) as usize;
         let y = ((inst & 0x00F0) >> 4) as usize;
         self.reg[x] |= self.reg[y];
+        self.logic_vf_reset();
         self.increment_pc()?;
         Ok(())
     }
@@ -415,6 +1099,7 @@ impl Cpu {
         let x = ((inst & 0x0F00) >> 8) as usize;
         let y = ((inst & 0x00F0) >> 4) as usize;
         self.reg[x] &= self.reg[y];
+        self.logic_vf_reset();
         self.increment_pc()?;
         Ok(())
     }
@@ -427,6 +1112,7 @@ impl Cpu {
         let x = ((inst & 0x0F00) >> 8) as usize;
         let y = ((inst & 0x00F0) >> 4) as usize;
         self.reg[x] ^= self.reg[y];
+        self.logic_vf_reset();
         self.increment_pc()?;
         Ok(())
     }
@@ -440,13 +1126,10 @@ impl Cpu {
     fn addxy(&mut self, inst: u16) -> Result<(), CpuError> {
         let x = ((inst & 0x0F00) >> 8) as usize;
         let y = ((inst & 0x00F0) >> 4) as usize;
-        let res = self.reg[x] as u16 + self.reg[y] as u16;
-        if res > 255 {
-            self.reg[0xF] = 1
-        } else {
-            self.reg[0xF] = 0
-        }
-        self.reg[x] = res as u8;
+        let (res, carry) = self.reg[x].overflowing_add(self.reg[y]);
+        self.reg[x] = res;
+        // Flag is written last, so that it wins when x is 0xF
+        self.reg[0xF] = carry as u8;
         self.increment_pc()?;
         Ok(())
     }
@@ -460,12 +1143,10 @@ impl Cpu {
         let y = ((inst & 0x00F0) >> 4) as usize;
         // Use wrapping_sub instead of regular operator to allow overflow
         let res = self.reg[x].wrapping_sub(self.reg[y]);
-        if self.reg[x] > self.reg[y] {
-            self.reg[0xF] = 1
-        } else {
-            self.reg[0xF] = 0
-        }
+        let not_borrow = self.reg[x] > self.reg[y];
         self.reg[x] = res;
+        // Flag is written last, so that it wins when x is 0xF
+        self.reg[0xF] = not_borrow as u8;
         self.increment_pc()?;
         Ok(())
     }
@@ -474,14 +1155,19 @@ impl Cpu {
     ///
     /// Set Vx = Vx SHR 1.
     /// If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
+    /// With the shift quirk, Vy is shifted instead, with VF taken from its bit, and the result stored in Vx.
     fn shrx(&mut self, inst: u16) -> Result<(), CpuError> {
         let x = ((inst & 0x0F00) >> 8) as usize;
-        if self.reg[x] % 2 == 0 {
-            self.reg[0xF] = 0
+        let y = ((inst & 0x00F0) >> 4) as usize;
+        let src = if self.quirks.shift_quirk {
+            self.reg[y]
         } else {
-            self.reg[0xF] = 1
-        }
-        self.reg[x] /= 2;
+            self.reg[x]
+        };
+        let carry = src & 0x01;
+        self.reg[x] = src >> 1;
+        // Flag is written last, so that it wins when x is 0xF
+        self.reg[0xF] = carry;
         self.increment_pc()?;
         Ok(())
     }
@@ -495,12 +1181,10 @@ impl Cpu {
         let y = ((inst & 0x00F0) >> 4) as usize;
         // Use wrapping_sub instead of regular operator to allow overflow
         let res = self.reg[y].wrapping_sub(self.reg[x]);
-        if self.reg[y] > self.reg[x] {
-            self.reg[0xF] = 1
-        } else {
-            self.reg[0xF] = 0
-        }
+        let not_borrow = self.reg[y] > self.reg[x];
         self.reg[x] = res;
+        // Flag is written last, so that it wins when x is 0xF
+        self.reg[0xF] = not_borrow as u8;
         self.increment_pc()?;
         Ok(())
     }
@@ -509,14 +1193,19 @@ impl Cpu {
     ///
     /// Set Vx = Vx SHL 1.
     /// If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
+    /// With the shift quirk, Vy is shifted instead, with VF taken from its bit, and the result stored in Vx.
     fn shlx(&mut self, inst: u16) -> Result<(), CpuError> {
         let x = ((inst & 0x0F00) >> 8) as usize;
-        if self.reg[x] >> 7 == 1 {
-            self.reg[0xF] = 1
+        let y = ((inst & 0x00F0) >> 4) as usize;
+        let src = if self.quirks.shift_quirk {
+            self.reg[y]
         } else {
-            self.reg[0xF] = 0
-        }
-        self.reg[x] = self.reg[x].wrapping_mul(2);
+            self.reg[x]
+        };
+        let carry = src >> 7;
+        self.reg[x] = src << 1;
+        // Flag is written last, so that it wins when x is 0xF
+        self.reg[0xF] = carry;
         self.increment_pc()?;
         Ok(())
     }
@@ -530,7 +1219,7 @@ impl Cpu {
         let x = ((inst & 0x0F00) >> 8) as usize;
         let y = ((inst & 0x00F0) >> 4) as usize;
         if self.reg[x] != self.reg[y] {
-            self.increment_pc()?;
+            self.skip_next()?;
         }
         self.increment_pc()?;
         Ok(())
@@ -551,6 +1240,8 @@ impl Cpu {
     /// Set program counter to nnn + value in V0.
     fn jp0(&mut self, inst: u16) -> Result<(), CpuError> {
         let addr = inst & 0x0FFF;
+        // At most 0xFFF + 0xFF, so this cannot overflow; addresses past the end of memory
+        // fail when the next instruction is executed
         self.pc = addr + self.reg[0x0] as u16;
         Ok(())
     }
@@ -563,7 +1254,7 @@ impl Cpu {
     fn rndx(&mut self, inst: u16) -> Result<(), CpuError> {
         let x = ((inst & 0x0F00) >> 8) as usize;
         let kk = inst as u8;
-        let val: u8 = rand::random();
+        let val: u8 = self.rng.gen();
         self.reg[x] = val & kk;
         self.increment_pc()?;
         Ok(())
@@ -577,33 +1268,99 @@ impl Cpu {
     /// Sprites are XORed onto the existing screen. If this causes any pixels to be erased,
     /// VF is set to 1, otherwise it is set to 0. If the sprite is positioned so part of it is
     /// outside the coordinates of the display, it wraps around to the opposite side of the screen.
+    ///
+    /// If n is 0 in high resolution mode, a 16x16 sprite of 32 bytes is drawn instead.
+    /// In low resolution mode this depends on the lores_dxy0 quirk.
+    /// With XO-CHIP, sprite data is read for each selected plane in turn.
+    /// With the display_wait quirk, execution then waits for the next frame.
     fn drwxy(&mut self, inst: u16) -> Result<(), CpuError> {
         let x = ((inst & 0x0F00) >> 8) as usize;
         let y = ((inst & 0x00F0) >> 4) as usize;
         let n = (inst & 0x000F) as usize;
         let x_coord = self.reg[x] as usize;
         let y_coord = self.reg[y] as usize;
-        let mut sprite: Vec<u8> = vec![];
-        for j in 0..n {
-            sprite.push(self.mem[self.i as usize + j])
+        if n == 0 && (self.dct.hires() || self.quirks.lores_dxy0) {
+            let sprite = self.read_sprite(32 * self.dct.selected_planes())?;
+            self.reg[0xF] = self.dct.draw_16x16(x_coord, y_coord, sprite);
+        } else {
+            let sprite = self.read_sprite(n * self.dct.selected_planes())?;
+            self.reg[0xF] = self.dct.draw(x_coord, y_coord, sprite);
         }
-        #[cfg(test)]
-        assert_eq!(sprite, [0xF0, 0x90, 0x90, 0x90, 0xF0]);
-        self.reg[0xF] = self.dct.draw(x_coord, y_coord, sprite);
+        self.vblank_wait = self.quirks.display_wait;
         self.increment_pc()?;
         Ok(())
     }
 
+    // Read n bytes of sprite data from memory, starting at the address stored in I
+    fn read_sprite(&self, n: usize) -> Result<Vec<u8>, CpuError> {
+        Ok(self.mem[self.mem_range_at_i(n)?].to_vec())
+    }
+
+    // Range of n bytes of memory starting at the address stored in I
+    fn mem_range_at_i(&self, n: usize) -> Result<Range<usize>, CpuError> {
+        let start = self.i as usize;
+        if start + n > self.memory_size() {
+            return Err(CpuError::MemoryOutOfBounds);
+        }
+        Ok(start..start + n)
+    }
+
+    // Whether messages at the given level are logged, or captured by a test
+    fn log_enabled(&self, level: Level) -> bool {
+        #[cfg(test)]
+        if self.log_capture.is_some() {
+            return true;
+        }
+        log_enabled!(level)
+    }
+
+    // Log a message, keeping a copy while a test is capturing them
+    fn log(&mut self, level: Level, message: String) {
+        log::log!(level, "{message}");
+        #[cfg(test)]
+        if let Some(captured) = &mut self.log_capture {
+            captured.push((level, message));
+        }
+    }
+
+    // Write a byte of memory on behalf of the program, logging it if the address is watched
+    fn write_memory(&mut self, addr: usize, val: u8) {
+        if self.memory_watches.contains(&(addr as u16)) {
+            let message = format!(
+                "Memory at {addr:#05X} changed from {:#04X} to {val:#04X} by instruction at {:#05X}.",
+                self.mem[addr], self.pc
+            );
+            self.log(Level::Info, message);
+        }
+        self.mem[addr] = val;
+    }
+
+    // Check a write to memory against write protection, warning about protected writes.
+    // Returns whether the write should go ahead.
+    fn write_allowed(&self, range: &Range<usize>) -> Result<bool, CpuError> {
+        if self.write_protect == WriteProtect::Off || range.start >= PROGRAM_ENTRY_POINT {
+            return Ok(true);
+        }
+        warn!(
+            "Program at {:#05X} writes to protected memory at {:#05X}.",
+            self.pc, range.start
+        );
+        match self.write_protect {
+            WriteProtect::Error => Err(CpuError::WriteProtected),
+            _ => Ok(false),
+        }
+    }
+
     /// Opcode 0xEx9E - SKP Vx
     ///
     /// Skip next instruction if key with the value of Vx is pressed.
     /// Checks the keyboard, and if the key corresponding to the value of Vx is
     /// currently in the down position, PC is increased by 2.
+    /// Only the low nibble of Vx is used, unless in strict mode, where values above 0xF are an error.
     fn skpx(&mut self, inst: u16) -> Result<(), CpuError> {
-        let x = ((inst & 0x0F00) >> 8) as usize;
-        let key = self.reg[x];
+        let key = self.key_in_vx(inst)?;
         if self.ict.key_pressed(key) {
-            self.increment_pc()?;
+            self.skip_next()?;
         }
         self.increment_pc()?;
         Ok(())
@@ -614,23 +1371,78 @@ impl Cpu {
     /// Skip next instruction if key with the value of Vx is not pressed.
     /// Checks the keyboard, and if the key corresponding to the value of Vx is
     /// currently in the up position, PC is increased by 2.
+    /// Only the low nibble of Vx is used, unless in strict mode, where values above 0xF are an error.
     fn sknpx(&mut self, inst: u16) -> Result<(), CpuError> {
-        let x = ((inst & 0x0F00) >> 8) as usize;
-        let key = self.reg[x];
+        let key = self.key_in_vx(inst)?;
         if !self.ict.key_pressed(key) {
-            self.increment_pc()?;
+            self.skip_next()?;
         }
         self.increment_pc()?;
         Ok(())
     }
 
-    /// Opcode 0xFx07 - LD Vc, DT
-    ///
-    /// Set Vx = delay timer value.
-    /// The value of DT is placed into Vx.
-    fn ldxdt(&mut self, inst: u16) -> Result<(), CpuError> {
+    // Key checked by SKP and SKNP, from the register in the x field of the instruction
+    fn key_in_vx(&self, inst: u16) -> Result<u8, CpuError> {
         let x = ((inst & 0x0F00) >> 8) as usize;
-        self.reg[x] = self.dt;
+        if self.quirks.strict && self.reg[x] > 0xF {
+            return Err(CpuError::InvalidKey);
+        }
+        Ok(self.reg[x] & 0x0F)
+    }
+
+    /// Opcode 0xFn01 - PLANE n (XO-CHIP)
+    ///
+    /// Select the planes which DRW and CLS act on, as a bit mask n of the two planes.
+    /// Only decoded with the bit_planes quirk, so other programs always use the first plane.
+    fn plane(&mut self, inst: u16) -> Result<(), CpuError> {
+        if !self.quirks.bit_planes {
+            return Err(CpuError::UnknownOpcode);
+        }
+        let n = ((inst & 0x0F00) >> 8) as u8;
+        self.dct.set_plane_mask(n);
+        self.increment_pc()?;
+        Ok(())
+    }
+
+    /// Opcode 0xF000 nnnn - LD I, LONG addr (XO-CHIP)
+    ///
+    /// Set I to the 16-bit address nnnn in the word after the instruction, then skip both.
+    /// Only decoded with the extended_memory quirk, since LD I, addr reaches all of standard memory.
+    fn ldilong(&mut self) -> Result<(), CpuError> {
+        if !self.quirks.extended_memory {
+            return Err(CpuError::UnknownOpcode);
+        }
+        let addr = self
+            .word_at(self.pc as usize + 2)
+            .ok_or(CpuError::MemoryOutOfBounds)?;
+        // Both words must be within memory before anything changes
+        if self.pc as usize + 4 >= self.memory_size() {
+            return Err(CpuError::MemoryOutOfBounds);
+        }
+        self.i = addr;
+        self.pc += 4;
+        Ok(())
+    }
+
+    /// Opcode 0xF002 - AUDIO (XO-CHIP)
+    ///
+    /// Load the audio pattern buffer from the 16 bytes of memory starting at I.
+    /// The pattern is played while the sound timer is active, one bit per sample.
+    fn audio(&mut self) -> Result<(), CpuError> {
+        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+        pattern.copy_from_slice(&self.mem[self.mem_range_at_i(AUDIO_PATTERN_SIZE)?]);
+        self.audio_pattern = Some(pattern);
+        self.increment_pc()?;
+        Ok(())
+    }
+
+    /// Opcode 0xFx07 - LD Vc, DT
+    ///
+    /// Set Vx = delay timer value.
+    /// The value of DT is placed into Vx.
+    fn ldxdt(&mut self, inst: u16) -> Result<(), CpuError> {
+        let x = ((inst & 0x0F00) >> 8) as usize;
+        self.reg[x] = self.dt;
         self.increment_pc()?;
         Ok(())
     }
@@ -654,8 +1466,8 @@ impl Cpu {
     /// Set delay timer = Vx.
     /// DT is set equal to the value of Vx.
     fn lddtx(&mut self, inst: u16) -> Result<(), CpuError> {
-        let x = ((inst & 0x0F00) >> 8) as u8;
-        self.dt = x;
+        let x = ((inst & 0x0F00) >> 8) as usize;
+        self.dt = self.reg[x];
         self.increment_pc()?;
         Ok(())
     }
@@ -664,9 +1476,13 @@ impl Cpu {
     ///
     /// Set sound timer = Vx.
     /// ST is set equal to the value of Vx.
+    /// A nonzero value is raised to at least min_beep_frames, so that very short beeps remain audible.
     fn ldstx(&mut self, inst: u16) -> Result<(), CpuError> {
-        let x = ((inst & 0x0F00) >> 8) as u8;
-        self.st = x;
+        let x = ((inst & 0x0F00) >> 8) as usize;
+        self.st = match self.reg[x] {
+            0 => 0,
+            val => val.max(self.min_beep_frames),
+        };
         self.increment_pc()?;
         Ok(())
     }
@@ -677,7 +1493,7 @@ impl Cpu {
     /// The values of I and Vx are added, and the results are stored in I.
     fn addix(&mut self, inst: u16) -> Result<(), CpuError> {
         let x = ((inst & 0x0F00) >> 8) as usize;
-        self.i += self.reg[x] as u16;
+        self.i = self.i.wrapping_add(self.reg[x] as u16);
         self.increment_pc()?;
         Ok(())
     }
@@ -686,9 +1502,15 @@ impl Cpu {
     ///
     /// Set I = location of sprite for digit Vx.
     /// The value of I is set to the location for the hexadecimal sprite corresponding to the value of Vx.
+    ///
+    /// Only the low nibble of Vx is used, unless in strict mode, where values above 0xF are an error.
     fn ldfx(&mut self, inst: u16) -> Result<(), CpuError> {
         let x = ((inst & 0x0F00) >> 8) as usize;
-        self.i = FONT_START_ADDR as u16 + (self.reg[x] * 5) as u16;
+        if self.quirks.strict && self.reg[x] > 0xF {
+            return Err(CpuError::InvalidDigit);
+        }
+        let digit = (self.reg[x] & 0x0F) as u16;
+        self.i = self.font_addr as u16 + digit * 5;
         self.increment_pc()?;
         Ok(())
     }
@@ -700,39 +1522,78 @@ impl Cpu {
     /// the tens digit at location I+1, and the ones digit at location I+2.
     fn ldbx(&mut self, inst: u16) -> Result<(), CpuError> {
         let x = ((inst & 0x0F00) >> 8) as usize;
-        let mut num = self.reg[x];
-        let mut j = 3;
-        while num != 0 && j != 0 {
-            j -= 1;
-            self.mem[self.i as usize + j] = num % 10;
-            num /= 10;
+        let range = self.mem_range_at_i(3)?;
+        if !self.write_allowed(&range)? {
+            return self.increment_pc();
+        }
+        // All three digits are written, including leading zeros
+        let num = self.reg[x];
+        let digits = [num / 100, num / 10 % 10, num % 10];
+        for (addr, digit) in range.zip(digits) {
+            self.write_memory(addr, digit);
         }
         self.increment_pc()?;
         Ok(())
     }
 
+    /// Opcode 0xFx3A - PITCH Vx (XO-CHIP)
+    ///
+    /// Set the pitch register = Vx.
+    /// The audio pattern plays at 4000 * 2^((Vx - 64) / 48) samples per second.
+    fn pitchx(&mut self, inst: u16) -> Result<(), CpuError> {
+        let x = ((inst & 0x0F00) >> 8) as usize;
+        self.pitch = self.reg[x];
+        self.increment_pc()?;
+        Ok(())
+    }
+
     /// Opcode 0xFx55 - LD [I], Vx
     ///
     /// Store registers V0 through Vx in memory starting at location I.
     /// The interpreter copies the values of registers V0 through Vx into memory, starting at the address in I.
     fn ldiax(&mut self, inst: u16) -> Result<(), CpuError> {
         let x = ((inst & 0x0F00) >> 8) as usize;
-        for j in 0..x + 1 {
-            self.mem[self.i as usize + j] = self.reg[j]
+        let range = self.mem_range_at_i(x + 1)?;
+        if !self.write_allowed(&range)? {
+            return self.increment_pc();
+        }
+        // Writing below the entry point is allowed, but rarely intended
+        let font = self.font_range();
+        if range.start < font.end && font.start < range.end {
+            warn!(
+                "Program at {:#05X} overwrites the font at {:#05X}.",
+                self.pc, range.start
+            );
+        } else if range.start < PROGRAM_ENTRY_POINT {
+            warn!(
+                "Program at {:#05X} writes to interpreter memory at {:#05X}.",
+                self.pc, range.start
+            );
         }
+        for (addr, val) in range.zip(self.reg) {
+            self.write_memory(addr, val);
+        }
+        self.load_store_increment(x);
         self.increment_pc()?;
         Ok(())
     }
 
+    // With the load_store_increment quirk, leave I just past registers V0 through Vx
+    fn load_store_increment(&mut self, x: usize) {
+        if self.quirks.load_store_increment {
+            self.i = self.i.wrapping_add(x as u16 + 1);
+        }
+    }
+
     /// Opcode 0xFx65 - LD Vx, [I]
     ///
     /// Read registers V0 through Vx from memory starting at location I.
     /// The interpreter reads values from memory starting at location I into registers V0 through Vx.
     fn ldxia(&mut self, inst: u16) -> Result<(), CpuError> {
         let x = ((inst & 0x0F00) >> 8) as usize;
-        for j in 0..x + 1{
-            self.reg[j] = self.mem[self.i as usize + j]
-        }
+        let range = self.mem_range_at_i(x + 1)?;
+        self.reg[..=x].copy_from_slice(&self.mem[range]);
+        self.load_store_increment(x);
         self.increment_pc()?;
         Ok(())
     }
@@ -741,6 +1602,25 @@ impl Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    // Run f on the CPU, returning the messages it logged at the given level
+    fn capture_log(c: &mut Cpu, level: Level, f: impl FnOnce(&mut Cpu)) -> Vec<String> {
+        c.log_capture = Some(vec![]);
+        f(c);
+        let captured = c.log_capture.take().unwrap_or_default();
+        captured
+            .into_iter()
+            .filter(|(captured_level, _)| *captured_level == level)
+            .map(|(_, message)| message)
+            .collect()
+    }
+
+    // Run f on the CPU, returning the trace messages it logged
+    fn capture_trace(c: &mut Cpu, f: impl FnOnce(&mut Cpu)) -> Vec<String> {
+        capture_log(c, Level::Trace, f)
+    }
 
     // Execute a known opcode loaded to address 0x0000
     #[test]
@@ -777,6 +1657,153 @@ mod tests {
         c.exec_routine().unwrap();
     }
 
+    // Paths which used to overflow in debug builds return errors instead of panicking
+    #[test]
+    fn exec_routine_overflow_paths() {
+        // Program counter at the top of the address space
+        let mut c = Cpu {
+            pc: 0xFFFE,
+            ..Default::default()
+        };
+        assert!(matches!(c.exec_routine(), Err(CpuError::MemoryOutOfBounds)));
+        assert_eq!(c.pc, 0xFFFE);
+        // Jump past the end of memory with JP V0, then run off the end
+        let mut c = Cpu::default();
+        c.mem[0..2].copy_from_slice(&[0xBF, 0xFF]);
+        c.reg[0] = 0xFF;
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 0x10FE);
+        assert!(matches!(c.exec_routine(), Err(CpuError::MemoryOutOfBounds)));
+        // Overflowing the stack keeps the stack pointer in range
+        let mut c = Cpu::default();
+        c.mem[0..2].copy_from_slice(&[0x20, 0x00]);
+        while c.exec_routine().is_ok() {}
+        assert_eq!(c.sp(), STACK_SIZE);
+        // Timers survive the longest possible time step
+        c.dt = 2;
+        c.timer_tick(Duration::MAX);
+        c.timer_tick(Duration::MAX);
+        assert_eq!(c.dt, 0);
+    }
+
+    // Execute the exit instruction, which halts without advancing
+    #[test]
+    fn exec_routine_exit() {
+        let mut c = Cpu::default();
+        c.mem[0] = 0x00;
+        c.mem[1] = 0xFD;
+        assert!(!c.halted());
+        c.exec_routine().expect("exec_routine failed");
+        assert!(c.halted());
+        assert!(!c.paused());
+        assert_eq!(c.pc, 0);
+        c.reset();
+        assert!(!c.halted());
+    }
+
+    // With a filter for draws, only draw instructions are traced
+    #[test]
+    fn trace_filter() {
+        // LD V0, 0x00; LD F, V0; CLS; DRW V1, V1, 5; ADD V1, 0x08; JP 0x206
+        let program = [
+            0x60, 0x00, 0xF0, 0x29, 0x00, 0xE0, 0xD1, 0x15, 0x71, 0x08, 0x12, 0x06,
+        ];
+        let mut c = Cpu::default();
+        c.load_program_from_slice(&program)
+            .expect("failed to load program");
+        let unfiltered = capture_trace(&mut c, |c| {
+            for _ in 0..6 {
+                c.exec_routine().expect("exec_routine failed");
+            }
+        });
+        assert_eq!(unfiltered.len(), 6);
+        assert_eq!(unfiltered[0], "0x200: LD V0, 0x00");
+
+        c.set_trace_filter(Some(HashSet::from([OpcodeFamily::Draw])));
+        let filtered = capture_trace(&mut c, |c| {
+            for _ in 0..6 {
+                c.exec_routine().expect("exec_routine failed");
+            }
+        });
+        assert_eq!(filtered, ["0x206: DRW V1, V1, 5", "0x206: DRW V1, V1, 5"]);
+    }
+
+    // Writes to a watched address are logged with the old and new value
+    #[test]
+    fn watch_memory() {
+        let mut c = Cpu::default();
+        // LD V0, 0x12; LD V1, 0x34; LD I, 0x300; LD [I], V1
+        c.load_program_from_slice(&[0x60, 0x12, 0x61, 0x34, 0xA3, 0x00, 0xF1, 0x55])
+            .expect("failed to load program");
+        c.watch_memory(0x301);
+        let logged = capture_log(&mut c, Level::Info, |c| {
+            for _ in 0..4 {
+                c.exec_routine().expect("exec_routine failed");
+            }
+        });
+        assert_eq!(
+            logged,
+            ["Memory at 0x301 changed from 0x00 to 0x34 by instruction at 0x206."]
+        );
+        assert_eq!(c.mem[0x300..0x302], [0x12, 0x34]);
+
+        // Unwatched addresses are written silently
+        c.unwatch_memory(0x301);
+        c.pc = 0x206;
+        let logged = capture_log(&mut c, Level::Info, |c| {
+            c.exec_routine().expect("exec_routine failed");
+        });
+        assert!(logged.is_empty());
+    }
+
+    // RET with an empty stack fails, leaving the program counter and stack pointer alone
+    #[test]
+    fn exec_routine_ret_empty_stack() {
+        let mut c = Cpu {
+            pc: 0x200,
+            ..Default::default()
+        };
+        c.mem[0x200..0x202].copy_from_slice(&[0x00, 0xEE]);
+        assert!(matches!(c.exec_routine(), Err(CpuError::EmptyStack)));
+        assert_eq!((c.pc, c.sp()), (0x200, 0));
+        // CALL 0x300, RET, then RET again
+        c.mem[0x200..0x202].copy_from_slice(&[0x23, 0x00]);
+        c.mem[0x300..0x302].copy_from_slice(&[0x00, 0xEE]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.sp(), 1);
+        c.exec_routine().expect("exec_routine failed");
+        // RET continues after the CALL rather than running it again
+        assert_eq!((c.pc, c.sp()), (0x202, 0));
+        c.pc = 0x300;
+        assert!(matches!(c.exec_routine(), Err(CpuError::EmptyStack)));
+        assert_eq!((c.pc, c.sp()), (0x300, 0));
+    }
+
+    // Execute the sys instruction, which is ignored unless in strict mode
+    #[test]
+    fn exec_routine_sys() {
+        let mut c = Cpu::default();
+        c.mem[0] = 0x01;
+        c.mem[1] = 0x23;
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 2);
+
+        let mut c = Cpu {
+            quirks: Quirks {
+                strict: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        c.mem[0] = 0x01;
+        c.mem[1] = 0x23;
+        assert!(matches!(
+            c.exec_routine(),
+            Err(CpuError::UnsupportedSysCall)
+        ));
+        assert_eq!(c.pc, 0);
+    }
+
     // Execute the jp instruction
     #[test]
     fn exec_routine_jp() {
@@ -796,12 +1823,37 @@ mod tests {
         c.exec_routine().expect("exec_routine failed");
         assert_eq!(
             c.stk.pop(),
-            Some(0),
-            "testing if PC has been saved on stack"
+            Some(2),
+            "testing if the return address has been saved on stack"
         );
         assert_eq!(c.pc, 0xBEE);
     }
 
+    // Subroutines nest 16 deep, and each RET returns to the instruction after its CALL
+    #[test]
+    fn exec_routine_nested_calls() {
+        let mut c = Cpu::default();
+        // CALL 0x202 at 0x200, CALL 0x204 at 0x202 and so on up to 0x21E, then CALL 0x200
+        for n in 0..STACK_SIZE {
+            let addr = 0x200 + 2 * n;
+            let target = (addr + 2) as u16;
+            c.mem[addr..addr + 2].copy_from_slice(&(0x2000 | target).to_be_bytes());
+        }
+        c.mem[0x220..0x222].copy_from_slice(&[0x22, 0x00]);
+        c.pc = 0x200;
+        for _ in 0..STACK_SIZE {
+            c.exec_routine().expect("exec_routine failed");
+        }
+        assert_eq!((c.pc, c.sp()), (0x220, STACK_SIZE));
+        // A 17th CALL overflows and leaves the stack alone
+        assert!(matches!(c.exec_routine(), Err(CpuError::StackOverflow)));
+        assert_eq!((c.pc, c.sp()), (0x220, STACK_SIZE));
+        // RET
+        c.mem[0x220..0x222].copy_from_slice(&[0x00, 0xEE]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!((c.pc, c.sp()), (0x220, STACK_SIZE - 1));
+    }
+
     // Execute the sexb instruction
     #[test]
     fn exec_routine_sexb() {
@@ -837,6 +1889,35 @@ mod tests {
         assert_eq!(c.pc, 4);
     }
 
+    // Save and load register ranges in both directions, leaving I alone,
+    // while 5xy0 still skips if equal
+    #[test]
+    fn exec_routine_register_ranges() {
+        let mut c = Cpu {
+            i: 0x300,
+            ..Default::default()
+        };
+        c.reg[..4].copy_from_slice(&[0x11, 0x22, 0x33, 0x44]);
+        // SAVE V0, V2; SAVE V3, V1; LOAD V0, V2
+        c.mem[0..6].copy_from_slice(&[0x50, 0x22, 0x53, 0x12, 0x50, 0x23]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.mem[0x300..0x303], [0x11, 0x22, 0x33]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.mem[0x300..0x303], [0x44, 0x33, 0x22]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.reg[..4], [0x44, 0x33, 0x22, 0x44]);
+        assert_eq!((c.i, c.pc), (0x300, 6));
+
+        // SE V3, V2 skips only when the registers are equal
+        c.mem[6..8].copy_from_slice(&[0x53, 0x20]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 8);
+        c.reg[3] = 0x22;
+        c.pc = 6;
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 10);
+    }
+
     // Execute the ldxb instruction
     #[test]
     fn exec_routine_ldxb() {
@@ -911,6 +1992,25 @@ mod tests {
         assert_eq!(c.pc, 2);
     }
 
+    // The logic instructions reset VF only with the vf_reset quirk
+    #[test]
+    fn exec_routine_logic_vf_reset() {
+        for vf_reset in [false, true] {
+            for opcode in [0x8121, 0x8122, 0x8123] {
+                let mut c = Cpu::default();
+                c.set_quirks(Quirks {
+                    vf_reset,
+                    ..Default::default()
+                });
+                c.mem[..2].copy_from_slice(&u16::to_be_bytes(opcode));
+                c.reg[0xF] = 0x55;
+                c.exec_routine().expect("exec_routine failed");
+                let expected = if vf_reset { 0 } else { 0x55 };
+                assert_eq!(c.reg[0xF], expected, "{opcode:#06X}, vf_reset = {vf_reset}");
+            }
+        }
+    }
+
     // Execute the addxy instruction
     #[test]
     fn exec_routine_addxy() {
@@ -966,6 +2066,113 @@ mod tests {
         assert_eq!(c.pc, 2);
     }
 
+    // Execute addxy, subxy and subnxy with VF as the destination; the flag wins over the result
+    #[test]
+    fn exec_routine_arithmetic_vf_destination() {
+        // (opcode low byte, VF, V1, expected VF)
+        let cases = [
+            (0x14, 200, 100, 1),
+            (0x14, 10, 20, 0),
+            (0x15, 100, 10, 1),
+            (0x15, 10, 100, 0),
+            (0x17, 10, 100, 1),
+            (0x17, 100, 10, 0),
+        ];
+        for (op, vf, v1, expected) in cases {
+            let mut c = Cpu::default();
+            c.mem[0] = 0x8F;
+            c.mem[1] = op;
+            c.reg[0xF] = vf;
+            c.reg[0x1] = v1;
+            c.exec_routine().expect("exec_routine failed");
+            assert_eq!(c.reg[0xF], expected, "opcode 0x8F{op:02X}");
+            assert_eq!(c.pc, 2);
+        }
+    }
+
+    // Execute the shrx and shlx instructions with the shift quirk, shifting Vy into Vx
+    #[test]
+    fn exec_routine_shift_quirk() {
+        let mut c = Cpu {
+            quirks: Quirks {
+                shift_quirk: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        c.mem[0..4].copy_from_slice(&[0x8B, 0xC6, 0x8D, 0xCE]);
+        c.reg[0xB] = 0xFF;
+        c.reg[0xC] = 0x42;
+        c.reg[0xD] = 0xFF;
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.reg[0xB], 0x21);
+        assert_eq!(c.reg[0xF], 0);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.reg[0xD], 0x84);
+        assert_eq!(c.reg[0xF], 0);
+        assert_eq!(c.reg[0xC], 0x42);
+    }
+
+    // Execute shrx and shlx with VF as the destination; the flag wins over the result
+    #[test]
+    fn exec_routine_shift_vf_destination() {
+        for shift_quirk in [false, true] {
+            // (opcode low byte, VF, V1, expected VF)
+            let cases = [
+                (0x16, 0x03, 0x03, 1),
+                (0x16, 0x02, 0x02, 0),
+                (0x1E, 0x81, 0x81, 1),
+                (0x1E, 0x41, 0x41, 0),
+            ];
+            for (op, vf, v1, expected) in cases {
+                let mut c = Cpu {
+                    quirks: Quirks {
+                        shift_quirk,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                };
+                c.mem[0] = 0x8F;
+                c.mem[1] = op;
+                c.reg[0xF] = vf;
+                c.reg[0x1] = v1;
+                c.exec_routine().expect("exec_routine failed");
+                assert_eq!(
+                    c.reg[0xF], expected,
+                    "opcode 0x8F{op:02X}, shift_quirk {shift_quirk}"
+                );
+            }
+        }
+    }
+
+    // The carry comes from the bit shifted out of whichever register is shifted
+    #[test]
+    fn exec_routine_shift_carry_source() {
+        // (opcode low byte, shift_quirk, expected Vx, expected VF) with Vx = 0x81, Vy = 0x7E
+        let cases = [
+            (0x26, false, 0x40, 1),
+            (0x26, true, 0x3F, 0),
+            (0x2E, false, 0x02, 1),
+            (0x2E, true, 0xFC, 0),
+        ];
+        for (op, shift_quirk, vx, vf) in cases {
+            let mut c = Cpu::default();
+            c.set_quirks(Quirks {
+                shift_quirk,
+                ..Default::default()
+            });
+            c.mem[0] = 0x81;
+            c.mem[1] = op;
+            c.reg[0x1] = 0x81;
+            c.reg[0x2] = 0x7E;
+            c.exec_routine().expect("exec_routine failed");
+            let case = format!("opcode 0x81{op:02X}, shift_quirk {shift_quirk}");
+            assert_eq!(c.reg[0x1], vx, "{case}");
+            assert_eq!(c.reg[0xF], vf, "{case}");
+            assert_eq!(c.reg[0x2], 0x7E);
+        }
+    }
+
     // Execute the shlx instruction
     #[test]
     fn exec_routine_shlx() {
@@ -1002,6 +2209,119 @@ mod tests {
         assert_eq!(c.pc, 2);
     }
 
+    // The program counter advances past 0xFFF only with extended memory,
+    // and never past the end of memory
+    #[test]
+    fn increment_pc_memory_size() {
+        let mut c = Cpu {
+            pc: 0xFFE,
+            ..Default::default()
+        };
+        assert!(matches!(c.increment_pc(), Err(CpuError::MemoryOutOfBounds)));
+        assert_eq!(c.pc, 0xFFE);
+
+        c.set_quirks(Quirks {
+            extended_memory: true,
+            ..Default::default()
+        });
+        c.increment_pc().expect("increment_pc failed");
+        assert_eq!(c.pc, 0x1000);
+        c.pc = 0xFFFE;
+        assert!(matches!(c.increment_pc(), Err(CpuError::MemoryOutOfBounds)));
+        assert_eq!(c.pc, 0xFFFE);
+    }
+
+    // JP and CALL address 12 bits, so they stay within the first 4KB even with extended memory
+    #[test]
+    fn exec_routine_jump_address_mask() {
+        // JP 0xF00 at 0x1000 and CALL 0x204 at 0xF00
+        let mut c = Cpu::default();
+        c.set_quirks(Quirks {
+            extended_memory: true,
+            ..Default::default()
+        });
+        assert_eq!(c.memory_size(), EXTENDED_MEMORY_SIZE);
+        c.mem[0x1000..0x1002].copy_from_slice(&[0x1F, 0x00]);
+        c.mem[0xF00..0xF02].copy_from_slice(&[0x22, 0x04]);
+        c.pc = 0x1000;
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 0xF00);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 0x204);
+        assert_eq!(c.stk, [0xF02]);
+    }
+
+    // JP V0 can land past 0xFFF, where code only runs with extended memory
+    #[test]
+    fn exec_routine_jp0_extended_memory() {
+        let mut c = Cpu::default();
+        c.set_quirks(Quirks {
+            extended_memory: true,
+            ..Default::default()
+        });
+        // JP V0, 0xF03 with V0 = 0xFF, then ADD V1, 0x01 at 0x1002
+        c.mem[0..2].copy_from_slice(&[0xBF, 0x03]);
+        c.mem[0x1002..0x1004].copy_from_slice(&[0x71, 0x01]);
+        c.reg[0] = 0xFF;
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 0x1002);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 0x1004);
+        assert_eq!(c.reg[1], 1);
+
+        // Without it, the same jump runs off the end of memory
+        let mut c = Cpu::default();
+        c.mem[0..2].copy_from_slice(&[0xBF, 0x03]);
+        c.reg[0] = 0xFF;
+        c.exec_routine().expect("exec_routine failed");
+        assert!(matches!(c.exec_routine(), Err(CpuError::MemoryOutOfBounds)));
+    }
+
+    // LD I, LONG loads a 16-bit address from the next word and skips past it
+    #[test]
+    fn exec_routine_ldilong() {
+        let mut c = Cpu::default();
+        c.set_quirks(Quirks {
+            extended_memory: true,
+            ..Default::default()
+        });
+        c.mem[0..4].copy_from_slice(&[0xF0, 0x00, 0xAB, 0xCD]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!((c.i, c.pc), (0xABCD, 4));
+
+        // Its address must be within memory, and nothing changes if it is not
+        let last = (c.memory_size() - 2) as u16;
+        c.pc = last;
+        c.mem[last as usize..].copy_from_slice(&[0xF0, 0x00]);
+        assert!(matches!(c.exec_routine(), Err(CpuError::MemoryOutOfBounds)));
+        assert_eq!((c.i, c.pc), (0xABCD, last));
+
+        // Without extended memory it is not an instruction
+        let mut c = Cpu::default();
+        c.mem[0..4].copy_from_slice(&[0xF0, 0x00, 0xAB, 0xCD]);
+        assert!(matches!(c.exec_routine(), Err(CpuError::UnknownOpcode)));
+    }
+
+    // Skips step over both words of LD I, LONG with extended memory
+    #[test]
+    fn exec_routine_skip_ldilong() {
+        let mut c = Cpu::default();
+        c.set_quirks(Quirks {
+            extended_memory: true,
+            ..Default::default()
+        });
+        // SE V0, 0x00; LD I, LONG 0x1234
+        c.mem[0..6].copy_from_slice(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 6);
+
+        // In standard memory F000 is a single word like any other
+        let mut c = Cpu::default();
+        c.mem[0..6].copy_from_slice(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 4);
+    }
+
     // Execute the jp0 instruction
     #[test]
     fn exec_routine_jp0() {
@@ -1029,6 +2349,154 @@ mod tests {
         assert_eq!(c.pc, 2);
     }
 
+    // Drawing with I pointed at the font renders the glyph, whether I is set directly or by LD F, Vx
+    #[test]
+    fn draw_font_glyph() {
+        let mut c = Cpu::default();
+        c.set_write_protect(WriteProtect::Error);
+        // LD I, 0x050; DRW V0, V1, 5; LD F, V2; DRW V3, V1, 5
+        c.load_program_from_slice(&[0xA0, 0x50, 0xD0, 0x15, 0xF2, 0x29, 0xD3, 0x15])
+            .unwrap();
+        c.reg[0x0] = 10;
+        c.reg[0x1] = 3;
+        c.reg[0x2] = 0xA;
+        c.reg[0x3] = 20;
+        for _ in 0..4 {
+            c.exec_routine().expect("exec_routine failed");
+        }
+        for (glyph, x) in [(0x0, 10), (0xA, 20)] {
+            for row in 0..5 {
+                let byte = FONT[glyph * 5 + row];
+                for col in 0..8 {
+                    let lit = byte & (0x80 >> col) != 0;
+                    assert_eq!(c.dct.pixel(x + col, 3 + row), lit, "glyph {glyph:X}");
+                }
+            }
+        }
+        assert_eq!(c.reg[0xF], 0);
+    }
+
+    // Execute the drwxy instruction at the bottom right corner with and without clipping
+    #[test]
+    fn exec_routine_drwxy_edge() {
+        for clip_sprites in [false, true] {
+            let mut c = Cpu {
+                i: 0x300,
+                ..Default::default()
+            };
+            c.set_quirks(Quirks {
+                clip_sprites,
+                ..Default::default()
+            });
+            c.mem[0x300..0x303].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+            c.reg[0] = 63;
+            c.reg[1] = 31;
+            c.mem[0] = 0xD0;
+            c.mem[1] = 0x13;
+            c.exec_routine().expect("exec_routine failed");
+            assert_eq!(c.reg[0xF], 0);
+            // Pixel wrapped to the top left corner, unless clipped
+            assert_eq!(c.dct.draw(0, 0, vec![0x80]), !clip_sprites as u8);
+            assert_eq!(c.dct.draw(63, 31, vec![0x80]), 1);
+        }
+    }
+
+    // Execute the drwxy instruction with n = 0 in high resolution mode
+    #[test]
+    fn exec_routine_drwxy_16x16() {
+        let mut c = Cpu {
+            i: 0x300,
+            ..Default::default()
+        };
+        c.mem[0] = 0x00;
+        c.mem[1] = 0xFF;
+        c.mem[2] = 0xD0;
+        c.mem[3] = 0x00;
+        // Fill one byte past the end of the sprite, which must not be read
+        for j in 0..33 {
+            c.mem[0x300 + j] = 0xFF;
+        }
+        c.exec_routine().expect("exec_routine failed");
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.reg[0xF], 0);
+        assert_eq!(c.pc, 4);
+        // Last row of the right half was drawn
+        assert_eq!(c.dct.draw(8, 15, vec![0x80]), 1);
+        // Row below the sprite was not drawn
+        assert_eq!(c.dct.draw(0, 16, vec![0xFF]), 0);
+        assert_eq!(c.dct.draw(8, 16, vec![0xFF]), 0);
+    }
+
+    // Execute the drwxy instruction with n = 0 in low resolution mode
+    #[test]
+    fn exec_routine_drwxy_lores_dxy0() {
+        let mut c = Cpu {
+            i: 0x300,
+            ..Default::default()
+        };
+        for j in 0..32 {
+            c.mem[0x300 + j] = 0xFF;
+        }
+        c.mem[0] = 0xD0;
+        c.mem[1] = 0x00;
+        c.exec_routine().expect("exec_routine failed");
+        // Nothing is drawn without the quirk
+        assert_eq!(c.dct.draw(0, 0, vec![0xFF]), 0);
+
+        let mut c = Cpu {
+            i: 0x300,
+            quirks: Quirks {
+                lores_dxy0: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for j in 0..32 {
+            c.mem[0x300 + j] = 0xFF;
+        }
+        c.mem[0] = 0xD0;
+        c.mem[1] = 0x00;
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.dct.draw(8, 15, vec![0x80]), 1);
+    }
+
+    // Execute skpx and sknpx with key values above 0xF, which are masked to their low nibble
+    #[test]
+    fn exec_routine_skp_invalid_key() {
+        let mut c = Cpu::default();
+        c.ict.press_key(0x5);
+        // SKP V3, with 0x5 pressed
+        c.reg[0x3] = 0x5;
+        c.mem[0..2].copy_from_slice(&[0xE3, 0x9E]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 4);
+        // SKP V3, with 0x5 not pressed
+        c.ict.unpress_key(0x5);
+        c.mem[4..6].copy_from_slice(&[0xE3, 0x9E]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 6);
+        // SKNP V3 with 0x1F checks key 0xF
+        c.ict.press_key(0xF);
+        c.reg[0x3] = 0x1F;
+        c.mem[6..8].copy_from_slice(&[0xE3, 0xA1]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 8);
+        c.mem[8..10].copy_from_slice(&[0xE3, 0x9E]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 12);
+
+        // Strict mode rejects keys which are not on the keypad
+        c.quirks.strict = true;
+        c.mem[12..14].copy_from_slice(&[0xE3, 0x9E]);
+        assert!(matches!(c.exec_routine(), Err(CpuError::InvalidKey)));
+        c.mem[12..14].copy_from_slice(&[0xE3, 0xA1]);
+        assert!(matches!(c.exec_routine(), Err(CpuError::InvalidKey)));
+        assert_eq!(c.pc, 12);
+        c.reg[0x3] = 0xF;
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 14);
+    }
+
     // Execute the addix instruction
     #[test]
     fn exec_routine_addix() {
@@ -1060,6 +2528,147 @@ mod tests {
         assert_eq!(c.i as usize, 0x5A);
     }
 
+    // A relocated font is where LD F, Vx points I, and must fit below the entry point
+    #[test]
+    fn set_font_address() {
+        let mut c = Cpu::default();
+        c.set_font_address(0x100).expect("failed to move font");
+        assert_eq!(c.font_addr, 0x100);
+        assert_eq!(c.mem[0x100..0x100 + FONT.len()], FONT);
+        assert!(c.mem[FONT_START_ADDR..FONT_START_ADDR + FONT.len()]
+            .iter()
+            .all(|&b| b == 0));
+        // LD F, V0
+        c.mem[0..2].copy_from_slice(&[0xF0, 0x29]);
+        c.reg[0] = 0xA;
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.i, 0x100 + 0xA * 5);
+        c.reset();
+        assert_eq!(c.mem[0x100..0x100 + FONT.len()], FONT);
+
+        let last = PROGRAM_ENTRY_POINT - FONT.len();
+        assert!(c.set_font_address(last).is_ok());
+        assert!(matches!(
+            c.set_font_address(last + 1),
+            Err(CpuError::InvalidFontAddress)
+        ));
+        assert!(matches!(
+            c.set_font_address(usize::MAX),
+            Err(CpuError::InvalidFontAddress)
+        ));
+        assert_eq!(c.font_addr, last);
+    }
+
+    // Execute the ldfx instruction with digits at and beyond the end of the font
+    #[test]
+    fn exec_routine_ldfx_overflow() {
+        for (val, addr) in [(0x0, 0x50), (0xF, 0x9B), (0xFF, 0x9B)] {
+            let mut c = Cpu::default();
+            c.mem[0] = 0xF0;
+            c.mem[1] = 0x29;
+            c.reg[0] = val;
+            c.exec_routine().expect("exec_routine failed");
+            assert_eq!(c.i as usize, addr, "digit {val:#X}");
+        }
+
+        let mut c = Cpu {
+            quirks: Quirks {
+                strict: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        c.mem[0] = 0xF0;
+        c.mem[1] = 0x29;
+        c.reg[0] = 0xFF;
+        assert!(matches!(c.exec_routine(), Err(CpuError::InvalidDigit)));
+        assert_eq!(c.pc, 0);
+        c.reg[0] = 0xF;
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.i as usize, 0x9B);
+    }
+
+    // Read CPU state through getters
+    #[test]
+    fn getters() {
+        let mut c = Cpu {
+            pc: 0x204,
+            i: 0x300,
+            ..Default::default()
+        };
+        c.reg[0xF] = 0xBE;
+        assert_eq!(c.pc(), 0x204);
+        assert_eq!(c.index(), 0x300);
+        assert_eq!(c.register(0xF).unwrap(), 0xBE);
+        assert!(matches!(c.register(0x10), Err(CpuError::InvalidRegister)));
+    }
+
+    // The register file reads back as a whole
+    #[test]
+    fn registers() {
+        let mut c = Cpu::default();
+        // LD V0, 0x01; LD V7, 0x77; LD VF, 0xFF
+        c.load_program_from_slice(&[0x60, 0x01, 0x67, 0x77, 0x6F, 0xFF])
+            .expect("failed to load program");
+        for _ in 0..3 {
+            c.exec_routine().expect("exec_routine failed");
+        }
+        let mut expected = [0; 16];
+        expected[0x0] = 0x01;
+        expected[0x7] = 0x77;
+        expected[0xF] = 0xFF;
+        assert_eq!(c.registers(), expected);
+    }
+
+    // Timers set by LD DT, Vx and LD ST, Vx read back through the accessors
+    #[test]
+    fn timer_getters() {
+        let mut c = Cpu::default();
+        // LD DT, V3; LD ST, V4
+        c.mem[..4].copy_from_slice(&[0xF3, 0x15, 0xF4, 0x18]);
+        c.reg[3] = 0x20;
+        c.reg[4] = 0x08;
+        c.exec_routine().expect("exec_routine failed");
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.delay_timer(), 0x20);
+        assert_eq!(c.sound_timer(), 0x08);
+    }
+
+    // Execute the lddtx instruction
+    #[test]
+    fn exec_routine_lddtx() {
+        let mut c = Cpu::default();
+        c.mem[0] = 0xF3;
+        c.mem[1] = 0x15;
+        c.reg[3] = 0x20;
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 2);
+        assert_eq!(c.dt, 0x20);
+    }
+
+    // Execute the ldstx instruction with and without a minimum beep length
+    #[test]
+    fn exec_routine_ldstx() {
+        for (min_beep_frames, expected) in [(0, 1), (4, 4)] {
+            let mut c = Cpu::default();
+            c.set_min_beep_frames(min_beep_frames);
+            c.mem[0] = 0xF3;
+            c.mem[1] = 0x18;
+            c.reg[3] = 1;
+            c.exec_routine().expect("exec_routine failed");
+            assert_eq!(c.pc, 2);
+            assert_eq!(c.st, expected);
+        }
+        // Zero still silences the beeper
+        let mut c = Cpu::default();
+        c.set_min_beep_frames(4);
+        c.st = 10;
+        c.mem[0] = 0xF3;
+        c.mem[1] = 0x18;
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.st, 0);
+    }
+
     // Execute the ldbx instruction
     #[test]
     fn exec_routine_ldbx() {
@@ -1074,6 +2683,167 @@ mod tests {
         assert_eq!(c.mem[c.i as usize + 2], 3);
     }
 
+    // ldbx writes leading zeros over whatever memory held before
+    #[test]
+    fn exec_routine_ldbx_leading_zeros() {
+        let mut c = Cpu::default();
+        c.set_memory_fill(MemoryFill::Ones);
+        c.reset();
+        // LD I, 0x300; LD B, V0 with V0 = 7, then LD B, V1 with V1 = 0
+        c.load_program_from_slice(&[0xA3, 0x00, 0xF0, 0x33, 0xF1, 0x33])
+            .unwrap();
+        c.reg[0] = 7;
+        c.exec_routine().expect("exec_routine failed");
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.mem[0x300..0x303], [0, 0, 7]);
+        c.mem[0x300..0x303].fill(0xFF);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.mem[0x300..0x303], [0, 0, 0]);
+    }
+
+    // Undo instructions one at a time back to the original state
+    #[test]
+    fn undo_instruction() {
+        // State compared before and after undoing
+        fn state(c: &Cpu) -> (String, Vec<u8>, [u8; FRAME_SIZE]) {
+            let summary = format!("{c:?} stack: {:?} hires: {}", c.stk, c.dct.hires());
+            (summary, c.mem.clone(), c.dct.snapshot())
+        }
+        let mut c = Cpu::default();
+        // LD V0, 0x25; LD DT, V0; LD I, 0x300; LD [I], V0; HIGH; LD F, V0;
+        // DRW V0, V0, 5; CALL 0x20E; CLS
+        let program = [
+            0x60, 0x25, 0xF0, 0x15, 0xA3, 0x00, 0xF0, 0x55, 0x00, 0xFF, 0xF0, 0x29, 0xD0, 0x05,
+            0x22, 0x12, 0x00, 0x00, 0x00, 0xE0,
+        ];
+        c.load_program_from_slice(&program).unwrap();
+        assert!(!c.undo_instruction());
+        c.set_undo_depth(16);
+        let mut states = vec![];
+        for _ in 0..9 {
+            states.push(state(&c));
+            c.exec_routine().expect("exec_routine failed");
+        }
+        assert_eq!(c.mem[0x300], 0x25);
+        assert_eq!(c.stk.len(), 1);
+        while let Some(expected) = states.pop() {
+            assert!(c.undo_instruction());
+            assert_eq!(state(&c), expected, "undoing to step {}", states.len());
+        }
+        assert!(!c.undo_instruction());
+
+        // Only the last depth instructions are kept
+        c.set_undo_depth(2);
+        for _ in 0..4 {
+            c.exec_routine().expect("exec_routine failed");
+        }
+        assert!(c.undo_instruction());
+        assert!(c.undo_instruction());
+        assert!(!c.undo_instruction());
+        assert_eq!(c.pc, 0x204);
+    }
+
+    // Undo reverts drawing to the second plane and the plane selection
+    #[test]
+    fn undo_planes() {
+        let mut c = Cpu::default();
+        c.set_quirks(Quirks {
+            bit_planes: true,
+            ..Default::default()
+        });
+        // PLANE 2; LD F, V0; DRW V0, V0, 5
+        c.load_program_from_slice(&[0xF2, 0x01, 0xF0, 0x29, 0xD0, 0x05])
+            .unwrap();
+        c.set_undo_depth(4);
+        for _ in 0..3 {
+            c.exec_routine().expect("exec_routine failed");
+        }
+        assert_eq!(c.dct.plane_pixels(0, 0), (false, true));
+        assert!(c.undo_instruction());
+        assert_eq!(c.dct.snapshot(), [0; FRAME_SIZE]);
+        assert_eq!(c.dct.plane_mask(), 0b10);
+        assert!(c.undo_instruction());
+        assert!(c.undo_instruction());
+        assert_eq!(c.dct.plane_mask(), DEFAULT_PLANE_MASK);
+    }
+
+    // Preset registers and I are seen by the first instruction
+    #[test]
+    fn preset_registers() {
+        let mut c = Cpu::default();
+        c.load_program_from_slice(&[0xF3, 0x55]).unwrap();
+        let mut registers = [0; REGISTER_COUNT];
+        registers[..4].copy_from_slice(&[1, 2, 3, 7]);
+        c.preset(registers, 0x300);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.mem[0x300..0x305], [1, 2, 3, 7, 0]);
+        assert_eq!(c.index(), 0x300);
+    }
+
+    // Load the XO-CHIP audio pattern from memory at I and set the pitch
+    #[test]
+    fn exec_routine_audio() {
+        let mut c = Cpu::default();
+        assert_eq!(c.audio_pattern(), None);
+        assert_eq!(c.pitch(), DEFAULT_PITCH);
+        // AUDIO, PITCH V3
+        c.mem[..4].copy_from_slice(&[0xF0, 0x02, 0xF3, 0x3A]);
+        c.i = 0x300;
+        for n in 0..AUDIO_PATTERN_SIZE {
+            c.mem[0x300 + n] = n as u8 * 0x11;
+        }
+        c.reg[3] = 112;
+        c.exec_routine().expect("exec_routine failed");
+        let pattern = c.audio_pattern().expect("no audio pattern");
+        assert_eq!(pattern[..], c.mem[0x300..0x310]);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pitch(), 112);
+        assert_eq!(c.pc, 4);
+        // The pattern must fit in memory
+        c.pc = 0;
+        c.i = (MEMORY_SIZE - 8) as u16;
+        assert!(matches!(c.exec_routine(), Err(CpuError::MemoryOutOfBounds)));
+        c.reset();
+        assert_eq!((c.audio_pattern(), c.pitch()), (None, DEFAULT_PITCH));
+    }
+
+    // Select both planes, then clear only the second one
+    #[test]
+    fn exec_routine_plane() {
+        // PLANE 3, DRW V0 V0 1, PLANE 2, CLS
+        const PROGRAM: [u8; 8] = [0xF3, 0x01, 0xD0, 0x01, 0xF2, 0x01, 0x00, 0xE0];
+        let mut c = Cpu {
+            i: 0x300,
+            ..Default::default()
+        };
+        c.set_quirks(Quirks {
+            bit_planes: true,
+            ..Default::default()
+        });
+        c.mem[0x300..0x302].copy_from_slice(&[0xF0, 0x0F]);
+        c.mem[..8].copy_from_slice(&PROGRAM);
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.dct.plane_mask(), 0b11);
+        c.exec_routine().expect("exec_routine failed");
+        // The first byte went to the first plane, the second to the second plane
+        assert_eq!(c.dct.plane_pixels(0, 0), (true, false));
+        assert_eq!(c.dct.plane_pixels(4, 0), (false, true));
+        c.exec_routine().expect("exec_routine failed");
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.pc, 8);
+        assert_eq!(c.dct.plane_pixels(0, 0), (true, false));
+        assert_eq!(c.dct.plane_pixels(4, 0), (false, false));
+        // Only the first plane is selected after a reset
+        c.reset();
+        assert_eq!(c.dct.plane_mask(), DEFAULT_PLANE_MASK);
+
+        // Without the bit_planes quirk, PLANE is an unknown opcode
+        c.set_quirks(Quirks::default());
+        c.mem[..8].copy_from_slice(&PROGRAM);
+        assert!(matches!(c.exec_routine(), Err(CpuError::UnknownOpcode)));
+        assert_eq!(c.dct.plane_mask(), DEFAULT_PLANE_MASK);
+    }
+
     // Execute the ldiax instruction
     #[test]
     fn exec_routine_ldiax() {
@@ -1090,6 +2860,149 @@ mod tests {
         assert_eq!(c.mem[c.i as usize + 2], 3);
     }
 
+    // Fx55 and Fx65 advance I only with the load_store_increment quirk
+    #[test]
+    fn exec_routine_load_store_increment() {
+        for load_store_increment in [false, true] {
+            let mut c = Cpu::default();
+            c.set_quirks(Quirks {
+                load_store_increment,
+                ..Default::default()
+            });
+            // LD [I], V2; LD V1, [I]
+            c.mem[..4].copy_from_slice(&[0xF2, 0x55, 0xF1, 0x65]);
+            c.i = 0x300;
+            c.exec_routine().expect("exec_routine failed");
+            c.exec_routine().expect("exec_routine failed");
+            let expected = if load_store_increment { 0x305 } else { 0x300 };
+            assert_eq!(c.i, expected);
+        }
+    }
+
+    // With display_wait, drawing waits for the next frame until vblank is signalled
+    #[test]
+    fn exec_routine_drwxy_display_wait() {
+        let mut c = Cpu::default();
+        c.mem[..4].copy_from_slice(&[0xD0, 0x01, 0xD0, 0x01]);
+        c.exec_routine().expect("exec_routine failed");
+        assert!(!c.waiting_for_vblank());
+        c.set_quirks(Quirks {
+            display_wait: true,
+            ..Default::default()
+        });
+        c.exec_routine().expect("exec_routine failed");
+        assert!(c.waiting_for_vblank());
+        c.vblank();
+        assert!(!c.waiting_for_vblank());
+    }
+
+    // Implemented opcodes decode to a handler, others do not
+    #[test]
+    fn is_implemented() {
+        for opcode in [
+            0x00E0, 0x00EE, 0x00FF, 0x0123, 0x5120, 0x8AB6, 0xD125, 0xE19E, 0xF002, 0xF43A, 0xF265,
+        ] {
+            assert!(Cpu::is_implemented(opcode), "{opcode:#06X}");
+        }
+        for opcode in [0x5121, 0x8AB8, 0x9121, 0xE1A2, 0xF075, 0xF2FF] {
+            assert!(!Cpu::is_implemented(opcode), "{opcode:#06X}");
+        }
+    }
+
+    // ldiax may write up to and including the last byte of memory
+    #[test]
+    fn ldiax_memory_end() {
+        let mut c = Cpu::default();
+        c.mem[0] = 0xF2;
+        c.mem[1] = 0x55;
+        c.reg[..3].copy_from_slice(&[1, 2, 3]);
+        c.i = (MEMORY_SIZE - 3) as u16;
+        c.exec_routine().expect("exec_routine failed");
+        assert_eq!(c.mem[MEMORY_SIZE - 3..], [1, 2, 3]);
+    }
+
+    // ldiax fails without writing anything if the registers do not fit before the end of memory
+    #[test]
+    fn ldiax_overrun() {
+        let mut c = Cpu::default();
+        c.mem[0] = 0xF2;
+        c.mem[1] = 0x55;
+        c.reg[..3].copy_from_slice(&[1, 2, 3]);
+        c.i = (MEMORY_SIZE - 2) as u16;
+        assert!(matches!(c.exec_routine(), Err(CpuError::MemoryOutOfBounds)));
+        assert_eq!(c.mem[MEMORY_SIZE - 2..], [0, 0]);
+        assert_eq!(c.pc, 0);
+    }
+
+    // Reset fills memory according to the memory fill setting, around the font and program
+    #[test]
+    fn memory_fill() {
+        let mut c = Cpu::default();
+        c.reg[1] = 0x12;
+        c.set_memory_fill(MemoryFill::Ones);
+        c.reset();
+        c.load_program_from_slice(&[0x60, 0x01]).unwrap();
+        assert_eq!(c.mem[0x202], 0xFF);
+        assert_eq!(c.mem[0x000], 0xFF);
+        assert_eq!(c.mem[0x200..0x202], [0x60, 0x01]);
+        assert_eq!(c.mem[FONT_START_ADDR..FONT_START_ADDR + FONT.len()], FONT);
+        assert_eq!(c.reg[1], 0);
+        // Random fill is repeatable for a given seed
+        c.set_memory_fill(MemoryFill::Random(7));
+        c.reset();
+        let first = c.mem.clone();
+        c.reset();
+        assert_eq!(c.mem, first);
+        assert!(first[0x300..].iter().any(|&b| b != first[0x300]));
+    }
+
+    // Writes below the entry point are skipped or fail depending on write protection,
+    // while writes to program memory go ahead
+    #[test]
+    fn write_protect() {
+        let mut c = Cpu::default();
+        c.set_write_protect(WriteProtect::Skip);
+        // LD [I], V1; LD B, V0
+        c.load_program_from_slice(&[0xF1, 0x55, 0xF0, 0x33, 0xF1, 0x55])
+            .unwrap();
+        c.reg[0] = 123;
+        c.reg[1] = 0xAA;
+        c.i = FONT_START_ADDR as u16;
+        c.exec_routine().expect("skipped write failed");
+        assert_eq!(c.mem[FONT_START_ADDR..FONT_START_ADDR + 2], FONT[..2]);
+        c.i = 0x1FF;
+        c.exec_routine().expect("skipped write failed");
+        assert_eq!(c.mem[0x1FF..0x202], [0x00, 0xF1, 0x55]);
+        assert_eq!(c.pc, 0x204);
+
+        c.set_write_protect(WriteProtect::Error);
+        c.i = 0x1FE;
+        assert!(matches!(c.exec_routine(), Err(CpuError::WriteProtected)));
+        assert_eq!(c.mem[0x1FE..0x200], [0x00, 0x00]);
+        assert_eq!(c.pc, 0x204);
+
+        c.i = 0x300;
+        c.exec_routine().expect("unprotected write failed");
+        assert_eq!(c.mem[0x300..0x302], [123, 0xAA]);
+    }
+
+    // Debug output shows registers and timers compactly
+    #[test]
+    fn debug_format() {
+        let mut c = Cpu::default();
+        c.load_program_from_slice(&[0x00, 0xE0]).unwrap();
+        c.i = 0x50;
+        c.dt = 60;
+        c.st = 3;
+        c.reg[0x0] = 0x12;
+        c.reg[0xF] = 0x01;
+        assert_eq!(
+            format!("{c:?}"),
+            "Cpu { pc: 0x200, i: 0x050, sp: 0, dt: 60, st: 3, \
+             v: [12 00 00 00 00 00 00 00 00 00 00 00 00 00 00 01] }"
+        );
+    }
+
     // Execute the ldxia instruction
     #[test]
     fn exec_routine_ldxia() {
@@ -1106,4 +3019,113 @@ mod tests {
         assert_eq!(c.reg[1], 2);
         assert_eq!(c.reg[2], 3);
     }
+
+    // Export CPU state as JSON
+    #[cfg(feature = "serde")]
+    #[test]
+    fn state_json() {
+        let mut c = Cpu {
+            pc: 0x204,
+            i: 0x300,
+            dt: 10,
+            ..Default::default()
+        };
+        c.reg[0xA] = 0xBE;
+        c.stk.push(0x200);
+        let state: serde_json::Value = serde_json::from_str(&c.state_json(false)).unwrap();
+        assert_eq!(state["pc"], 0x204);
+        assert_eq!(state["i"], 0x300);
+        assert_eq!(state["dt"], 10);
+        assert_eq!(state["st"], 0);
+        assert_eq!(state["registers"][0xA], 0xBE);
+        assert_eq!(state["stack"][0], 0x200);
+        assert!(state.get("memory").is_none());
+
+        let state: serde_json::Value = serde_json::from_str(&c.state_json(true)).unwrap();
+        assert_eq!(state["memory"].as_array().unwrap().len(), MEMORY_SIZE);
+        assert_eq!(state["memory"][FONT_START_ADDR], FONT[0]);
+    }
+
+    // Count executed opcodes while profiling
+    #[test]
+    fn coverage_report() {
+        let mut c = Cpu::default();
+        assert!(c.coverage_report().is_none());
+        c.set_profiling(true);
+        // LD V0, 0x01; ADD V0, 0x01; ADD V0, V0; JP 0x200
+        c.load_program_from_slice(&[0x60, 0x01, 0x70, 0x01, 0x80, 0x04, 0x12, 0x00])
+            .expect("failed to load program");
+        for _ in 0..6 {
+            c.exec_routine().expect("exec_routine failed");
+        }
+        let report = c.coverage_report().unwrap();
+        assert_eq!(report.len(), 4);
+        assert_eq!(report[&0x6000], 2);
+        assert_eq!(report[&0x7000], 2);
+        assert_eq!(report[&0x8004], 1);
+        assert_eq!(report[&0x1000], 1);
+        c.set_profiling(false);
+        assert!(c.coverage_report().is_none());
+    }
+
+    // Timers continue from a fresh tick after resuming, however long the pause was
+    #[test]
+    fn timers_resume() {
+        let mut c = Cpu {
+            dt: 10,
+            st: 10,
+            ..Default::default()
+        };
+        let almost_tick = Duration::from_nanos(TIMER_TICK as u64) - CLOCK_SPEED;
+        c.timer_tick(almost_tick);
+        c.pause();
+        // Chip8 does not tick timers while paused
+        c.resume();
+        c.timer_tick(CLOCK_SPEED * 2);
+        assert_eq!(c.dt, 10);
+        assert_eq!(c.st, 10);
+        c.timer_tick(almost_tick);
+        assert_eq!(c.dt, 9);
+        assert_eq!(c.st, 9);
+    }
+
+    proptest! {
+        // Execution never panics, whatever the contents of memory and registers
+        #[test]
+        fn exec_routine_never_panics(
+            mem in vec(any::<u8>(), MEMORY_SIZE),
+            pc in any::<u16>(),
+            reg in any::<[u8; REGISTER_COUNT]>(),
+            i in any::<u16>(),
+            hires in any::<bool>(),
+            quirks in any::<(bool, bool, bool, bool, bool, bool, bool, bool, bool)>(),
+        ) {
+            let mut c = Cpu {
+                pc,
+                reg,
+                i,
+                ..Default::default()
+            };
+            c.mem.copy_from_slice(&mem);
+            c.dct.set_hires(hires);
+            let (lores_dxy0, strict, clip_sprites, shift_quirk, extended_memory, vf_reset,
+                load_store_increment, display_wait, bit_planes) = quirks;
+            c.set_quirks(Quirks {
+                lores_dxy0,
+                strict,
+                clip_sprites,
+                shift_quirk,
+                extended_memory,
+                vf_reset,
+                load_store_increment,
+                display_wait,
+                bit_planes,
+            });
+            for _ in 0..100 {
+                if c.exec_routine().is_err() || c.is_blocking() {
+                    break;
+                }
+            }
+        }
+    }
 }
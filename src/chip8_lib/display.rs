@@ -1,11 +1,82 @@
+pub mod convert;
+
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
-const NUM_COLS: usize = SCREEN_WIDTH / 8;
-const NUM_ROWS: usize = SCREEN_HEIGHT / 8;
-pub const PIXEL_COUNT: usize = NUM_COLS * NUM_ROWS;
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+// Each byte of the frame buffer holds 8 horizontally adjacent pixels.
+// The buffer is sized to fit high resolution mode; in low resolution mode only
+// the first SCREEN_WIDTH * SCREEN_HEIGHT / 8 bytes are used.
+pub const PIXEL_COUNT: usize = HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT / 8;
+// Size of a frame buffer holding both XO-CHIP planes: the first plane, which is also
+// the screen of non XO-CHIP programs, followed by the second plane laid out the same way
+pub const FRAME_SIZE: usize = 2 * PIXEL_COUNT;
+// XO-CHIP plane selection as a bit mask, bit 0 being the first plane.
+// Only the first plane is selected by default, so non XO-CHIP programs draw to it alone.
+pub const DEFAULT_PLANE_MASK: u8 = 0b01;
+
+// Changes to the frame buffer, with the resolution it is laid out in, so that
+// a copy can be drawn at the current resolution
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrameDiff {
+    pub width: usize,
+    pub height: usize,
+    // Changed bytes as (index, new value) pairs
+    pub changes: Vec<(usize, u8)>,
+}
+
+// Colors as 0xRRGGBB for the four pixel states of the two XO-CHIP bit planes:
+// off, first plane only, second plane only, both planes
+pub type Palette = [u32; 4];
+pub const DEFAULT_PALETTE: Palette = [0x000000, 0x00FF00, 0xFF0000, 0xFFFF00];
+
+// Parameters of the 64-bit FNV-1a hash used by frame_hash
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// Outcome of the most recent draw, for debugging sprite positioning
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawReport {
+    // Some pixel went from 1 to 0, as reported in Vf
+    pub collision: bool,
+    // The sprite wrapped around the right edge to the left one
+    pub wrapped_x: bool,
+    // The sprite wrapped around the bottom edge to the top one
+    pub wrapped_y: bool,
+}
+
+// Color of a pixel, given whether it is set in each plane
+pub fn plane_color(palette: &Palette, plane1: bool, plane2: bool) -> u32 {
+    palette[plane1 as usize | (plane2 as usize) << 1]
+}
 
 pub struct DisplayController {
-    frame_buffer: [u8; PIXEL_COUNT],
+    // Both planes, as described for FRAME_SIZE
+    frame_buffer: [u8; FRAME_SIZE],
+    // Planes which draw and clear_screen act on
+    plane_mask: u8,
+    // SCHIP high resolution mode
+    hires: bool,
+    // Current resolution in pixels
+    width: usize,
+    height: usize,
+    // Frame buffer has changed since it was last taken
+    dirty: bool,
+    // Frame buffer as of the last diff, which the next diff is computed against
+    last_frame: [u8; FRAME_SIZE],
+    // Cut off sprites at the screen edges instead of wrapping them around
+    clip_sprites: bool,
+    // Number of draws which collided, if counting is enabled
+    collisions: Option<u64>,
+    // Collision and wrapping of the most recent draw
+    last_draw: DrawReport,
+}
+
+// Bring a copy of the frame buffer up to date with a diff from take_frame_diff
+pub fn apply_frame_diff(frame: &mut [u8; FRAME_SIZE], diff: &FrameDiff) {
+    for &(i, val) in &diff.changes {
+        frame[i] = val;
+    }
 }
 
 enum Direction {
@@ -16,24 +87,243 @@ enum Direction {
 impl Default for DisplayController {
     fn default() -> Self {
         Self {
-            frame_buffer: [0; NUM_COLS * NUM_ROWS],
+            frame_buffer: [0; FRAME_SIZE],
+            plane_mask: DEFAULT_PLANE_MASK,
+            hires: false,
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            dirty: false,
+            last_frame: [0; FRAME_SIZE],
+            clip_sprites: false,
+            collisions: None,
+            last_draw: DrawReport::default(),
         }
     }
 }
 
 impl DisplayController {
+    // Clear the selected planes
     pub fn clear_screen(&mut self) {
-        for i in self.frame_buffer {
-            self.frame_buffer[i as usize] = 0;
+        self.clear_planes(self.plane_mask);
+    }
+
+    fn clear_planes(&mut self, mask: u8) {
+        let (first, second) = self.frame_buffer.split_at_mut(PIXEL_COUNT);
+        if mask & 0b01 != 0 {
+            first.fill(0);
+        }
+        if mask & 0b10 != 0 {
+            second.fill(0);
+        }
+        self.dirty = true;
+    }
+
+    // Select the planes which draw and clear_screen act on, as a bit mask of the two planes
+    pub fn set_plane_mask(&mut self, mask: u8) {
+        self.plane_mask = mask & 0b11;
+    }
+
+    pub fn plane_mask(&self) -> u8 {
+        self.plane_mask
+    }
+
+    // Number of selected planes, each of which takes its own sprite data when drawing
+    pub fn selected_planes(&self) -> usize {
+        self.plane_mask.count_ones() as usize
+    }
+
+    // Returns the frame buffer if it has changed since it was last taken, marking it clean.
+    // The next diff is computed against the frame returned here, so this can be mixed
+    // with take_frame_diff.
+    pub fn take_frame_if_dirty(&mut self) -> Option<&[u8]> {
+        if !self.dirty {
+            return None;
+        }
+        self.dirty = false;
+        self.last_frame = self.frame_buffer;
+        Some(&self.frame_buffer)
+    }
+
+    // Returns the bytes which changed since the frame buffer was last taken, by either
+    // this or take_frame_if_dirty, marking it clean
+    pub fn take_frame_diff(&mut self) -> FrameDiff {
+        let mut diff = FrameDiff {
+            width: self.width,
+            height: self.height,
+            changes: vec![],
+        };
+        if !self.dirty {
+            return diff;
         }
+        self.dirty = false;
+        diff.changes = self
+            .frame_buffer
+            .iter()
+            .zip(self.last_frame.iter())
+            .enumerate()
+            .filter(|(_, (new, old))| new != old)
+            .map(|(i, (new, _))| (i, *new))
+            .collect();
+        self.last_frame = self.frame_buffer;
+        diff
+    }
+
+    // Copy of the current frame buffer with both planes, e.g. for screenshots or a frontend
+    // which connects mid-game
+    pub fn snapshot(&self) -> [u8; FRAME_SIZE] {
+        self.frame_buffer
+    }
+
+    // Replace the frame buffer with one taken by snapshot
+    pub fn restore(&mut self, buf: [u8; FRAME_SIZE]) {
+        self.frame_buffer = buf;
+        self.dirty = true;
+    }
+
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    // Switch between low and high resolution mode.
+    // The frame buffer layout depends on the resolution, so both planes are cleared.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        (self.width, self.height) = if hires {
+            (HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT)
+        } else {
+            (SCREEN_WIDTH, SCREEN_HEIGHT)
+        };
+        self.clear_planes(0b11);
+    }
+
+    pub fn set_clip_sprites(&mut self, clip_sprites: bool) {
+        self.clip_sprites = clip_sprites;
+    }
+
+    // Start or stop counting draws which set Vf, e.g. to find flickery programs
+    pub fn set_collision_counting(&mut self, enabled: bool) {
+        self.collisions = enabled.then_some(0);
+    }
+
+    // Number of draws which set Vf since counting was enabled
+    pub fn collision_count(&self) -> Option<u64> {
+        self.collisions
+    }
+
+    // Count a draw towards the collision statistics and pass its Vf through
+    fn record_collision(&mut self, vf: u8) -> u8 {
+        if let Some(count) = &mut self.collisions {
+            *count += u64::from(vf);
+        }
+        vf
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Copy the given 16x16 sprite to the selected planes, starting from position (x, y)
+    // Sprite is given as 32 bytes per selected plane, two per row: left half first, then right half.
+    // Returns value of Vf.
+    pub fn draw_16x16(&mut self, start_x: usize, start_y: usize, sprite: Vec<u8>) -> u8 {
+        assert_eq!(sprite.len(), 32 * self.selected_planes());
+        let wrap = self.wrap_report(start_x, start_y, 16, 16);
+        let vf = self.blit_planes(sprite, |dct, part| dct.blit_16x16(start_x, start_y, part));
+        self.report_draw(vf, wrap)
     }
 
-    // Copy the given sprite to the frame buffer, starting from position (x, y)
-    // If sprite is outside bounds of display, wrap it around.
+    // Copy the given sprite to the selected planes, starting from position (x, y)
+    // Starting position is taken modulo the screen dimensions.
+    // If sprite is outside bounds of display, wrap it around, or clip it if clip_sprites is set.
+    // With several planes selected, the sprite holds the rows for each plane in turn.
     // If any pixel goes from 1 to 0, set Vf to 1. Else, 0.
     // Returns value of Vf.
     pub fn draw(&mut self, start_x: usize, start_y: usize, sprite: Vec<u8>) -> u8 {
-        assert!(start_x < SCREEN_WIDTH && start_y < SCREEN_HEIGHT);
+        let rows = sprite.len() / self.selected_planes().max(1);
+        let wrap = self.wrap_report(start_x, start_y, 8, rows);
+        let vf = self.blit_planes(sprite, |dct, part| dct.blit(start_x, start_y, part));
+        self.report_draw(vf, wrap)
+    }
+
+    // Collision and wrapping of the most recent draw or draw_16x16
+    pub fn last_draw(&self) -> DrawReport {
+        self.last_draw
+    }
+
+    // Which edges a sprite of the given size at (x, y) wraps around. Clipped sprites never wrap.
+    fn wrap_report(&self, start_x: usize, start_y: usize, width: usize, rows: usize) -> DrawReport {
+        if self.clip_sprites {
+            return DrawReport::default();
+        }
+        let (start_x, start_y) = (start_x % self.width, start_y % self.height);
+        DrawReport {
+            collision: false,
+            wrapped_x: start_x + width > self.width,
+            wrapped_y: start_y + rows > self.height,
+        }
+    }
+
+    // Remember the outcome of a draw and count its collision, passing its Vf through
+    fn report_draw(&mut self, vf: u8, wrap: DrawReport) -> u8 {
+        self.last_draw = DrawReport {
+            collision: vf != 0,
+            ..wrap
+        };
+        self.record_collision(vf)
+    }
+
+    // Split sprite data evenly between the selected planes, first plane first,
+    // and blit each part onto its plane
+    fn blit_planes(&mut self, sprite: Vec<u8>, blit: impl Fn(&mut Self, Vec<u8>) -> u8) -> u8 {
+        let planes = self.selected_planes();
+        if planes == 0 {
+            return 0;
+        }
+        let len = sprite.len() / planes;
+        let mut parts = sprite.chunks_exact(len.max(1)).map(|part| part.to_vec());
+        let mut vf = 0;
+        if self.plane_mask & 0b01 != 0 {
+            vf |= blit(self, parts.next().unwrap_or_default());
+        }
+        if self.plane_mask & 0b10 != 0 {
+            // Blitting works on the first plane, so swap the second one in for the duration
+            self.swap_planes();
+            vf |= blit(self, parts.next().unwrap_or_default());
+            self.swap_planes();
+        }
+        vf
+    }
+
+    fn swap_planes(&mut self) {
+        let (first, second) = self.frame_buffer.split_at_mut(PIXEL_COUNT);
+        first.swap_with_slice(second);
+    }
+
+    // Blit a 16x16 sprite as described for draw_16x16, without counting collisions
+    fn blit_16x16(&mut self, start_x: usize, start_y: usize, sprite: Vec<u8>) -> u8 {
+        let left: Vec<u8> = sprite.iter().step_by(2).copied().collect();
+        let right: Vec<u8> = sprite.iter().skip(1).step_by(2).copied().collect();
+        let (start_x, start_y) = (start_x % self.width, start_y % self.height);
+        let vf_left = self.blit(start_x, start_y, left);
+        // Right half is entirely off screen
+        if self.clip_sprites && start_x + 8 >= self.width {
+            return vf_left;
+        }
+        let vf_right = self.blit((start_x + 8) % self.width, start_y, right);
+        vf_left | vf_right
+    }
+
+    // XOR a sprite onto the frame buffer as described for draw, without counting collisions
+    fn blit(&mut self, start_x: usize, start_y: usize, mut sprite: Vec<u8>) -> u8 {
+        let (start_x, start_y) = (start_x % self.width, start_y % self.height);
+        if self.clip_sprites {
+            sprite.truncate(self.height - start_y);
+        }
+        self.dirty = true;
         let mut collision = false;
         // Check if x will wrap to next byte in frame_buffer
         // if it does, do XOR in two steps
@@ -41,50 +331,96 @@ impl DisplayController {
         if x_offset != 0 {
             // Start with first frame_buffer chunk, i.e. left side of sprite
             for (i, &s_byte) in sprite.iter().enumerate() {
-                let y = (start_y + i) % SCREEN_HEIGHT;
+                let y = (start_y + i) % self.height;
                 let chunk_idx: usize = self.get_idx(start_x, y);
                 let orig_chunk: u8 = self.frame_buffer[chunk_idx];
                 self.frame_buffer[chunk_idx] =
                     self.xor_side_from_offset(orig_chunk, s_byte, x_offset, Direction::Right);
-                // Check if bit was unset
-                if !collision {
-                    collision = self.bit_unset(orig_chunk, self.frame_buffer[chunk_idx]);
-                }
+                // Check if any bit was unset
+                collision |= bits_unset(orig_chunk, self.frame_buffer[chunk_idx]);
+            }
+            // Blit second frame_buffer chunk, i.e. right side of sprite,
+            // which may wrap around to the left edge
+            let right_x = start_x + (8 - x_offset as usize);
+            if self.clip_sprites && right_x >= self.width {
+                return collision as u8;
             }
-            // Blit second frame_buffer chunk, i.e. right side of sprite
             for (i, &s_byte) in sprite.iter().enumerate() {
-                let y = (start_y + i) % SCREEN_HEIGHT;
-                let chunk_idx: usize = self.get_idx(start_x + (8 - x_offset as usize), y);
+                let y = (start_y + i) % self.height;
+                let chunk_idx: usize = self.get_idx(right_x, y);
                 let orig_chunk: u8 = self.frame_buffer[chunk_idx];
                 self.frame_buffer[chunk_idx] =
                     self.xor_side_from_offset(orig_chunk, s_byte, x_offset, Direction::Left);
-                // Check if bit was unset
-                if !collision {
-                    collision = self.bit_unset(orig_chunk, self.frame_buffer[chunk_idx]);
-                }
+                // Check if any bit was unset
+                collision |= bits_unset(orig_chunk, self.frame_buffer[chunk_idx]);
             }
         }
         // Else, simply XOR the sprite onto the frame buffer
         else {
             // For each row (y)
             for (i, s_byte) in sprite.iter().enumerate() {
-                let y = (start_y + i) % SCREEN_HEIGHT;
+                let y = (start_y + i) % self.height;
                 // Index of current chunk of frame buffer to be XORed
                 let chunk_idx: usize = self.get_idx(start_x, y);
                 let orig_chunk: u8 = self.frame_buffer[chunk_idx];
                 self.frame_buffer[chunk_idx] ^= s_byte;
-                // For each pixel in row, check if bit was unset
-                if !collision {
-                    collision = self.bit_unset(orig_chunk, self.frame_buffer[chunk_idx]);
-                }
+                // Check if any pixel in row was unset
+                collision |= bits_unset(orig_chunk, self.frame_buffer[chunk_idx]);
             }
         }
         collision as u8
     }
 
-    // Return the index in frame_buffer of the given x and y coordinates
+    // Whether the pixel at (x, y) is set in either plane. Coordinates wrap around the screen.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let (first, second) = self.plane_pixels(x, y);
+        first || second
+    }
+
+    // Whether the pixel at (x, y) is set in the first and in the second plane,
+    // which together select its palette entry. Coordinates wrap around the screen.
+    pub fn plane_pixels(&self, x: usize, y: usize) -> (bool, bool) {
+        let (x, y) = (x % self.width, y % self.height);
+        let idx = self.get_idx(x, y);
+        let mask = 0x80 >> (x % 8);
+        (
+            self.frame_buffer[idx] & mask != 0,
+            self.frame_buffer[PIXEL_COUNT + idx] & mask != 0,
+        )
+    }
+
+    /// Raw frame buffer byte holding the pixel at (x, y), for checking drawing from
+    /// outside this module. Coordinates wrap around the screen.
+    #[cfg(feature = "testing")]
+    pub fn chunk_at(&self, x: usize, y: usize) -> u8 {
+        self.frame_buffer[self.get_idx(x, y)]
+    }
+
+    // Render the screen as rows of '#' (set) and '.' (unset) pixels, e.g. for logging
+    pub fn to_ascii(&self) -> String {
+        let mut ascii = String::with_capacity((self.width + 1) * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                ascii.push(if self.pixel(x, y) { '#' } else { '.' });
+            }
+            ascii.push('\n');
+        }
+        ascii
+    }
+
+    // Stable 64-bit FNV-1a hash of both planes, e.g. for golden image tests
+    pub fn frame_hash(&self) -> u64 {
+        self.frame_buffer
+            .iter()
+            .fold(FNV_OFFSET_BASIS, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+            })
+    }
+
+    // Return the index in frame_buffer of the given x and y coordinates.
+    // Coordinates wrap around the screen, so the index is always within the current resolution.
     fn get_idx(&self, x: usize, y: usize) -> usize {
-        (y * NUM_COLS + x) / 8
+        (y % self.height) * (self.width / 8) + (x % self.width) / 8
     }
 
     // XOR byte1 with byte2, retaining bits of byte1 either left or right of offset.
@@ -110,27 +446,25 @@ impl DisplayController {
         ret += save_bits;
         ret
     }
+}
 
-    // Returns true if a bit in byte1 has been unset in byte2
-    fn bit_unset(&self, byte1: u8, byte2: u8) -> bool {
-        for j in 0..8 {
-            // Original bit was 0 anyway, so cannot be unset
-            if (1 << j) & byte1 == 0 {
-                continue;
-            }
-            // Is frame buffer bit now at 0?
-            if (1 << j) & byte2 == 0 {
-                return true;
-            }
-        }
-        false
-    }
+// Whether any bit set in byte1 is unset in byte2, i.e. a pixel was erased.
+// Compares all 8 pixels of a frame buffer chunk at once.
+fn bits_unset(byte1: u8, byte2: u8) -> bool {
+    byte1 & !byte2 != 0
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::cpu::FONT;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    // Collision check as it was done before bits_unset, one bit at a time
+    fn bit_unset_per_bit(byte1: u8, byte2: u8) -> bool {
+        (0..8).any(|j| (1 << j) & byte1 != 0 && (1 << j) & byte2 == 0)
+    }
 
     // Draw a sprite to frame buffer that evenly fits into a single byte
     #[test]
@@ -177,4 +511,411 @@ mod tests {
         // there was a collision and Vf must be 1.
         assert_eq!(vf, 1);
     }
+
+    // Draw a 16x16 sprite, which is split into two 8 pixel wide columns
+    #[test]
+    fn draw_16x16() {
+        let mut dct = DisplayController::default();
+        let sprite: Vec<u8> = (0..32)
+            .map(|i| if i % 2 == 0 { 0xF0 } else { 0x0F })
+            .collect();
+        let vf = dct.draw_16x16(0, 0, sprite);
+        assert_eq!(vf, 0);
+        for y in 0..16 {
+            assert_eq!(dct.frame_buffer[dct.get_idx(0, y)], 0xF0);
+            assert_eq!(dct.frame_buffer[dct.get_idx(8, y)], 0x0F);
+        }
+    }
+
+    // Draw a sprite at the bottom right corner in low resolution mode, wrapping to the top
+    #[test]
+    fn draw_wrap_lores() {
+        let mut dct = DisplayController::default();
+        let sprite: Vec<u8> = vec![0x01, 0x02, 0x03, 0x04];
+        let vf = dct.draw(56, 30, sprite);
+        assert_eq!(vf, 0);
+        assert_eq!(dct.get_idx(56, 31), SCREEN_WIDTH * SCREEN_HEIGHT / 8 - 1);
+        assert_eq!(dct.frame_buffer[dct.get_idx(56, 30)], 0x01);
+        assert_eq!(dct.frame_buffer[dct.get_idx(56, 31)], 0x02);
+        assert_eq!(dct.frame_buffer[dct.get_idx(56, 0)], 0x03);
+        assert_eq!(dct.frame_buffer[dct.get_idx(56, 1)], 0x04);
+        // Nothing may be written past the low resolution area
+        assert!(dct.frame_buffer[SCREEN_WIDTH * SCREEN_HEIGHT / 8..]
+            .iter()
+            .all(|&b| b == 0));
+    }
+
+    // Draw a sprite at the bottom right corner in high resolution mode, wrapping to the top
+    #[test]
+    fn draw_wrap_hires() {
+        let mut dct = DisplayController::default();
+        dct.set_hires(true);
+        assert_eq!(
+            (dct.width(), dct.height()),
+            (HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT)
+        );
+        let sprite: Vec<u8> = vec![0x01, 0x02, 0x03, 0x04];
+        let vf = dct.draw(120, 62, sprite);
+        assert_eq!(vf, 0);
+        assert_eq!(dct.get_idx(120, 63), PIXEL_COUNT - 1);
+        assert_eq!(dct.frame_buffer[dct.get_idx(120, 62)], 0x01);
+        assert_eq!(dct.frame_buffer[dct.get_idx(120, 63)], 0x02);
+        assert_eq!(dct.frame_buffer[dct.get_idx(120, 0)], 0x03);
+        assert_eq!(dct.frame_buffer[dct.get_idx(120, 1)], 0x04);
+        // Rows in high resolution mode are 16 bytes wide
+        assert_eq!(dct.get_idx(120, 1), 31);
+    }
+
+    // Frame is only handed out once after it changes
+    #[test]
+    fn take_frame_if_dirty() {
+        let mut dct = DisplayController::default();
+        assert!(dct.take_frame_if_dirty().is_none());
+        let sprite: Vec<u8> = Vec::from(&FONT[0..5]);
+        dct.draw(0, 0, sprite);
+        assert!(dct.dirty);
+        let frame = dct.take_frame_if_dirty().expect("frame should be dirty");
+        assert_eq!(frame[0], 0xF0);
+        assert!(!dct.dirty);
+        assert!(dct.take_frame_if_dirty().is_none());
+        dct.clear_screen();
+        assert!(dct.dirty);
+        assert_eq!(dct.take_frame_if_dirty().unwrap()[0], 0);
+    }
+
+    // Only bytes touched by a draw are in the diff
+    #[test]
+    fn take_frame_diff() {
+        let mut dct = DisplayController::default();
+        assert!(dct.take_frame_diff().changes.is_empty());
+        // Sprite straddles two bytes of each row
+        dct.draw(9, 2, vec![0xFF; 5]);
+        let diff = dct.take_frame_diff();
+        assert_eq!((diff.width, diff.height), (SCREEN_WIDTH, SCREEN_HEIGHT));
+        let mut indices: Vec<usize> = diff.changes.iter().map(|&(i, _)| i).collect();
+        indices.sort();
+        let mut expected = vec![];
+        for y in 2..7 {
+            expected.push(dct.get_idx(9, y));
+            expected.push(dct.get_idx(16, y));
+        }
+        expected.sort();
+        assert_eq!(indices, expected);
+        assert!(dct.take_frame_diff().changes.is_empty());
+
+        // A copy kept up to date with diffs matches the frame buffer
+        let mut frame = [0; FRAME_SIZE];
+        apply_frame_diff(&mut frame, &diff);
+        assert_eq!(frame, dct.frame_buffer);
+
+        // Diffs carry the resolution. Changing it clears the sprite.
+        dct.set_hires(true);
+        let diff = dct.take_frame_diff();
+        assert_eq!(
+            (diff.width, diff.height),
+            (HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT)
+        );
+        assert_eq!(diff.changes.len(), 10);
+        assert!(diff.changes.iter().all(|&(_, val)| val == 0));
+    }
+
+    // Diffs continue from a frame taken whole, so a consumer can mix both
+    #[test]
+    fn take_frame_mixed() {
+        let mut dct = DisplayController::default();
+        dct.draw(0, 0, vec![0xF0]);
+        let mut frame = [0; FRAME_SIZE];
+        frame.copy_from_slice(dct.take_frame_if_dirty().expect("frame should be dirty"));
+        dct.draw(8, 1, vec![0x0F]);
+        let diff = dct.take_frame_diff();
+        assert_eq!(diff.changes, [(dct.get_idx(8, 1), 0x0F)]);
+        apply_frame_diff(&mut frame, &diff);
+        assert_eq!(frame, dct.frame_buffer);
+
+        // A change handed out with the whole frame is not repeated in the next diff
+        dct.draw(0, 0, vec![0xF0]);
+        assert!(dct.take_frame_if_dirty().is_some());
+        assert!(dct.take_frame_diff().changes.is_empty());
+    }
+
+    // Draw at the bottom right pixel, wrapping around both edges
+    #[test]
+    fn draw_bottom_right_wrap() {
+        let mut dct = DisplayController::default();
+        let vf = dct.draw(63, 31, vec![0xFF, 0xFF, 0xFF]);
+        assert_eq!(vf, 0);
+        for y in [31, 0, 1] {
+            assert_eq!(dct.frame_buffer[dct.get_idx(63, y)], 0x01);
+            assert_eq!(dct.frame_buffer[dct.get_idx(0, y)], 0xFE);
+        }
+        assert_eq!(dct.frame_buffer.iter().filter(|&&b| b != 0).count(), 6);
+    }
+
+    // The last draw reports which edges the sprite wrapped around, and clipped sprites never wrap
+    #[test]
+    fn draw_wrap_report() {
+        let mut dct = DisplayController::default();
+        assert_eq!(dct.last_draw(), DrawReport::default());
+        dct.draw(60, 30, vec![0xFF, 0xFF, 0xFF]);
+        let report = DrawReport {
+            collision: false,
+            wrapped_x: true,
+            wrapped_y: true,
+        };
+        assert_eq!(dct.last_draw(), report);
+        // Fits exactly against the right edge, but wraps at the bottom and collides
+        assert_eq!(dct.draw(56, 31, vec![0xFF, 0xFF]), 1);
+        let report = DrawReport {
+            collision: true,
+            wrapped_x: false,
+            wrapped_y: true,
+        };
+        assert_eq!(dct.last_draw(), report);
+        dct.set_hires(true);
+        dct.draw_16x16(120, 0, vec![0xFF; 32]);
+        assert!(dct.last_draw().wrapped_x && !dct.last_draw().wrapped_y);
+        dct.set_clip_sprites(true);
+        dct.draw(63, 63, vec![0xFF, 0xFF]);
+        assert_eq!(dct.last_draw(), DrawReport::default());
+    }
+
+    // Draw at the bottom right pixel, clipping at both edges
+    #[test]
+    fn draw_bottom_right_clip() {
+        let mut dct = DisplayController::default();
+        dct.set_clip_sprites(true);
+        let vf = dct.draw(63, 31, vec![0xFF, 0xFF, 0xFF]);
+        assert_eq!(vf, 0);
+        assert_eq!(dct.frame_buffer[dct.get_idx(63, 31)], 0x01);
+        assert_eq!(dct.frame_buffer.iter().filter(|&&b| b != 0).count(), 1);
+    }
+
+    // A sprite straddling the right edge at an offset wraps its right side to the left edge,
+    // in both resolutions
+    #[test]
+    fn draw_offset_right_edge_wrap() {
+        let mut dct = DisplayController::default();
+        for hires in [false, true] {
+            dct.set_hires(hires);
+            let x = dct.width() - 2;
+            let vf = dct.draw(x, 0, vec![0xFF, 0x81]);
+            assert_eq!(vf, 0);
+            assert_eq!(dct.frame_buffer[dct.get_idx(x, 0)], 0x03);
+            assert_eq!(dct.frame_buffer[dct.get_idx(0, 0)], 0xFC);
+            assert_eq!(dct.frame_buffer[dct.get_idx(x, 1)], 0x02);
+            assert_eq!(dct.frame_buffer[dct.get_idx(0, 1)], 0x04);
+            assert_eq!(dct.frame_buffer.iter().filter(|&&b| b != 0).count(), 4);
+        }
+        // Indices past the edges wrap around rather than leaving the screen
+        assert_eq!(dct.get_idx(HIRES_SCREEN_WIDTH + 8, 0), 1);
+        assert_eq!(dct.get_idx(0, HIRES_SCREEN_HEIGHT), 0);
+    }
+
+    // Clearing with only the second plane selected leaves the first plane untouched
+    #[test]
+    fn clear_selected_planes() {
+        let mut dct = DisplayController::default();
+        assert_eq!(dct.selected_planes(), 1);
+        // Draw to both planes, with the rows for each plane in turn
+        dct.set_plane_mask(0b11);
+        let vf = dct.draw(0, 0, vec![0xF0, 0x90, 0x0F, 0x09]);
+        assert_eq!(vf, 0);
+        assert_eq!(dct.frame_buffer[dct.get_idx(0, 0)], 0xF0);
+        assert_eq!(dct.frame_buffer[dct.get_idx(0, 1)], 0x90);
+        assert_eq!(dct.frame_buffer[PIXEL_COUNT + dct.get_idx(0, 0)], 0x0F);
+        assert_eq!(dct.frame_buffer[PIXEL_COUNT + dct.get_idx(0, 1)], 0x09);
+        let first_plane = dct.frame_buffer[..PIXEL_COUNT].to_vec();
+
+        dct.set_plane_mask(0b10);
+        dct.clear_screen();
+        assert_eq!(dct.frame_buffer[PIXEL_COUNT..], [0; PIXEL_COUNT]);
+        assert_eq!(dct.frame_buffer[..PIXEL_COUNT], first_plane);
+        // Collisions are reported from the second plane too
+        assert_eq!(dct.draw(0, 0, vec![0x80]), 0);
+        assert_eq!(dct.draw(0, 0, vec![0x80]), 1);
+        assert_eq!(dct.frame_buffer[..PIXEL_COUNT], first_plane);
+
+        // With no planes selected, nothing is drawn or cleared
+        dct.set_plane_mask(0);
+        assert_eq!(dct.draw(0, 0, vec![]), 0);
+        dct.clear_screen();
+        assert_eq!(dct.frame_buffer[..PIXEL_COUNT], first_plane);
+        // Changing resolution clears both planes
+        dct.set_plane_mask(0b10);
+        dct.draw(0, 0, vec![0x80]);
+        dct.set_hires(true);
+        assert_eq!(dct.frame_buffer, [0; FRAME_SIZE]);
+        assert_eq!(dct.frame_buffer[PIXEL_COUNT..], [0; PIXEL_COUNT]);
+    }
+
+    // The frame hash of a drawn sprite is stable and changes when the frame does
+    #[test]
+    fn frame_hash() {
+        let mut dct = DisplayController::default();
+        assert_eq!(dct.frame_hash(), 0x28c31cf8df2ec325);
+        dct.draw(0, 0, vec![0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        assert_eq!(dct.frame_hash(), 0x9144fff673458eb5);
+        dct.clear_screen();
+        assert_eq!(dct.frame_hash(), 0x28c31cf8df2ec325);
+        // The second plane is part of the hash
+        dct.set_plane_mask(0b10);
+        dct.draw(0, 0, vec![0x80]);
+        assert_eq!(dct.frame_hash(), 0x661f8b2c8136c3a5);
+    }
+
+    // Coordinates beyond the screen are taken modulo the screen dimensions
+    #[test]
+    fn draw_coordinates_modulo() {
+        let mut dct = DisplayController::default();
+        let vf = dct.draw(64 + 8, 32 + 2, vec![0xAA]);
+        assert_eq!(vf, 0);
+        assert_eq!(dct.frame_buffer[dct.get_idx(8, 2)], 0xAA);
+    }
+
+    // Restore the frame buffer from a snapshot after clearing the screen
+    #[test]
+    fn snapshot_restore() {
+        let mut dct = DisplayController::default();
+        dct.draw(8, 4, vec![0xF0, 0x90]);
+        dct.set_plane_mask(0b10);
+        dct.draw(0, 0, vec![0x80]);
+        let snapshot = dct.snapshot();
+        dct.set_plane_mask(0b11);
+        dct.clear_screen();
+        assert_eq!(dct.frame_buffer, [0; FRAME_SIZE]);
+        dct.take_frame_diff();
+        dct.restore(snapshot);
+        assert_eq!(dct.frame_buffer[dct.get_idx(8, 4)], 0xF0);
+        assert_eq!(dct.frame_buffer[dct.get_idx(8, 5)], 0x90);
+        assert_eq!(dct.plane_pixels(0, 0), (false, true));
+        // Restored pixels are reported as changed, in both planes
+        let diff = dct.take_frame_diff();
+        assert_eq!(diff.changes.len(), 3);
+        assert!(diff.changes.contains(&(PIXEL_COUNT, 0x80)));
+    }
+
+    // Each combination of planes maps to its own palette entry
+    #[test]
+    fn plane_color_mapping() {
+        let palette = [0x111111, 0x222222, 0x333333, 0x444444];
+        assert_eq!(plane_color(&palette, false, false), 0x111111);
+        assert_eq!(plane_color(&palette, true, false), 0x222222);
+        assert_eq!(plane_color(&palette, false, true), 0x333333);
+        assert_eq!(plane_color(&palette, true, true), 0x444444);
+    }
+
+    // Render the font '0' at the origin as ASCII
+    #[test]
+    fn to_ascii() {
+        let mut dct = DisplayController::default();
+        dct.draw(0, 0, FONT[0..5].to_vec());
+        let ascii = dct.to_ascii();
+        let rows: Vec<&str> = ascii.lines().collect();
+        assert_eq!(rows.len(), SCREEN_HEIGHT);
+        assert!(rows.iter().all(|row| row.len() == SCREEN_WIDTH));
+        assert_eq!(&rows[0][..5], "####.");
+        assert_eq!(&rows[1][..5], "#..#.");
+        assert_eq!(&rows[2][..5], "#..#.");
+        assert_eq!(&rows[3][..5], "#..#.");
+        assert_eq!(&rows[4][..5], "####.");
+        assert_eq!(ascii.matches('#').count(), 14);
+    }
+
+    // A tall sprite near the bottom wraps to the top and collides with a sprite there
+    #[test]
+    fn draw_tall_wrap_collision() {
+        let mut dct = DisplayController::default();
+        assert_eq!(dct.draw(0, 5, vec![0x80]), 0);
+        // Rows 28-31 are empty, rows 0-10 wrap to the top; only row 5 overlaps
+        assert_eq!(dct.draw(0, 28, vec![0x80; 15]), 1);
+        assert!(!dct.pixel(0, 5));
+        assert!(dct.pixel(0, 4) && dct.pixel(0, 6) && dct.pixel(0, 31));
+        assert!(!dct.pixel(0, 11));
+    }
+
+    // Same as above with a sprite which straddles two bytes of the frame buffer
+    #[test]
+    fn draw_tall_wrap_collision_offset() {
+        let mut dct = DisplayController::default();
+        // Pixel (11, 2) is in the right hand byte of a sprite drawn at x = 4
+        assert_eq!(dct.draw(11, 2, vec![0x80]), 0);
+        assert_eq!(dct.draw(4, 30, vec![0x01; 5]), 1);
+        assert!(!dct.pixel(11, 2));
+        assert!(dct.pixel(11, 30) && dct.pixel(11, 1) && dct.pixel(11, 0));
+        // Wrapping past lit pixels which are not overlapped does not set VF
+        let mut dct = DisplayController::default();
+        dct.draw(11, 2, vec![0x80]);
+        assert_eq!(dct.draw(4, 30, vec![0x01, 0x01, 0x01, 0x01, 0x00]), 0);
+        assert!(dct.pixel(11, 2));
+    }
+
+    // A 16x16 sprite wrapping past the bottom right corner in high resolution mode
+    // collides with a sprite in the top left corner
+    #[test]
+    fn draw_16x16_wrap_collision() {
+        let mut dct = DisplayController::default();
+        dct.set_hires(true);
+        assert_eq!(dct.draw(1, 1, vec![0x40]), 0);
+        assert_eq!(dct.draw_16x16(120, 60, vec![0xFF; 32]), 1);
+        assert!(!dct.pixel(2, 1));
+        assert!(dct.pixel(0, 0) && dct.pixel(127, 63) && dct.pixel(7, 11));
+        assert!(!dct.pixel(8, 0) && !dct.pixel(0, 12));
+    }
+
+    // Only draws which set Vf are counted, once per draw, while counting is enabled
+    #[test]
+    fn collision_count() {
+        let mut dct = DisplayController::default();
+        assert_eq!(dct.collision_count(), None);
+        dct.draw(0, 0, vec![0xFF]);
+        dct.set_collision_counting(true);
+        assert_eq!(dct.collision_count(), Some(0));
+        assert_eq!(dct.draw(4, 0, vec![0xFF]), 1);
+        assert_eq!(dct.draw(20, 0, vec![0xFF]), 0);
+        assert_eq!(dct.draw(20, 0, vec![0xFF, 0xFF]), 1);
+        assert_eq!(dct.collision_count(), Some(2));
+        // Both halves of a 16x16 sprite collide, but it is one draw
+        dct.draw_16x16(0, 0, vec![0xFF; 32]);
+        assert_eq!(dct.collision_count(), Some(3));
+        dct.set_collision_counting(false);
+        assert_eq!(dct.collision_count(), None);
+    }
+
+    // The bitwise collision check agrees with the per-bit one for every pair of bytes
+    #[test]
+    fn bits_unset_matches_per_bit() {
+        for byte1 in 0..=u8::MAX {
+            for byte2 in 0..=u8::MAX {
+                assert_eq!(
+                    bits_unset(byte1, byte2),
+                    bit_unset_per_bit(byte1, byte2),
+                    "{byte1:#04X} {byte2:#04X}"
+                );
+            }
+        }
+    }
+
+    proptest! {
+        // Vf from draw matches the per-bit check applied to the frame buffer before and after
+        #[test]
+        fn draw_collision_matches_per_bit(
+            frame in vec(any::<u8>(), PIXEL_COUNT),
+            sprite in vec(any::<u8>(), 0..16),
+            x in 0..256usize,
+            y in 0..128usize,
+            hires in any::<bool>(),
+            clip_sprites in any::<bool>(),
+        ) {
+            let mut dct = DisplayController::default();
+            dct.set_hires(hires);
+            dct.set_clip_sprites(clip_sprites);
+            dct.frame_buffer[..PIXEL_COUNT].copy_from_slice(&frame);
+            let vf = dct.draw(x, y, sprite);
+            let expected = frame
+                .iter()
+                .zip(dct.frame_buffer)
+                .any(|(&before, after)| bit_unset_per_bit(before, after));
+            prop_assert_eq!(vf, expected as u8);
+        }
+    }
 }
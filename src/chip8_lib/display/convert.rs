@@ -0,0 +1,88 @@
+// Unpacking of the 1-bit frame buffer into the pixel formats frontends draw with.
+// Each function takes a frame buffer as stored by DisplayController, e.g. from
+// frame_snapshot, along with the current resolution, and returns pixels row by row.
+// Only the first plane is read, so the XO-CHIP second plane does not show up.
+
+// Whether pixel (x, y) is set in a packed frame buffer of the given width.
+// Pixels past the end of a short buffer are off.
+fn lit(buf: &[u8], width: usize, x: usize, y: usize) -> bool {
+    let byte = buf.get(y * (width / 8) + x / 8).copied().unwrap_or(0);
+    byte & (0x80 >> (x % 8)) != 0
+}
+
+// Apply f to every pixel, row by row
+fn map_pixels<T>(buf: &[u8], width: usize, height: usize, f: impl Fn(bool) -> T) -> Vec<T> {
+    let mut out = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            out.push(f(lit(buf, width, x, y)));
+        }
+    }
+    out
+}
+
+/// One byte per pixel, 1 for a lit pixel and 0 otherwise
+pub fn unpack(buf: &[u8], width: usize, height: usize) -> Vec<u8> {
+    map_pixels(buf, width, height, |lit| lit as u8)
+}
+
+/// One byte per pixel, 255 for a lit pixel and 0 otherwise, e.g. for terminal frontends
+pub fn to_grayscale(buf: &[u8], width: usize, height: usize) -> Vec<u8> {
+    map_pixels(buf, width, height, |lit| if lit { u8::MAX } else { 0 })
+}
+
+/// Four bytes per pixel in R, G, B, A order, fully opaque, e.g. for RGBA8888 textures.
+/// Colors are given as 0xRRGGBB, like palette entries.
+pub fn to_rgba(buf: &[u8], width: usize, height: usize, fg: u32, bg: u32) -> Vec<u8> {
+    map_pixels(buf, width, height, |lit| if lit { fg } else { bg })
+        .into_iter()
+        .flat_map(|rgb| {
+            let [_, r, g, b] = rgb.to_be_bytes();
+            [r, g, b, u8::MAX]
+        })
+        .collect()
+}
+
+/// One 0xAARRGGBB word per pixel, fully opaque.
+/// Colors are given as 0xRRGGBB, like palette entries.
+pub fn to_argb(buf: &[u8], width: usize, height: usize, fg: u32, bg: u32) -> Vec<u32> {
+    map_pixels(buf, width, height, |lit| {
+        let rgb = if lit { fg } else { bg };
+        0xFF000000 | (rgb & 0xFFFFFF)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 16x2 buffer with the leftmost and rightmost pixels of the first row lit,
+    // and every other pixel of the second row
+    const BUF: [u8; 4] = [0x80, 0x01, 0xAA, 0xAA];
+
+    // Unpack a known buffer into one byte per pixel
+    #[test]
+    fn unpack_and_grayscale() {
+        let bits = unpack(&BUF, 16, 2);
+        assert_eq!(bits.len(), 32);
+        assert_eq!(bits[..16], [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(bits[16..20], [1, 0, 1, 0]);
+        let gray = to_grayscale(&BUF, 16, 2);
+        assert_eq!(gray[..2], [255, 0]);
+        assert_eq!(gray[15], 255);
+        // Pixels missing from a short buffer are off
+        assert_eq!(unpack(&BUF[..1], 16, 2)[15], 0);
+    }
+
+    // Convert a known buffer into color formats
+    #[test]
+    fn rgba_and_argb() {
+        let rgba = to_rgba(&BUF, 16, 2, 0x12AB34, 0x000080);
+        assert_eq!(rgba.len(), 16 * 2 * 4);
+        assert_eq!(rgba[..8], [0x12, 0xAB, 0x34, 0xFF, 0x00, 0x00, 0x80, 0xFF]);
+        let argb = to_argb(&BUF, 16, 2, 0x12AB34, 0x000080);
+        assert_eq!(argb.len(), 32);
+        assert_eq!(argb[..2], [0xFF12AB34, 0xFF000080]);
+        assert_eq!(argb[15], 0xFF12AB34);
+    }
+}
@@ -1,4 +1,9 @@
-#[derive(PartialEq, Eq)]
+use log::warn;
+
+// Number of keys on the keypad, 0x0 - 0xF
+pub const KEY_COUNT: u8 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum KeyStatus {
     Pressed,
     Unpressed,
@@ -9,22 +14,86 @@ pub struct InputController {
     // Bit flag representing the state of keys '0' (0x01) - 'F' (0x80)
     // Set bit means pressed, unset not pressed
     key_state: u16,
+    // Number of frames a key keeps reading as pressed after release; 0 disables sticky keys
+    sticky_frames: u8,
+    // Frames left until each released key stops reading as pressed
+    sticky_countdown: [u8; 16],
+    // Record how long keys are held, e.g. for input latency analysis
+    track_hold: bool,
+    // Frames counted by frame_tick while track_hold is set
+    frame: u64,
+    // Frame at which each currently pressed key was pressed
+    press_frame: [Option<u64>; 16],
+    // Number of frames each key was held for, the last time it was released
+    last_held: [Option<u64>; 16],
 }
 
 impl InputController {
     // Checks whether numerical key from 0-F is pressed
-    // Assumes key is max 4 bits long
+    // Keys above 0xF don't exist, so they are never pressed
     pub fn key_pressed(&self, key: u8) -> bool {
-        (self.key_state & (1 << key)) > 0
+        key < KEY_COUNT && (self.keys() & (1 << key)) > 0
     }
     pub fn press_key(&mut self, key: u8) {
+        if !valid_key(key) {
+            return;
+        }
+        if self.track_hold && self.key_state & (1 << key) == 0 {
+            self.press_frame[key as usize] = Some(self.frame);
+        }
         self.key_state |= 1 << key;
     }
     pub fn unpress_key(&mut self, key: u8) {
-        self.key_state ^= 1 << key;
+        if !valid_key(key) {
+            return;
+        }
+        if self.key_pressed(key) {
+            self.sticky_countdown[key as usize] = self.sticky_frames;
+        }
+        if let Some(pressed) = self.press_frame[key as usize].take() {
+            self.last_held[key as usize] = Some(self.frame - pressed);
+        }
+        self.key_state &= !(1 << key);
     }
+    // Keys which read as pressed, including released keys which are still sticky
     pub fn keys(&self) -> u16 {
-        self.key_state
+        let sticky = self
+            .sticky_countdown
+            .iter()
+            .enumerate()
+            .filter(|(_, &frames)| frames > 0)
+            .fold(0, |keys, (key, _)| keys | (1 << key));
+        self.key_state | sticky
+    }
+    // Keep released keys reading as pressed for the given number of frames
+    pub fn set_sticky_frames(&mut self, frames: u8) {
+        self.sticky_frames = frames;
+    }
+    // Start or stop recording how long keys are held. Stopping forgets all recorded durations.
+    pub fn set_track_hold(&mut self, enabled: bool) {
+        self.track_hold = enabled;
+        self.press_frame = [None; 16];
+        self.last_held = [None; 16];
+    }
+    // Number of frames a key has been held for, or was held for the last time it was released.
+    // None if hold tracking is disabled or the key has not been pressed since it was enabled.
+    pub fn key_held_duration(&self, key: u8) -> Option<u64> {
+        if !self.track_hold || key >= KEY_COUNT {
+            return None;
+        }
+        match self.press_frame[key as usize] {
+            Some(pressed) => Some(self.frame - pressed),
+            None => self.last_held[key as usize],
+        }
+    }
+    // Count down sticky keys; called once per frame
+    pub fn frame_tick(&mut self) {
+        if self.track_hold {
+            self.frame += 1;
+        }
+        for frames in self.sticky_countdown.iter_mut() {
+            *frames = frames.saturating_sub(1);
+        }
     }
     pub fn update_key(&mut self, key: u8, state: &KeyStatus) {
         match state {
@@ -34,13 +103,25 @@ impl InputController {
     }
 }
 
+// Keys above 0xF are ignored, as they would not fit in the key state
+fn valid_key(key: u8) -> bool {
+    if key >= KEY_COUNT {
+        warn!("Ignoring key {key:#X}, which is not on the keypad.");
+        return false;
+    }
+    true
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn key_pressed() {
-        let ict = InputController { key_state: 0xAAAA };
+        let ict = InputController {
+            key_state: 0xAAAA,
+            ..Default::default()
+        };
         assert!(!ict.key_pressed(0x0));
         assert!(ict.key_pressed(0x1));
         assert!(!ict.key_pressed(0x2));
@@ -66,5 +147,65 @@ mod tests {
         assert!(ict.key_pressed(0xA));
         ict.unpress_key(0xA);
         assert!(!ict.key_pressed(0xA));
+        // Releasing a key which is not pressed leaves it unpressed
+        ict.unpress_key(0xA);
+        assert!(!ict.key_pressed(0xA));
+    }
+
+    // Released keys read as pressed for the configured number of frames
+    #[test]
+    fn sticky_keys() {
+        let mut ict = InputController::default();
+        ict.set_sticky_frames(3);
+        ict.press_key(0x4);
+        ict.frame_tick();
+        ict.unpress_key(0x4);
+        for _ in 0..3 {
+            assert!(ict.key_pressed(0x4));
+            assert_eq!(ict.keys(), 1 << 0x4);
+            ict.frame_tick();
+        }
+        assert!(!ict.key_pressed(0x4));
+        assert_eq!(ict.keys(), 0);
+    }
+
+    // Keys above 0xF are ignored without affecting valid keys
+    #[test]
+    fn invalid_keys() {
+        let mut ict = InputController::default();
+        for key in [0x10, 0xFF] {
+            ict.press_key(key);
+            assert!(!ict.key_pressed(key));
+            assert_eq!(ict.keys(), 0);
+            ict.unpress_key(key);
+            assert_eq!(ict.keys(), 0);
+        }
+    }
+
+    // Hold durations are counted in frames, and only while tracking is enabled
+    #[test]
+    fn key_held_duration() {
+        let mut ict = InputController::default();
+        ict.press_key(0x5);
+        ict.frame_tick();
+        assert_eq!(ict.key_held_duration(0x5), None);
+        ict.unpress_key(0x5);
+
+        ict.set_track_hold(true);
+        assert_eq!(ict.key_held_duration(0x5), None);
+        ict.press_key(0x5);
+        assert_eq!(ict.key_held_duration(0x5), Some(0));
+        for _ in 0..4 {
+            ict.frame_tick();
+        }
+        // Repeated presses do not restart the count
+        ict.press_key(0x5);
+        assert_eq!(ict.key_held_duration(0x5), Some(4));
+        ict.frame_tick();
+        ict.unpress_key(0x5);
+        ict.frame_tick();
+        assert_eq!(ict.key_held_duration(0x5), Some(5));
+        assert_eq!(ict.key_held_duration(0x6), None);
+        assert_eq!(ict.key_held_duration(0x10), None);
     }
 }
@@ -1,5 +1,19 @@
+pub mod asm;
 pub mod chip8;
 pub mod config;
 mod cpu;
 pub mod display;
 pub mod input;
+pub mod opcode;
+pub mod quirk_test;
+pub mod quirks;
+#[cfg(feature = "gif")]
+pub mod recorder;
+pub mod rom;
+#[cfg(test)]
+mod test_util;
+pub mod trace;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use cpu::{CpuError, IOError, MemoryFill, WriteProtect, CLOCK_SPEED, FONT};
@@ -0,0 +1,340 @@
+use crate::cpu::CpuError;
+
+/// A decoded instruction with its operands, shared by the interpreter, assembler and tooling.
+/// Names follow Cowgod's technical reference: x and y are register numbers, byte is an
+/// 8-bit immediate value, addr a 12-bit address and n a 4-bit nibble.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    // 00E0
+    Cls,
+    // 00EE
+    Ret,
+    // 00FD, SCHIP
+    Exit,
+    // 00FE, SCHIP
+    Low,
+    // 00FF, SCHIP
+    High,
+    // 0nnn
+    Sys { addr: u16 },
+    // 1nnn
+    Jp { addr: u16 },
+    // 2nnn
+    Call { addr: u16 },
+    // 3xkk
+    SeVxByte { x: u8, byte: u8 },
+    // 4xkk
+    SneVxByte { x: u8, byte: u8 },
+    // 5xy0
+    SeVxVy { x: u8, y: u8 },
+    // 5xy2, XO-CHIP
+    SaveVxVy { x: u8, y: u8 },
+    // 5xy3, XO-CHIP
+    LoadVxVy { x: u8, y: u8 },
+    // 6xkk
+    LdVxByte { x: u8, byte: u8 },
+    // 7xkk
+    AddVxByte { x: u8, byte: u8 },
+    // 8xy0
+    LdVxVy { x: u8, y: u8 },
+    // 8xy1
+    Or { x: u8, y: u8 },
+    // 8xy2
+    And { x: u8, y: u8 },
+    // 8xy3
+    Xor { x: u8, y: u8 },
+    // 8xy4
+    AddVxVy { x: u8, y: u8 },
+    // 8xy5
+    Sub { x: u8, y: u8 },
+    // 8xy6
+    Shr { x: u8, y: u8 },
+    // 8xy7
+    Subn { x: u8, y: u8 },
+    // 8xyE
+    Shl { x: u8, y: u8 },
+    // 9xy0
+    SneVxVy { x: u8, y: u8 },
+    // Annn
+    LdI { addr: u16 },
+    // Bnnn
+    JpV0 { addr: u16 },
+    // Cxkk
+    Rnd { x: u8, byte: u8 },
+    // Dxyn
+    Drw { x: u8, y: u8, n: u8 },
+    // Ex9E
+    Skp { x: u8 },
+    // ExA1
+    Sknp { x: u8 },
+    // Fn01, XO-CHIP
+    Plane { n: u8 },
+    // F000 nnnn, XO-CHIP; the 16-bit address is the word after the instruction
+    LdILong,
+    // F002, XO-CHIP
+    Audio,
+    // Fx07
+    LdVxDt { x: u8 },
+    // Fx0A
+    LdVxK { x: u8 },
+    // Fx15
+    LdDtVx { x: u8 },
+    // Fx18
+    LdStVx { x: u8 },
+    // Fx1E
+    AddIVx { x: u8 },
+    // Fx29
+    LdFVx { x: u8 },
+    // Fx33
+    LdBVx { x: u8 },
+    // Fx3A, XO-CHIP
+    Pitch { x: u8 },
+    // Fx55
+    LdIVx { x: u8 },
+    // Fx65
+    LdVxI { x: u8 },
+}
+
+/// Coarse grouping of instructions by what they do, e.g. for filtering trace logs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OpcodeFamily {
+    // CLS, DRW
+    Draw,
+    // SYS, JP, JP V0, CALL, RET, EXIT
+    Jump,
+    // SE, SNE
+    Skip,
+    // LD between registers, immediates, I and memory, LD I, LONG, LD F, LD B, SAVE, LOAD
+    Load,
+    // ADD, including ADD I, Vx, OR, AND, XOR, SUB, SHR, SUBN, SHL, RND
+    Arithmetic,
+    // LD Vx, DT, LD DT, Vx
+    Timer,
+    // SKP, SKNP, LD Vx, K
+    Input,
+    // LD ST, Vx, AUDIO, PITCH
+    Sound,
+    // LOW, HIGH, PLANE
+    Mode,
+}
+
+impl Opcode {
+    /// Family the instruction belongs to
+    pub fn family(&self) -> OpcodeFamily {
+        use Opcode::*;
+        use OpcodeFamily::*;
+        match self {
+            Cls | Drw { .. } => Draw,
+            Sys { .. } | Jp { .. } | JpV0 { .. } | Call { .. } | Ret | Exit => Jump,
+            SeVxByte { .. } | SneVxByte { .. } | SeVxVy { .. } | SneVxVy { .. } => Skip,
+            LdVxByte { .. } | LdVxVy { .. } | LdI { .. } | LdILong | LdFVx { .. } => Load,
+            LdBVx { .. } | LdIVx { .. } | LdVxI { .. } => Load,
+            SaveVxVy { .. } | LoadVxVy { .. } => Load,
+            AddVxByte { .. } | AddVxVy { .. } | AddIVx { .. } => Arithmetic,
+            Or { .. } | And { .. } | Xor { .. } => Arithmetic,
+            Sub { .. } | Shr { .. } | Subn { .. } | Shl { .. } | Rnd { .. } => Arithmetic,
+            LdVxDt { .. } | LdDtVx { .. } => Timer,
+            Skp { .. } | Sknp { .. } | LdVxK { .. } => Input,
+            LdStVx { .. } | Audio | Pitch { .. } => Sound,
+            Low | High | Plane { .. } => Mode,
+        }
+    }
+
+    /// Decode an instruction word, failing with CpuError::UnknownOpcode for words
+    /// which are not implemented instructions
+    pub fn decode(inst: u16) -> Result<Opcode, CpuError> {
+        use Opcode::*;
+        let x = ((inst & 0x0F00) >> 8) as u8;
+        let y = ((inst & 0x00F0) >> 4) as u8;
+        let n = (inst & 0x000F) as u8;
+        let byte = (inst & 0x00FF) as u8;
+        let addr = inst & 0x0FFF;
+        let opcode = match inst & 0xF000 {
+            0x0000 => match inst {
+                0x00E0 => Cls,
+                0x00EE => Ret,
+                0x00FD => Exit,
+                0x00FE => Low,
+                0x00FF => High,
+                _ => Sys { addr },
+            },
+            0x1000 => Jp { addr },
+            0x2000 => Call { addr },
+            0x3000 => SeVxByte { x, byte },
+            0x4000 => SneVxByte { x, byte },
+            0x5000 => match n {
+                0x0 => SeVxVy { x, y },
+                0x2 => SaveVxVy { x, y },
+                0x3 => LoadVxVy { x, y },
+                _ => return Err(CpuError::UnknownOpcode),
+            },
+            0x6000 => LdVxByte { x, byte },
+            0x7000 => AddVxByte { x, byte },
+            0x8000 => match n {
+                0x0 => LdVxVy { x, y },
+                0x1 => Or { x, y },
+                0x2 => And { x, y },
+                0x3 => Xor { x, y },
+                0x4 => AddVxVy { x, y },
+                0x5 => Sub { x, y },
+                0x6 => Shr { x, y },
+                0x7 => Subn { x, y },
+                0xE => Shl { x, y },
+                _ => return Err(CpuError::UnknownOpcode),
+            },
+            0x9000 if n == 0 => SneVxVy { x, y },
+            0xA000 => LdI { addr },
+            0xB000 => JpV0 { addr },
+            0xC000 => Rnd { x, byte },
+            0xD000 => Drw { x, y, n },
+            0xE000 if byte == 0x9E => Skp { x },
+            0xE000 if byte == 0xA1 => Sknp { x },
+            0xF000 => match byte {
+                0x00 if x == 0 => LdILong,
+                0x01 => Plane { n: x },
+                0x02 if x == 0 => Audio,
+                0x07 => LdVxDt { x },
+                0x0A => LdVxK { x },
+                0x15 => LdDtVx { x },
+                0x18 => LdStVx { x },
+                0x1E => AddIVx { x },
+                0x29 => LdFVx { x },
+                0x33 => LdBVx { x },
+                0x3A => Pitch { x },
+                0x55 => LdIVx { x },
+                0x65 => LdVxI { x },
+                _ => return Err(CpuError::UnknownOpcode),
+            },
+            _ => return Err(CpuError::UnknownOpcode),
+        };
+        Ok(opcode)
+    }
+
+    /// Encode back into an instruction word. Operands are masked to their field widths.
+    pub fn encode(&self) -> u16 {
+        use Opcode::*;
+        let nnn = |addr: u16| addr & 0x0FFF;
+        let vx = |x: u8| ((x & 0xF) as u16) << 8;
+        let vxy = |x: u8, y: u8| vx(x) | ((y & 0xF) as u16) << 4;
+        match *self {
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            Exit => 0x00FD,
+            Low => 0x00FE,
+            High => 0x00FF,
+            Sys { addr } => nnn(addr),
+            Jp { addr } => 0x1000 | nnn(addr),
+            Call { addr } => 0x2000 | nnn(addr),
+            SeVxByte { x, byte } => 0x3000 | vx(x) | byte as u16,
+            SneVxByte { x, byte } => 0x4000 | vx(x) | byte as u16,
+            SeVxVy { x, y } => 0x5000 | vxy(x, y),
+            SaveVxVy { x, y } => 0x5002 | vxy(x, y),
+            LoadVxVy { x, y } => 0x5003 | vxy(x, y),
+            LdVxByte { x, byte } => 0x6000 | vx(x) | byte as u16,
+            AddVxByte { x, byte } => 0x7000 | vx(x) | byte as u16,
+            LdVxVy { x, y } => 0x8000 | vxy(x, y),
+            Or { x, y } => 0x8001 | vxy(x, y),
+            And { x, y } => 0x8002 | vxy(x, y),
+            Xor { x, y } => 0x8003 | vxy(x, y),
+            AddVxVy { x, y } => 0x8004 | vxy(x, y),
+            Sub { x, y } => 0x8005 | vxy(x, y),
+            Shr { x, y } => 0x8006 | vxy(x, y),
+            Subn { x, y } => 0x8007 | vxy(x, y),
+            Shl { x, y } => 0x800E | vxy(x, y),
+            SneVxVy { x, y } => 0x9000 | vxy(x, y),
+            LdI { addr } => 0xA000 | nnn(addr),
+            JpV0 { addr } => 0xB000 | nnn(addr),
+            Rnd { x, byte } => 0xC000 | vx(x) | byte as u16,
+            Drw { x, y, n } => 0xD000 | vxy(x, y) | (n & 0xF) as u16,
+            Skp { x } => 0xE09E | vx(x),
+            Sknp { x } => 0xE0A1 | vx(x),
+            Plane { n } => 0xF001 | vx(n),
+            LdILong => 0xF000,
+            Audio => 0xF002,
+            LdVxDt { x } => 0xF007 | vx(x),
+            LdVxK { x } => 0xF00A | vx(x),
+            LdDtVx { x } => 0xF015 | vx(x),
+            LdStVx { x } => 0xF018 | vx(x),
+            AddIVx { x } => 0xF01E | vx(x),
+            LdFVx { x } => 0xF029 | vx(x),
+            LdBVx { x } => 0xF033 | vx(x),
+            Pitch { x } => 0xF03A | vx(x),
+            LdIVx { x } => 0xF055 | vx(x),
+            LdVxI { x } => 0xF065 | vx(x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every implemented instruction word encodes back to itself
+    #[test]
+    fn decode_encode_round_trip() {
+        let mut implemented = 0;
+        for inst in 0..=u16::MAX {
+            if let Ok(opcode) = Opcode::decode(inst) {
+                implemented += 1;
+                assert_eq!(opcode.encode(), inst, "{opcode:?}");
+            }
+        }
+        // SYS alone covers 0x0000-0x0FFF, apart from the five 00xx instructions
+        assert!(implemented > 0x1000);
+    }
+
+    // Operands are decoded into their fields
+    #[test]
+    fn decode_operands() {
+        assert_eq!(
+            Opcode::decode(0xD12F).unwrap(),
+            Opcode::Drw { x: 1, y: 2, n: 0xF }
+        );
+        assert_eq!(
+            Opcode::decode(0x6A42).unwrap(),
+            Opcode::LdVxByte { x: 0xA, byte: 0x42 }
+        );
+        assert_eq!(
+            Opcode::decode(0xB123).unwrap(),
+            Opcode::JpV0 { addr: 0x123 }
+        );
+        assert!(matches!(
+            Opcode::decode(0x5121),
+            Err(CpuError::UnknownOpcode)
+        ));
+        assert_eq!(Opcode::decode(0xF53A).unwrap(), Opcode::Pitch { x: 5 });
+        assert_eq!(Opcode::decode(0xF301).unwrap(), Opcode::Plane { n: 3 });
+        assert_eq!(Opcode::decode(0xF000).unwrap(), Opcode::LdILong);
+        assert!(matches!(
+            Opcode::decode(0xF100),
+            Err(CpuError::UnknownOpcode)
+        ));
+        assert_eq!(
+            Opcode::decode(0x5023).unwrap(),
+            Opcode::LoadVxVy { x: 0, y: 2 }
+        );
+        assert!(matches!(
+            Opcode::decode(0xF102),
+            Err(CpuError::UnknownOpcode)
+        ));
+        assert!(matches!(
+            Opcode::decode(0xE1FF),
+            Err(CpuError::UnknownOpcode)
+        ));
+    }
+
+    // Operands which do not fit their fields are masked
+    #[test]
+    fn encode_masks_operands() {
+        assert_eq!(Opcode::Jp { addr: 0x1234 }.encode(), 0x1234);
+        assert_eq!(
+            Opcode::Drw {
+                x: 0x11,
+                y: 2,
+                n: 0x1F
+            }
+            .encode(),
+            0xD12F
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::fmt;
+
+use crate::display::DisplayController;
+
+/// Outcome shown for one row of a test ROM's result screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mark {
+    Pass,
+    Fail,
+    // Neither mark is on screen at the row's position, e.g. because the test has not finished
+    Unknown,
+}
+
+/// Row of a result screen: what it tests and where its mark is drawn
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GridRow {
+    pub name: String,
+    // Top left corner of the mark
+    pub x: usize,
+    pub y: usize,
+}
+
+/// Layout of the result screen of a test ROM, such as the quirks test which draws
+/// a checkmark or a cross next to the name of each quirk it checks
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResultGrid {
+    pub rows: Vec<GridRow>,
+    // Sprites of the pass and fail marks, one byte per row of 8 pixels
+    pub pass: Vec<u8>,
+    pub fail: Vec<u8>,
+}
+
+/// Address the quirks test reads its platform from at startup. A nonzero value selects
+/// that platform, numbered as in the ROM's menu, and skips the menu.
+pub const PLATFORM_ADDR: usize = 0x1FF;
+
+/// Input for a test ROM which would otherwise wait at a menu
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TestInput {
+    // Value written to PLATFORM_ADDR after the ROM is loaded, before it runs
+    pub platform: Option<u8>,
+    // CHIP-8 keys pressed at the start of the given frame, counted from 0, and released
+    // at the start of the next one
+    pub key_presses: Vec<(u64, u8)>,
+}
+
+/// Marks read from a result screen, in the order of the grid's rows
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GridResults(pub Vec<(String, Mark)>);
+
+impl ResultGrid {
+    /// Read the mark of each row from the screen
+    pub fn read(&self, dct: &DisplayController) -> GridResults {
+        let marks = self
+            .rows
+            .iter()
+            .map(|row| (row.name.clone(), self.mark_at(dct, row.x, row.y)))
+            .collect();
+        GridResults(marks)
+    }
+
+    fn mark_at(&self, dct: &DisplayController, x: usize, y: usize) -> Mark {
+        if shows_sprite(dct, x, y, &self.pass) {
+            Mark::Pass
+        } else if shows_sprite(dct, x, y, &self.fail) {
+            Mark::Fail
+        } else {
+            Mark::Unknown
+        }
+    }
+}
+
+// Whether the 8 pixel wide area at (x, y) shows exactly the given sprite
+fn shows_sprite(dct: &DisplayController, x: usize, y: usize, sprite: &[u8]) -> bool {
+    !sprite.is_empty()
+        && sprite.iter().enumerate().all(|(row, &byte)| {
+            (0..8).all(|col| dct.pixel(x + col, y + row) == (byte & (0x80 >> col) != 0))
+        })
+}
+
+impl GridResults {
+    /// Whether every row shows the pass mark
+    pub fn all_passed(&self) -> bool {
+        self.0.iter().all(|(_, mark)| *mark == Mark::Pass)
+    }
+}
+
+// One row per line, with the names padded to line up the marks
+impl fmt::Display for GridResults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.0.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        for (name, mark) in &self.0 {
+            let mark = match mark {
+                Mark::Pass => "pass",
+                Mark::Fail => "FAIL",
+                Mark::Unknown => "?",
+            };
+            writeln!(f, "{name:width$}  {mark}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Marks are read from the screen and listed one row per line
+    #[test]
+    fn read_grid() {
+        let grid = ResultGrid {
+            rows: vec![
+                GridRow {
+                    name: "vF reset".to_string(),
+                    x: 56,
+                    y: 0,
+                },
+                GridRow {
+                    name: "Clipping".to_string(),
+                    x: 56,
+                    y: 8,
+                },
+                GridRow {
+                    name: "Jumping".to_string(),
+                    x: 56,
+                    y: 16,
+                },
+            ],
+            pass: vec![0x01, 0x02, 0x84, 0x48, 0x30],
+            fail: vec![0x88, 0x50, 0x20, 0x50, 0x88],
+        };
+        let mut dct = DisplayController::default();
+        dct.draw(56, 0, grid.pass.clone());
+        dct.draw(56, 8, grid.fail.clone());
+        let results = grid.read(&dct);
+        assert_eq!(
+            results.0,
+            [
+                ("vF reset".to_string(), Mark::Pass),
+                ("Clipping".to_string(), Mark::Fail),
+                ("Jumping".to_string(), Mark::Unknown),
+            ]
+        );
+        assert!(!results.all_passed());
+        assert_eq!(
+            results.to_string(),
+            "vF reset  pass\nClipping  FAIL\nJumping   ?\n"
+        );
+    }
+}
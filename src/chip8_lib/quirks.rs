@@ -0,0 +1,54 @@
+/// Toggles for behaviour which differs between CHIP-8 interpreters.
+/// Defaults follow Cowgod's technical reference, on which this interpreter is based.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    // DXY0 draws a 16x16 sprite in low resolution mode, as in XO-CHIP.
+    // When unset, DXY0 only draws a 16x16 sprite in high resolution mode.
+    pub lores_dxy0: bool,
+    // Return an error for operands which have no defined meaning, e.g. a font digit
+    // above 0xF, instead of handling them the way most interpreters do.
+    pub strict: bool,
+    // Sprites are cut off at the screen edges, as on the COSMAC VIP.
+    // When unset, sprites wrap around to the opposite edge.
+    pub clip_sprites: bool,
+    // 8xy6 and 8xyE shift Vy and store the result in Vx, as on the COSMAC VIP.
+    // VF gets the bit shifted out of Vy. When unset, Vx is shifted in place, VF gets
+    // the bit shifted out of Vx and Vy is ignored.
+    pub shift_quirk: bool,
+    // 64KB of memory as in XO-CHIP, which F000 nnnn (LD I, LONG) can address.
+    // JP and CALL still address 12 bits, so only JP V0 and falling through reach
+    // code past 0xFFF. When unset, memory is 4KB and F000 is not an instruction.
+    pub extended_memory: bool,
+    // 8xy1, 8xy2 and 8xy3 reset VF to 0, as on the COSMAC VIP.
+    // When unset, VF is only changed if it is Vx.
+    pub vf_reset: bool,
+    // Fx55 and Fx65 leave I pointing just past the last register stored or loaded,
+    // as on the COSMAC VIP. When unset, I is left unchanged.
+    pub load_store_increment: bool,
+    // DXYN waits for the start of the next 60Hz frame before execution continues,
+    // as the COSMAC VIP waits for the vertical blank, so at most one sprite is drawn per frame.
+    pub display_wait: bool,
+    // XO-CHIP's second bit plane: PLANE n (Fn01) selects the planes which DXYN and CLS
+    // act on. When unset, Fn01 is an unknown opcode and only the first plane is used.
+    pub bit_planes: bool,
+}
+
+impl Quirks {
+    /// Behaviour of the original COSMAC VIP interpreter. Sets clip_sprites, shift_quirk,
+    /// vf_reset, load_store_increment and display_wait, and leaves lores_dxy0, strict,
+    /// extended_memory and bit_planes unset. JP V0 always jumps relative to V0, as on the COSMAC VIP,
+    /// so it needs no flag.
+    pub fn cosmac_vip() -> Self {
+        Self {
+            lores_dxy0: false,
+            strict: false,
+            clip_sprites: true,
+            shift_quirk: true,
+            extended_memory: false,
+            vf_reset: true,
+            load_store_increment: true,
+            display_wait: true,
+            bit_planes: false,
+        }
+    }
+}
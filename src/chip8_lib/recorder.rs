@@ -0,0 +1,180 @@
+use std::fs::File;
+use std::time::{Duration, Instant};
+
+use gif::{Encoder, EncodingError, Frame, Repeat};
+
+use crate::display::convert;
+
+// Delay of the last frame, which has no following frame to measure it by
+const LAST_FRAME_DELAY: Duration = Duration::from_nanos(1_000_000_000 / 60);
+// GIF delays are in hundredths of a second. Viewers slow down delays shorter than 2,
+// so frames are shown for at least that long.
+const MIN_DELAY_CS: u16 = 2;
+
+/// Captures frames while recording gameplay and encodes them as an animated GIF.
+/// Frames are mapped to the foreground and background colors, each shown until
+/// the time the next one was captured.
+/// The GIF has a fixed size, e.g. the hires resolution, and frames drawn at a lower
+/// resolution are scaled up to it, so a recording can span resolution changes.
+pub struct GifRecorder {
+    // Size of the GIF in pixels
+    width: usize,
+    height: usize,
+    // Colors as 0xRRGGBB for lit and unlit pixels
+    fg: u32,
+    bg: u32,
+    // Captured frames as one byte per pixel, with the time each was captured
+    frames: Vec<(Vec<u8>, Instant)>,
+}
+
+impl GifRecorder {
+    pub fn new(width: usize, height: usize, fg: u32, bg: u32) -> Self {
+        Self {
+            width,
+            height,
+            fg,
+            bg,
+            frames: vec![],
+        }
+    }
+
+    /// Add a frame buffer, as stored by DisplayController, captured at the given time.
+    /// The width and height are the resolution the frame was drawn at.
+    /// A frame identical to the previous one only extends how long that one is shown,
+    /// so the frame buffer can be captured on every frame of the frontend.
+    pub fn capture(&mut self, frame: &[u8], width: usize, height: usize, at: Instant) {
+        let pixels = self.scale(&convert::unpack(frame, width, height), width, height);
+        if self.frames.last().is_some_and(|(last, _)| *last == pixels) {
+            return;
+        }
+        self.frames.push((pixels, at));
+    }
+
+    // Scale unpacked pixels up to the size of the GIF by repeating each pixel
+    fn scale(&self, pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+        if (width, height) == (self.width, self.height) {
+            return pixels.to_vec();
+        }
+        let mut out = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let src = (y * height / self.height) * width + x * width / self.width;
+                out.push(pixels.get(src).copied().unwrap_or(0));
+            }
+        }
+        out
+    }
+
+    /// Number of frames captured so far
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Encode the captured frames as a looping animated GIF
+    pub fn finish(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut out = vec![];
+        self.encode(&mut out)?;
+        Ok(out)
+    }
+
+    /// Encode the captured frames and write them to a GIF file
+    pub fn save(&self, path: &str) -> Result<(), EncodingError> {
+        self.encode(File::create(path)?)
+    }
+
+    fn encode<W: std::io::Write>(&self, writer: W) -> Result<(), EncodingError> {
+        let [_, bg_r, bg_g, bg_b] = self.bg.to_be_bytes();
+        let [_, fg_r, fg_g, fg_b] = self.fg.to_be_bytes();
+        let palette = [bg_r, bg_g, bg_b, fg_r, fg_g, fg_b];
+        let mut encoder = Encoder::new(writer, self.width as u16, self.height as u16, &palette)?;
+        encoder.set_repeat(Repeat::Infinite)?;
+        for (n, (pixels, at)) in self.frames.iter().enumerate() {
+            let shown_for = match self.frames.get(n + 1) {
+                Some((_, next)) => *next - *at,
+                None => LAST_FRAME_DELAY,
+            };
+            let delay = (shown_for.as_millis() / 10).min(u16::MAX as u128) as u16;
+            let frame = Frame {
+                width: self.width as u16,
+                height: self.height as u16,
+                delay: delay.max(MIN_DELAY_CS),
+                buffer: pixels.into(),
+                ..Frame::default()
+            };
+            encoder.write_frame(&frame)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::{
+        DisplayController, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH, SCREEN_HEIGHT, SCREEN_WIDTH,
+    };
+
+    // Record a short capture and check the encoded GIF
+    #[test]
+    fn record_frames() {
+        let mut dct = DisplayController::default();
+        let mut recorder = GifRecorder::new(SCREEN_WIDTH, SCREEN_HEIGHT, 0x00FF00, 0x000000);
+        assert_eq!(recorder.frame_count(), 0);
+        let start = Instant::now();
+        for n in 0..3 {
+            dct.draw(n * 8, 0, vec![0xFF]);
+            let at = start + Duration::from_millis(50 * n as u64);
+            recorder.capture(&dct.snapshot(), SCREEN_WIDTH, SCREEN_HEIGHT, at);
+            // Unchanged frames are not captured again
+            let later = at + Duration::from_millis(20);
+            recorder.capture(&dct.snapshot(), SCREEN_WIDTH, SCREEN_HEIGHT, later);
+        }
+        assert_eq!(recorder.frame_count(), 3);
+        let gif = recorder.finish().expect("failed to encode GIF");
+        assert!(gif.starts_with(b"GIF89a"));
+        // Logical screen size, little endian
+        assert_eq!(gif[6..10], [64, 0, 32, 0]);
+        // Trailer
+        assert_eq!(gif.last(), Some(&0x3B));
+
+        // Frames decode back with the captured pixels and timing
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(&gif[..]).expect("failed to decode GIF");
+        let mut delays = vec![];
+        while let Some(frame) = decoder.read_next_frame().expect("failed to read frame") {
+            assert_eq!(frame.buffer[..8], [1; 8]);
+            delays.push(frame.delay);
+        }
+        assert_eq!(delays, [5, 5, MIN_DELAY_CS]);
+    }
+
+    // Lores frames are scaled up in a hires recording, and hires frames kept as drawn
+    #[test]
+    fn record_resolution_change() {
+        let mut dct = DisplayController::default();
+        let mut recorder = GifRecorder::new(HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT, 0xFF, 0);
+        let start = Instant::now();
+        dct.draw(0, 0, vec![0x80]);
+        recorder.capture(&dct.snapshot(), SCREEN_WIDTH, SCREEN_HEIGHT, start);
+        dct.set_hires(true);
+        dct.draw(0, 0, vec![0x80]);
+        let at = start + Duration::from_millis(50);
+        recorder.capture(&dct.snapshot(), HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT, at);
+        assert_eq!(recorder.frame_count(), 2);
+
+        let gif = recorder.finish().expect("failed to encode GIF");
+        assert_eq!(gif[6..10], [128, 0, 64, 0]);
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(&gif[..]).expect("failed to decode GIF");
+        let frame = decoder.read_next_frame().unwrap().expect("missing frame");
+        let lit: Vec<usize> = (0..frame.buffer.len())
+            .filter(|&n| frame.buffer[n] == 1)
+            .collect();
+        assert_eq!(lit, [0, 1, 128, 129]);
+        let frame = decoder.read_next_frame().unwrap().expect("missing frame");
+        assert_eq!(frame.buffer[..2], [1, 0]);
+        assert_eq!(frame.buffer.iter().filter(|&&p| p == 1).count(), 1);
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use crate::cpu::{self, Cpu, MEMORY_SIZE, PROGRAM_ENTRY_POINT};
+
+/// Metadata about a program, gathered without running it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RomInfo {
+    // Size of the program in bytes
+    pub size: usize,
+    // Whether the program fits in memory after the entry point
+    pub fits_in_memory: bool,
+    // Whether the program appears to use SUPER-CHIP instructions
+    pub schip: bool,
+    // Number of instructions this interpreter cannot execute; these may also be data
+    pub unimplemented: u64,
+    // Number of occurrences of each opcode, keyed by the instruction with its operands masked out
+    pub opcode_histogram: HashMap<u16, u64>,
+}
+
+// Whether an instruction only exists in SUPER-CHIP
+fn is_schip_opcode(inst: u16) -> bool {
+    match inst & 0xF000 {
+        0x0000 => matches!(inst & 0xFFF0, 0x00C0) || (0x00FB..=0x00FF).contains(&inst),
+        0xF000 => matches!(inst & 0xF0FF, 0xF030 | 0xF075 | 0xF085),
+        _ => false,
+    }
+}
+
+/// Inspect a program, treating every pair of bytes as an instruction.
+/// Since data is not distinguished from code, the results are only a guess.
+pub fn inspect(program: &[u8]) -> RomInfo {
+    let mut info = RomInfo {
+        size: program.len(),
+        fits_in_memory: program.len() <= MEMORY_SIZE - PROGRAM_ENTRY_POINT,
+        ..Default::default()
+    };
+    for word in program.chunks_exact(2) {
+        let inst = u16::from_be_bytes([word[0], word[1]]);
+        info.schip |= is_schip_opcode(inst);
+        if !Cpu::is_implemented(inst) {
+            info.unimplemented += 1;
+        }
+        *info
+            .opcode_histogram
+            .entry(cpu::opcode_pattern(inst))
+            .or_insert(0) += 1;
+    }
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Inspect a program which only uses standard CHIP-8 instructions
+    #[test]
+    fn inspect_chip8() {
+        // CLS, LD V0 0x05, ADD V0 0x01, JP 0x202
+        let info = inspect(&[0x00, 0xE0, 0x60, 0x05, 0x70, 0x01, 0x12, 0x02]);
+        assert_eq!(info.size, 8);
+        assert!(info.fits_in_memory);
+        assert!(!info.schip);
+        assert_eq!(info.unimplemented, 0);
+        assert_eq!(info.opcode_histogram.len(), 4);
+        assert_eq!(info.opcode_histogram[&0x00E0], 1);
+        assert_eq!(info.opcode_histogram[&0x6000], 1);
+        assert_eq!(info.opcode_histogram[&0x1000], 1);
+    }
+
+    // Inspect a program which switches to high resolution mode
+    #[test]
+    fn inspect_schip() {
+        // HIGH, LD V1 0x00, LD V2 0x00, LD R V2
+        let info = inspect(&[0x00, 0xFF, 0x61, 0x00, 0x62, 0x00, 0xF2, 0x75]);
+        assert!(info.schip);
+        // LD R, V2 is not implemented
+        assert_eq!(info.unimplemented, 1);
+        assert_eq!(info.opcode_histogram[&0x00FF], 1);
+        assert_eq!(info.opcode_histogram[&0x6000], 2);
+        assert_eq!(info.opcode_histogram[&0xF075], 1);
+    }
+
+    // Inspect a program which does not fit in memory
+    #[test]
+    fn inspect_too_large() {
+        let info = inspect(&vec![0; MEMORY_SIZE - PROGRAM_ENTRY_POINT + 1]);
+        assert!(!info.fits_in_memory);
+        assert_eq!(info.opcode_histogram[&0x0000], 1792);
+    }
+}
@@ -0,0 +1,9 @@
+// Helpers shared by the unit tests of several modules
+
+use std::path::PathBuf;
+
+// Path in the temp dir which no other test, or test run, writes to at the same time.
+// The name must be unique among the tests of this crate.
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rusty_chip8_{}_{name}", std::process::id()))
+}
@@ -0,0 +1,35 @@
+use crate::display::FRAME_SIZE;
+
+/// Interpreter state after one instruction of a traced run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateSnapshot {
+    // Address and value of the instruction which was executed
+    pub pc: u16,
+    pub opcode: u16,
+    // State after the instruction
+    pub i: u16,
+    pub registers: [u8; 16],
+    pub frame: [u8; FRAME_SIZE],
+}
+
+/// Where two traced runs first differ
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    // Index of the first instruction after which the runs differ
+    pub step: usize,
+    // Snapshots of both runs at that point; None if that run had already stopped
+    pub left: Option<StateSnapshot>,
+    pub right: Option<StateSnapshot>,
+}
+
+/// Compare two traces from run_traced, e.g. of the same program under different quirks,
+/// and find the first instruction after which their states differ.
+/// Returns None if the traces are identical.
+pub fn first_divergence(left: &[StateSnapshot], right: &[StateSnapshot]) -> Option<Divergence> {
+    let step = (0..left.len().max(right.len())).find(|&n| left.get(n) != right.get(n))?;
+    Some(Divergence {
+        step,
+        left: left.get(step).cloned(),
+        right: right.get(step).cloned(),
+    })
+}
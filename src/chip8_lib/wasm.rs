@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use wasm_bindgen::prelude::*;
+
+use crate::chip8::Chip8;
+use crate::display::convert;
+use crate::input::KeyStatus;
+
+/// Interpreter driven from JavaScript, e.g. from requestAnimationFrame.
+/// Input and the frame buffer are polled instead of being sent over channels.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Emulator {
+    chip8: Chip8,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copy a program to the entry point and start executing from it
+    pub fn load_rom(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.chip8
+            .load_rom_from_slice(bytes)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Advance the interpreter by dt_ms milliseconds of host time.
+    /// Negative or invalid durations are treated as zero.
+    /// Returns the number of instructions executed.
+    pub fn tick(&mut self, dt_ms: f64) -> usize {
+        let delta = Duration::try_from_secs_f64(dt_ms / 1000.0).unwrap_or_default();
+        self.chip8.tick(delta)
+    }
+
+    /// Width of the screen in pixels at the current resolution
+    pub fn width(&self) -> usize {
+        self.chip8.display().width()
+    }
+
+    /// Height of the screen in pixels at the current resolution
+    pub fn height(&self) -> usize {
+        self.chip8.display().height()
+    }
+
+    /// One byte per pixel, row by row, 1 for a lit pixel and 0 otherwise
+    pub fn frame_buffer(&self) -> Vec<u8> {
+        let dct = self.chip8.display();
+        convert::unpack(&dct.snapshot(), dct.width(), dct.height())
+    }
+
+    pub fn key_down(&mut self, key: u8) {
+        self.chip8.inject_key(key, KeyStatus::Pressed);
+    }
+
+    pub fn key_up(&mut self, key: u8) {
+        self.chip8.inject_key(key, KeyStatus::Unpressed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    // Drive a program through the JavaScript facing API on the native target
+    #[test]
+    fn emulator_api() {
+        let mut emu = Emulator::new();
+        // LD V0 K, LD F V0, DRW V1 V1 5, JP 0x206
+        emu.load_rom(&[0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06])
+            .unwrap();
+        assert_eq!(emu.tick(100.0), 1);
+        // Blocked waiting for a key press
+        assert_eq!(emu.tick(100.0), 0);
+        emu.key_down(0x1);
+        emu.key_up(0x1);
+        assert!(emu.tick(100.0) > 0);
+        let frame = emu.frame_buffer();
+        assert_eq!(frame.len(), emu.width() * emu.height());
+        assert_eq!((emu.width(), emu.height()), (SCREEN_WIDTH, SCREEN_HEIGHT));
+        // Top of the font sprite for 1 is 0x20
+        assert_eq!(frame[..8], [0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(emu.tick(-1.0), 0);
+    }
+
+    // Programs which do not fit in memory are rejected
+    #[test]
+    fn emulator_rom_too_large() {
+        let mut emu = Emulator::new();
+        assert!(emu.load_rom(&[0; 4096]).is_err());
+    }
+}
@@ -0,0 +1,45 @@
+use chip8_lib::chip8::{Chip8, ControlMessage};
+use chip8_lib::input::KeyStatus;
+use chip8_lib::{IOError, CLOCK_SPEED};
+use std::sync::mpsc;
+
+// Set up the interpreter the way the frontend does, with the shipped config file,
+// and check that it runs a program
+#[test]
+fn shipped_config() {
+    let mut c8 = Chip8::default();
+    c8.load_config("cfg/config.ini");
+    assert!(!c8.paused());
+    // LD V0, 0x05; ADD V0, 0x01; JP 0x204
+    c8.load_rom_from_slice(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x04])
+        .unwrap();
+    assert_eq!(c8.tick(CLOCK_SPEED * 3), 3);
+    let info = c8.debug_info();
+    assert_eq!(info.registers[0x0], 0x06);
+    assert_eq!(info.pc, 0x204);
+}
+
+// Build the interpreter from the shipped config, a ROM file and channels in one chain,
+// and check that the ROM runs
+#[test]
+fn builder() {
+    let (_input_tx, input_rx) = mpsc::channel::<(u8, KeyStatus)>();
+    let (_control_tx, control_rx) = mpsc::channel::<ControlMessage>();
+    let (display_tx, _display_rx) = mpsc::channel();
+    let mut c8 = Chip8::builder()
+        .config("cfg/config.ini")
+        .rom("tests/roms/ibm_logo.ch8")
+        .channels(input_rx, control_rx, display_tx)
+        .build()
+        .expect("failed to build interpreter");
+    assert_eq!(c8.current_rom_name(), Some("ibm_logo.ch8"));
+    // CLS; LD I, 0x22A
+    assert_eq!(c8.tick(CLOCK_SPEED * 2), 2);
+    let info = c8.debug_info();
+    assert_eq!((info.pc, info.i), (0x204, 0x22A));
+
+    assert!(matches!(
+        Chip8::builder().rom("tests/roms/missing.ch8").build(),
+        Err(IOError::FileOpenError)
+    ));
+}
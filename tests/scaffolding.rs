@@ -0,0 +1,61 @@
+#![cfg(feature = "testing")]
+
+use chip8_lib::chip8::Chip8;
+use chip8_lib::display::{DisplayController, SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip8_lib::{CpuError, CLOCK_SPEED};
+
+// Set up registers, memory and I from outside the crate, then run the scenario
+#[test]
+fn set_state_and_run() {
+    let mut c8 = Chip8::default();
+    c8.set_register(0x1, 0x20).unwrap();
+    c8.set_register(0x2, 0x22).unwrap();
+    c8.set_index(0x300);
+    // Execution starts at 0x000 without a loaded program.
+    // ADD V1, V2; LD [I], V2
+    for (addr, byte) in [0x81, 0x24, 0xF2, 0x55].into_iter().enumerate() {
+        c8.set_memory(addr, byte).unwrap();
+    }
+    assert_eq!(c8.tick(CLOCK_SPEED * 2), 2);
+
+    let info = c8.debug_info();
+    assert_eq!(info.pc, 0x004);
+    assert_eq!(info.i, 0x300);
+    assert_eq!(info.registers[0x1], 0x42);
+    assert_eq!(info.registers[0x2], 0x22);
+}
+
+// Setters reject registers and addresses which do not exist
+#[test]
+fn set_out_of_bounds() {
+    let mut c8 = Chip8::default();
+    assert!(matches!(
+        c8.set_register(0x10, 0),
+        Err(CpuError::InvalidRegister)
+    ));
+    assert!(matches!(
+        c8.set_memory(0x1000, 0),
+        Err(CpuError::MemoryOutOfBounds)
+    ));
+}
+
+// Drawing can be checked chunk by chunk from outside the display module
+#[test]
+fn chunk_at() {
+    let mut dct = DisplayController::default();
+    // A sprite drawn across a chunk boundary is split between both chunks
+    assert_eq!(dct.draw(4, 2, vec![0xFF, 0x81]), 0);
+    assert_eq!(dct.chunk_at(0, 2), 0x0F);
+    assert_eq!(dct.chunk_at(7, 2), 0x0F);
+    assert_eq!(dct.chunk_at(8, 2), 0xF0);
+    assert_eq!(dct.chunk_at(0, 3), 0x08);
+    assert_eq!(dct.chunk_at(8, 3), 0x10);
+    assert_eq!(dct.chunk_at(16, 2), 0x00);
+    // Coordinates wrap around the screen
+    assert_eq!(dct.chunk_at(SCREEN_WIDTH, SCREEN_HEIGHT + 2), 0x0F);
+
+    // Drawing over the sprite again clears it and reports the collision
+    assert_eq!(dct.draw(4, 2, vec![0xFF, 0x81]), 1);
+    assert_eq!(dct.chunk_at(0, 2), 0x00);
+    assert_eq!(dct.chunk_at(8, 3), 0x00);
+}
@@ -0,0 +1,122 @@
+// Public domain test ROMs, run headless and checked against known-good output
+use chip8_lib::asm::assemble;
+use chip8_lib::chip8::Chip8;
+use chip8_lib::quirk_test::{GridRow, Mark, ResultGrid, TestInput};
+
+// Instructions to run before giving up on a ROM reaching its final loop
+const INSTRUCTION_BUDGET: usize = 10_000;
+
+// Load a ROM and run it until it jumps to itself, returning the screen as ASCII rows
+fn run_rom(rom: &[u8]) -> Vec<String> {
+    let mut c8 = Chip8::default();
+    c8.load_rom_from_slice(rom).expect("failed to load ROM");
+    c8.run_until_loop(INSTRUCTION_BUDGET)
+        .expect("ROM did not finish");
+    c8.display().to_ascii().lines().map(String::from).collect()
+}
+
+// The IBM logo ROM draws the striped logo in the middle of the screen
+#[test]
+fn ibm_logo() {
+    let rows = run_rom(include_bytes!("roms/ibm_logo.ch8"));
+    let logo = [
+        "............########.#########...#####.........#####............",
+        "................................................................",
+        "............########.###########.######.......######............",
+        "................................................................",
+        "..............####.....###...###...#####.....#####..............",
+        "................................................................",
+        "..............####.....#######.....#######.#######..............",
+        "................................................................",
+        "..............####.....#######.....###.#######.###..............",
+        "................................................................",
+        "..............####.....###...###...###..#####..###..............",
+        "................................................................",
+        "............########.###########.#####...###...#####............",
+        "................................................................",
+        "............########.#########...#####....#....#####............",
+    ];
+    assert_eq!(rows.len(), 32);
+    assert_eq!(rows[8..23], logo);
+    let blank = ".".repeat(64);
+    assert!(rows[..8].iter().chain(&rows[23..]).all(|row| *row == blank));
+}
+
+// The quirks test ROM is not in the tree, so this stands in for it. Like the real ROM it
+// reads a platform from 0x1FF, and waits at a menu for a key press if none is set.
+// Its result screen has a checkmark on the first row, a cross on the second and
+// nothing yet on the third.
+const QUIRK_SCREEN: &str = "
+    LD I, 0x1FF
+    LD V0, [I]
+    SE V0, 0
+    JP results
+    LD V0, K
+results:
+    LD V0, 56
+    LD V1, 0
+    LD I, pass
+    DRW V0, V1, 5
+    LD V1, 8
+    LD I, fail
+    DRW V0, V1, 5
+end:
+    JP end
+pass:
+    DB 0x01, 0x02, 0x84, 0x48, 0x30
+fail:
+    DB 0x88, 0x50, 0x20, 0x50, 0x88
+";
+
+// Rows of the stand-in result screen, with the marks of the real quirks test
+fn quirk_grid() -> ResultGrid {
+    ResultGrid {
+        rows: ["vF reset", "Memory", "Display wait"]
+            .iter()
+            .enumerate()
+            .map(|(n, name)| GridRow {
+                name: name.to_string(),
+                x: 56,
+                y: n * 8,
+            })
+            .collect(),
+        pass: vec![0x01, 0x02, 0x84, 0x48, 0x30],
+        fail: vec![0x88, 0x50, 0x20, 0x50, 0x88],
+    }
+}
+
+// Run the stand-in quirks test with the given input and read its marks
+fn run_quirk_screen(input: &TestInput) -> Vec<(String, Mark)> {
+    let rom = assemble(QUIRK_SCREEN).expect("failed to assemble");
+    let mut c8 = Chip8::default();
+    c8.load_rom_from_slice(&rom).expect("failed to load ROM");
+    let results = c8
+        .run_quirk_test(10, &quirk_grid(), input)
+        .expect("failed to run quirk test");
+    assert!(!results.all_passed());
+    results.0
+}
+
+// The quirk test mode gets past the menu by preselecting a platform or pressing a key,
+// then reads the marks after a budget of frames
+#[test]
+fn quirk_test_grid() {
+    let expected = [
+        ("vF reset".to_string(), Mark::Pass),
+        ("Memory".to_string(), Mark::Fail),
+        ("Display wait".to_string(), Mark::Unknown),
+    ];
+    let preselected = TestInput {
+        platform: Some(1),
+        ..TestInput::default()
+    };
+    assert_eq!(run_quirk_screen(&preselected), expected);
+    let key_press = TestInput {
+        key_presses: vec![(2, 0x1)],
+        ..TestInput::default()
+    };
+    assert_eq!(run_quirk_screen(&key_press), expected);
+    // Without either, the ROM is still waiting at its menu
+    let waiting = run_quirk_screen(&TestInput::default());
+    assert!(waiting.iter().all(|(_, mark)| *mark == Mark::Unknown));
+}